@@ -26,6 +26,7 @@ use serde_yaml;
 
 use sawtooth_sdk::messages::block::{Block, BlockHeader};
 use sawtooth_sdk::messages::transaction::{TransactionHeader};
+use sawtooth_sdk::validation::Verifier;
 
 use blockstore::Blockstore;
 use database::lmdb;
@@ -256,6 +257,12 @@ fn run_import_command<'a>(args: &ArgMatches<'a>) -> Result<(), CliError> {
         .map_err(|err| CliError::ParseError(format!("{}", err)))?;
     let block_id = block.header_signature.clone();
 
+    let report = Verifier::new().verify_block(&block);
+    if !report.is_valid() {
+        return Err(CliError::ArgumentError(format!(
+            "Block {} is invalid: {}", block_id, report)));
+    }
+
     // Ensure this block is an immediate child of the current chain head
     match blockstore.get_chain_head() {
         Ok(chain_head) => {
@@ -24,9 +24,10 @@ use std::fmt;
 use std::io::Read;
 use std::io::Write;
 
+use sawtooth_sdk::builders::BatchBuilder;
+use sawtooth_sdk::builders::BuilderError;
 use sawtooth_sdk::messages::transaction::Transaction;
 use sawtooth_sdk::messages::batch::Batch;
-use sawtooth_sdk::messages::batch::BatchHeader;
 use self::protobuf::Message;
 
 use sawtooth_sdk::signing;
@@ -70,6 +71,7 @@ type TransactionSource<'a> = LengthDelimitedMessageSource<'a, Transaction>;
 pub enum BatchingError {
     MessageError(protobuf::ProtobufError),
     SigningError(signing::Error),
+    BuilderError(BuilderError),
 }
 
 impl From<signing::Error> for BatchingError {
@@ -85,6 +87,16 @@ impl From<protobuf::ProtobufError> for BatchingError {
     }
 }
 
+impl From<BuilderError> for BatchingError {
+    fn from(err: BuilderError) -> Self {
+        match err {
+            BuilderError::SigningError(err) => BatchingError::SigningError(err),
+            BuilderError::ProtobufError(err) => BatchingError::MessageError(err),
+            err => BatchingError::BuilderError(err),
+        }
+    }
+}
+
 impl fmt::Display for BatchingError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
@@ -92,6 +104,8 @@ impl fmt::Display for BatchingError {
                 write!(f, "Error occurred reading messages: {}", err),
             BatchingError::SigningError(ref err) =>
                 write!(f, "Unable to sign batch: {}", err),
+            BatchingError::BuilderError(ref err) =>
+                write!(f, "Unable to build batch: {}", err),
         }
     }
 }
@@ -101,6 +115,7 @@ impl error::Error for BatchingError {
         match *self {
             BatchingError::MessageError(ref err) => err.description(),
             BatchingError::SigningError(ref err) => err.description(),
+            BatchingError::BuilderError(ref err) => err.description(),
         }
     }
 
@@ -108,6 +123,7 @@ impl error::Error for BatchingError {
         match *self {
             BatchingError::MessageError(ref err) => Some(err),
             BatchingError::SigningError(ref err) => Some(err),
+            BatchingError::BuilderError(ref err) => Some(err),
         }
     }
 }
@@ -158,34 +174,7 @@ impl<'a> Iterator for SignedBatchProducer<'a> {
 }
 
 fn batch_transactions(txns: Vec<Transaction>, signer: &signing::Signer) -> BatchResult {
-
-    let mut batch_header = BatchHeader::new();
-
-    // set signer_public_key
-    let pk = match signer.get_public_key() {
-        Ok(pk) => pk,
-        Err(err) => return Err(BatchingError::SigningError(err)),
-    };
-    let public_key = pk.as_hex();
-
-
-    let txn_ids = txns.iter().map(|txn| String::from(txn.get_header_signature())).collect();
-    batch_header.set_transaction_ids(protobuf::RepeatedField::from_vec(txn_ids));
-    batch_header.set_signer_public_key(public_key);
-
-    let header_bytes = batch_header.write_to_bytes()?;
-    let signature = signer.sign(&header_bytes).map_err(BatchingError::SigningError);
-    match signature {
-        Ok(signature) => {
-            let mut batch = Batch::new();
-            batch.set_header_signature(signature);
-            batch.set_header(header_bytes);
-            batch.set_transactions(protobuf::RepeatedField::from_vec(txns));
-
-            Ok(batch)
-        },
-        Err(err) => Err(err),
-    }
+    Ok(BatchBuilder::new().transactions(txns).build(signer)?)
 }
 
 pub struct SignedBatchIterator<'a> {
@@ -208,8 +197,13 @@ impl<'a> Iterator for SignedBatchIterator<'a>
 {
     type Item = BatchResult;
 
+    /// Gets the next BatchResult, or None once the transactions run out.
     fn next(&mut self) -> Option<Self::Item> {
-        let txns = self.transaction_iterator.take(self.max_batch_size).collect();
+        let txns: Vec<Transaction> =
+            self.transaction_iterator.take(self.max_batch_size).collect();
+        if txns.is_empty() {
+            return None;
+        }
 
         Some(batch_transactions(txns, self.signer))
     }
@@ -384,7 +378,7 @@ mod tests {
     fn make_txn(sig: &str) -> Transaction {
         let mut txn_header = TransactionHeader::new();
 
-        txn_header.set_batcher_public_key(String::from("some_public_key"));
+        txn_header.set_batcher_public_key(String::from("123456789abcdef"));
         txn_header.set_family_name(String::from("test_family"));
         txn_header.set_family_version(String::from("1.0"));
         txn_header.set_signer_public_key(String::from("some_public_key"));
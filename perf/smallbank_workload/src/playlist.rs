@@ -27,7 +27,6 @@ use std::io::Write;
 use std::io::Error as StdIoError;
 use std::fmt;
 use std::borrow::Cow;
-use std::time::Instant;
 
 use self::yaml_rust::YamlEmitter;
 use self::yaml_rust::YamlLoader;
@@ -46,8 +45,8 @@ use protobuf;
 use protobuf::Message;
 
 use sawtooth_sdk::signing;
-use sawtooth_sdk::messages::transaction::Transaction;
-use sawtooth_sdk::messages::transaction::TransactionHeader;
+use sawtooth_sdk::builders::BuilderError;
+use sawtooth_sdk::builders::TransactionBuilder;
 
 use self::crypto::digest::Digest;
 use self::crypto::sha2::Sha512;
@@ -104,43 +103,20 @@ pub fn process_smallbank_playlist(output: &mut Write,
 
     let crypto_factory = signing::CryptoFactory::new(signing_context);
     let signer = crypto_factory.new_signer(signing_key);
-    let pub_key = try!(signing_context.get_public_key(signing_key).map_err(PlaylistError::SigningError));
-    let pub_key_hex = pub_key.as_hex();
+    let builder = TransactionBuilder::new()
+        .family_name("smallbank")
+        .family_version("1.0");
 
-    let start = Instant::now();
     for payload in payloads {
-        let mut txn = Transaction::new();
-        let mut txn_header = TransactionHeader::new();
-
-        txn_header.set_family_name(String::from("smallbank"));
-        txn_header.set_family_version(String::from("1.0"));
-
-        let elapsed = start.elapsed();
-        txn_header.set_nonce(format!("{}{}", elapsed.as_secs(), elapsed.subsec_nanos()));
-
-        let addresses = protobuf::RepeatedField::from_vec(make_addresses(&payload));
-
-        txn_header.set_inputs(addresses.clone());
-        txn_header.set_outputs(addresses.clone());
+        let addresses = make_addresses(&payload);
 
         let payload_bytes = try!(payload.write_to_bytes().map_err(PlaylistError::MessageError));
 
-        let mut sha = Sha512::new();
-        sha.input(&payload_bytes);
-        let hash: &mut [u8] = & mut [0; 64];
-        sha.result(hash);
-
-        txn_header.set_payload_sha512(bytes_to_hex_str(hash));
-        txn_header.set_signer_public_key(pub_key_hex.clone());
-        txn_header.set_batcher_public_key(pub_key_hex.clone());
-
-        let header_bytes = try!(txn_header.write_to_bytes().map_err(PlaylistError::MessageError));
-
-        let signature = try!(signer.sign(&header_bytes).map_err(PlaylistError::SigningError));
-
-        txn.set_header(header_bytes);
-        txn.set_header_signature(signature);
-        txn.set_payload(payload_bytes);
+        let txn = try!(builder.clone()
+            .inputs(addresses.clone())
+            .outputs(addresses)
+            .payload(payload_bytes)
+            .build(&signer));
 
         try!(txn.write_length_delimited_to_writer(output).map_err(PlaylistError::MessageError))
     }
@@ -522,6 +498,17 @@ pub enum PlaylistError {
     YamlInputError(yaml_rust::ScanError),
     MessageError(protobuf::ProtobufError),
     SigningError(signing::Error),
+    BuilderError(BuilderError),
+}
+
+impl From<BuilderError> for PlaylistError {
+    fn from(err: BuilderError) -> Self {
+        match err {
+            BuilderError::SigningError(err) => PlaylistError::SigningError(err),
+            BuilderError::ProtobufError(err) => PlaylistError::MessageError(err),
+            err => PlaylistError::BuilderError(err),
+        }
+    }
 }
 
 impl fmt::Display for PlaylistError {
@@ -537,6 +524,8 @@ impl fmt::Display for PlaylistError {
                 write!(f, "Error occurred creating protobuf: {}", err),
             PlaylistError::SigningError(ref err) =>
                 write!(f, "Error occurred signing transactions: {}", err),
+            PlaylistError::BuilderError(ref err) =>
+                write!(f, "Error occurred building transactions: {}", err),
         }
     }
 }
@@ -549,6 +538,7 @@ impl error::Error for PlaylistError {
             PlaylistError::YamlInputError(_) => "Yaml Input Error",
             PlaylistError::MessageError(ref err) => err.description(),
             PlaylistError::SigningError(ref err) => err.description(),
+            PlaylistError::BuilderError(ref err) => err.description(),
         }
     }
 
@@ -559,6 +549,7 @@ impl error::Error for PlaylistError {
             PlaylistError::YamlInputError(_) => None,
             PlaylistError::MessageError(ref err) => Some(err),
             PlaylistError::SigningError(ref err) => Some(err),
+            PlaylistError::BuilderError(ref err) => Some(err),
         }
     }
 }
@@ -17,9 +17,6 @@
 
 use cbor;
 
-use crypto::digest::Digest;
-use crypto::sha2::Sha512;
-
 use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::io::Cursor;
@@ -30,6 +27,7 @@ use cbor::value::Value;
 use cbor::value::Key;
 use cbor::value::Text;
 
+use sawtooth_sdk::addressing;
 use sawtooth_sdk::processor::handler::ApplyError;
 use sawtooth_sdk::processor::handler::TransactionContext;
 use sawtooth_sdk::processor::handler::TransactionHandler;
@@ -56,9 +54,7 @@ impl fmt::Display for Verb {
 }
 
 fn get_intkey_prefix() -> String {
-    let mut sha = Sha512::new();
-    sha.input_str("intkey");
-    sha.result_str()[..6].to_string()
+    addressing::namespace_prefix("intkey")
 }
 
 struct IntkeyPayload {
@@ -144,14 +140,13 @@ impl<'a> IntkeyState<'a> {
         }
     }
 
-    fn calculate_address(name: &str) -> String {
-        let mut sha = Sha512::new();
-        sha.input(name.as_bytes());
-        get_intkey_prefix() + &sha.result_str()[64..].to_string()
+    fn calculate_address(name: &str) -> Result<String, ApplyError> {
+        addressing::make_address_from_hash_suffix(&get_intkey_prefix(), name.as_bytes())
+            .map_err(|err| ApplyError::InternalError(format!("{}", err)))
     }
 
     pub fn get(&mut self, name: &str) -> Result<Option<u32>, ApplyError> {
-        let address = &IntkeyState::calculate_address(name);
+        let address = &IntkeyState::calculate_address(name)?;
         let d = self.context.get_state(address)?;
         match d {
             Some(packed) => {
@@ -180,7 +175,8 @@ impl<'a> IntkeyState<'a> {
     }
 
     pub fn set(&mut self, name: &str, value: u32) -> Result<(), ApplyError> {
-        let mut map: BTreeMap<Key, Value> = match self.get_cache.get_mut(&IntkeyState::calculate_address(name)) {
+        let address = IntkeyState::calculate_address(name)?;
+        let mut map: BTreeMap<Key, Value> = match self.get_cache.get_mut(&address) {
             Some(m) => m.clone(),
             None => BTreeMap::new()
         };
@@ -190,7 +186,7 @@ impl<'a> IntkeyState<'a> {
         e.value(&Value::Map(map)).map_err(|err|ApplyError::InternalError(format!("{}", err)))?;
 
         let packed = e.into_inner().into_writer().into_inner();
-        self.context.set_state(&IntkeyState::calculate_address(name), &packed)
+        self.context.set_state(&address, &packed)
             .map_err(|err|ApplyError::InternalError(format!("{}", err)))?;
 
         Ok(())
@@ -18,7 +18,6 @@
 extern crate cbor;
 #[macro_use]
 extern crate clap;
-extern crate crypto;
 #[macro_use]
 extern crate log;
 extern crate log4rs;
@@ -78,5 +77,14 @@ fn main() {
     info!("Console logging level: {}", console_log_level);
 
     processor.add_handler(&handler);
-    processor.start();
+
+    if let Err(err) = processor.install_ctrlc_handler() {
+        error!("Unable to set Ctrl-C handler: {:?}", err);
+        process::exit(1);
+    }
+
+    if let Err(err) = processor.start() {
+        error!("Transaction processor failed: {}", err);
+        process::exit(1);
+    }
 }
@@ -0,0 +1,186 @@
+/*
+ * Copyright 2017 Intel Corporation
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Measures the round trip time of requests sent with ZmqMessageSender to
+//! a ROUTER socket which echoes each one back, standing in for a validator.
+//!
+//! Run with `cargo bench --bench latency`. The number of round trips can be
+//! given as an argument, e.g. `cargo bench --bench latency -- 5000`.
+//!
+//! As a baseline, the same round trips are also made through a DEALER
+//! socket driven the way the stream thread used to drive it: polling the
+//! socket for 10 ms, then waiting on the outbound channel for 10 ms, in
+//! turn. Both are reported, so the two can be compared on the same machine.
+
+extern crate protobuf;
+extern crate sawtooth_sdk;
+extern crate zmq;
+
+use std::env;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use sawtooth_sdk::messages::validator::Message;
+use sawtooth_sdk::messages::validator::Message_MessageType;
+use sawtooth_sdk::messaging::stream::MessageConnection;
+use sawtooth_sdk::messaging::stream::MessageSender;
+use sawtooth_sdk::messaging::zmq_stream::ZmqMessageConnection;
+
+const DEFAULT_ROUND_TRIPS: usize = 1000;
+const WARMUP_ROUND_TRIPS: usize = 100;
+const BASELINE_POLL_TIMEOUT: i64 = 10;
+
+/// Echoes each message received on the router back to its sender as a
+/// PingResponse, until told to stop.
+fn echo(router: zmq::Socket, stop: Arc<AtomicBool>) {
+    while !stop.load(Ordering::SeqCst) {
+        let readable = {
+            let mut poll_items = [router.as_poll_item(zmq::POLLIN)];
+            zmq::poll(&mut poll_items, 100).expect("Poll failed");
+            poll_items[0].is_readable()
+        };
+        if !readable {
+            continue;
+        }
+
+        let mut parts = router.recv_multipart(0).expect("Receive failed");
+        let request: Message = protobuf::parse_from_bytes(&parts.pop().unwrap())
+            .expect("Invalid request");
+
+        let mut reply = Message::new();
+        reply.set_message_type(Message_MessageType::PING_RESPONSE);
+        reply.set_correlation_id(String::from(request.get_correlation_id()));
+        let reply_bytes = protobuf::Message::write_to_bytes(&reply).unwrap();
+        router.send_multipart(&[&parts[0], &reply_bytes], 0).expect("Send failed");
+    }
+}
+
+fn micros(duration: Duration) -> f64 {
+    duration.as_secs() as f64 * 1e6 + duration.subsec_nanos() as f64 / 1e3
+}
+
+/// Makes the round trips with ZmqMessageSender and returns the time each
+/// took, in microseconds.
+fn event_driven(endpoint: &str, round_trips: usize) -> Vec<f64> {
+    let (mut sender, _receiver) = ZmqMessageConnection::new(endpoint).create();
+
+    let mut samples = Vec::with_capacity(round_trips);
+    for i in 0..(WARMUP_ROUND_TRIPS + round_trips) {
+        let start = Instant::now();
+        let mut future = sender
+            .send(Message_MessageType::PING_REQUEST, &i.to_string(), &[])
+            .expect("Send failed");
+        future.get_timeout(Duration::from_secs(5)).expect("No reply received");
+        if i >= WARMUP_ROUND_TRIPS {
+            samples.push(micros(start.elapsed()));
+        }
+    }
+
+    sender.close();
+    samples
+}
+
+/// Makes the round trips through a socket thread which polls the socket
+/// and then its outbound channel, each for 10 ms, and returns the time
+/// each took, in microseconds.
+fn polling_baseline(context: &zmq::Context, endpoint: &str, round_trips: usize) -> Vec<f64> {
+    let socket = context.socket(zmq::DEALER).unwrap();
+    socket.connect(endpoint).unwrap();
+
+    let (outbound_send, outbound_recv) = channel::<Message>();
+    let (inbound_send, inbound_recv) = channel::<Message>();
+    let socket_thread = thread::spawn(move || loop {
+        let readable = {
+            let mut poll_items = [socket.as_poll_item(zmq::POLLIN)];
+            zmq::poll(&mut poll_items, BASELINE_POLL_TIMEOUT).expect("Poll failed");
+            poll_items[0].is_readable()
+        };
+        if readable {
+            let mut parts = socket.recv_multipart(0).expect("Receive failed");
+            let reply = protobuf::parse_from_bytes(&parts.pop().unwrap())
+                .expect("Invalid reply");
+            inbound_send.send(reply).unwrap();
+        }
+
+        match outbound_recv.recv_timeout(Duration::from_millis(BASELINE_POLL_TIMEOUT as u64)) {
+            Ok(message) => {
+                let message_bytes = protobuf::Message::write_to_bytes(&message).unwrap();
+                socket.send(&message_bytes, 0).expect("Send failed");
+            }
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    });
+
+    let mut samples = Vec::with_capacity(round_trips);
+    for i in 0..(WARMUP_ROUND_TRIPS + round_trips) {
+        let start = Instant::now();
+        let mut message = Message::new();
+        message.set_message_type(Message_MessageType::PING_REQUEST);
+        message.set_correlation_id(i.to_string());
+        outbound_send.send(message).unwrap();
+        inbound_recv.recv_timeout(Duration::from_secs(5)).expect("No reply received");
+        if i >= WARMUP_ROUND_TRIPS {
+            samples.push(micros(start.elapsed()));
+        }
+    }
+
+    drop(outbound_send);
+    socket_thread.join().unwrap();
+    samples
+}
+
+fn report(name: &str, mut samples: Vec<f64>) {
+    samples.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+    let percentile = |p: f64| samples[((samples.len() - 1) as f64 * p) as usize];
+
+    println!("{} ({} round trips)", name, samples.len());
+    println!("  mean:   {:>10.1} us", mean);
+    println!("  p50:    {:>10.1} us", percentile(0.5));
+    println!("  p99:    {:>10.1} us", percentile(0.99));
+    println!("  max:    {:>10.1} us", samples[samples.len() - 1]);
+}
+
+fn main() {
+    let round_trips = env::args()
+        .skip(1)
+        .filter_map(|arg| arg.parse().ok())
+        .next()
+        .unwrap_or(DEFAULT_ROUND_TRIPS);
+
+    let context = zmq::Context::new();
+    let router = context.socket(zmq::ROUTER).unwrap();
+    router.bind("tcp://127.0.0.1:*").unwrap();
+    let endpoint = router.get_last_endpoint().unwrap().unwrap();
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let echo_stop = stop.clone();
+    let echo_thread = thread::spawn(move || echo(router, echo_stop));
+
+    let baseline = polling_baseline(&context, &endpoint, round_trips);
+    let samples = event_driven(&endpoint, round_trips);
+
+    stop.store(true, Ordering::SeqCst);
+    echo_thread.join().unwrap();
+
+    report("baseline: 10 ms polling", baseline);
+    report("ZmqMessageSender", samples);
+}
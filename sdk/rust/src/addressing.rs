@@ -0,0 +1,219 @@
+/*
+ * Copyright 2017 Bitwise IO, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Helpers for deriving and checking Sawtooth state addresses.
+//!
+//! An address is 70 lowercase hex characters. By convention, its first 6
+//! characters are a namespace prefix derived from the transaction family
+//! name, and the remaining 64 identify the entry within the namespace.
+
+use crypto::digest::Digest;
+use crypto::sha2::Sha512;
+
+use std;
+use std::error::Error as StdError;
+
+/// The number of hex characters in a state address.
+pub const ADDRESS_LENGTH: usize = 70;
+
+/// The number of hex characters in a transaction family's namespace prefix.
+pub const NAMESPACE_PREFIX_LENGTH: usize = 6;
+
+#[derive(Debug)]
+pub enum AddressingError {
+    /// Returned when a prefix could not begin a valid address
+    InvalidPrefix(String),
+}
+
+impl StdError for AddressingError {
+    fn description(&self) -> &str {
+        match *self {
+            AddressingError::InvalidPrefix(ref msg) => msg,
+        }
+    }
+
+    fn cause(&self) -> Option<&StdError> {
+        match *self {
+            AddressingError::InvalidPrefix(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for AddressingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            AddressingError::InvalidPrefix(ref s) => write!(f, "InvalidPrefix: {}", s),
+        }
+    }
+}
+
+/// Returns the SHA-512 hash of the data as 128 lowercase hex characters.
+pub fn sha512_hex(data: &[u8]) -> String {
+    let mut sha = Sha512::new();
+    sha.input(data);
+    sha.result_str()
+}
+
+/// Returns the namespace prefix for the given transaction family name: the
+/// first 6 hex characters of the SHA-512 hash of the name.
+///
+/// # Arguments
+///
+/// * `family_name` - the name of the transaction family, e.g. "intkey"
+pub fn namespace_prefix(family_name: &str) -> String {
+    sha512_hex(family_name.as_bytes())[..NAMESPACE_PREFIX_LENGTH].to_string()
+}
+
+/// Returns an address within the namespace, made by appending the leading
+/// characters of the SHA-512 hash of the key to the prefix, as xo does.
+///
+/// Families differ in which end of the hash they use; for one which uses
+/// the trailing characters, as intkey does, see make_address_from_hash_suffix.
+///
+/// Returns an InvalidPrefix error if the prefix is not at most 70
+/// lowercase hex characters.
+///
+/// # Arguments
+///
+/// * `prefix` - the namespace prefix, of at most 70 hex characters
+/// * `key` - the data identifying the entry within the namespace
+pub fn make_address(prefix: &str, key: &[u8]) -> Result<String, AddressingError> {
+    check_prefix(prefix)?;
+    let remaining = ADDRESS_LENGTH - prefix.len();
+    Ok(format!("{}{}", prefix, &sha512_hex(key)[..remaining]))
+}
+
+/// Returns an address within the namespace, made by appending the
+/// trailing characters of the SHA-512 hash of the key to the prefix, as
+/// intkey does.
+///
+/// Returns an InvalidPrefix error if the prefix is not at most 70
+/// lowercase hex characters.
+///
+/// # Arguments
+///
+/// * `prefix` - the namespace prefix, of at most 70 hex characters
+/// * `key` - the data identifying the entry within the namespace
+pub fn make_address_from_hash_suffix(prefix: &str, key: &[u8])
+    -> Result<String, AddressingError>
+{
+    check_prefix(prefix)?;
+    let hash = sha512_hex(key);
+    let start = hash.len() - (ADDRESS_LENGTH - prefix.len());
+    Ok(format!("{}{}", prefix, &hash[start..]))
+}
+
+fn check_prefix(prefix: &str) -> Result<(), AddressingError> {
+    if is_valid_prefix(prefix) {
+        Ok(())
+    } else {
+        Err(AddressingError::InvalidPrefix(format!(
+            "Prefix {} is not at most {} lowercase hex characters", prefix, ADDRESS_LENGTH)))
+    }
+}
+
+/// Returns true if the address is 70 lowercase hex characters.
+pub fn is_valid_address(address: &str) -> bool {
+    address.len() == ADDRESS_LENGTH && is_hex(address)
+}
+
+/// Returns true if the prefix is at most 70 lowercase hex characters, and
+/// so could begin a valid address.
+pub fn is_valid_prefix(prefix: &str) -> bool {
+    prefix.len() <= ADDRESS_LENGTH && is_hex(prefix)
+}
+
+/// Returns true if the address starts with any of the given prefixes, as
+/// the validator checks addresses against a transaction's inputs and
+/// outputs.
+///
+/// # Arguments
+///
+/// * `address` - the address to check
+/// * `prefixes` - the prefixes, or full addresses, it may match
+pub fn matches_prefix(address: &str, prefixes: &[String]) -> bool {
+    prefixes.iter().any(|prefix| address.starts_with(prefix.as_str()))
+}
+
+fn is_hex(s: &str) -> bool {
+    s.chars().all(|c| (c >= '0' && c <= '9') || (c >= 'a' && c <= 'f'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn namespace_prefix_of_family() {
+        assert_eq!(namespace_prefix("intkey"), "1cf126");
+        assert_eq!(namespace_prefix("xo"), "5b7349");
+    }
+
+    #[test]
+    fn made_addresses_are_valid() {
+        let prefix = namespace_prefix("intkey");
+        let address = make_address(&prefix, b"name").unwrap();
+        assert!(address.starts_with(&prefix));
+        assert!(is_valid_address(&address));
+        assert_eq!(&address[6..], &sha512_hex(b"name")[..64]);
+
+        let address = make_address_from_hash_suffix(&prefix, b"name").unwrap();
+        assert!(address.starts_with(&prefix));
+        assert!(is_valid_address(&address));
+        assert_eq!(&address[6..], &sha512_hex(b"name")[64..]);
+
+        // A full address as the prefix is returned as it is
+        let full = "0".repeat(70);
+        assert_eq!(make_address(&full, b"name").unwrap(), full);
+        assert_eq!(make_address_from_hash_suffix(&full, b"name").unwrap(), full);
+    }
+
+    #[test]
+    fn invalid_prefixes_are_rejected() {
+        for prefix in &["0".repeat(71), String::from("1CF126"), String::from("1cf12g")] {
+            match make_address(prefix, b"name") {
+                Err(AddressingError::InvalidPrefix(_)) => (),
+                result => panic!("Expected an InvalidPrefix error, got {:?}", result)
+            }
+            match make_address_from_hash_suffix(prefix, b"name") {
+                Err(AddressingError::InvalidPrefix(_)) => (),
+                result => panic!("Expected an InvalidPrefix error, got {:?}", result)
+            }
+        }
+    }
+
+    #[test]
+    fn address_validation() {
+        assert!(!is_valid_address("1cf126"));
+        assert!(!is_valid_address(&"A".repeat(70)));
+        assert!(!is_valid_address(&"0".repeat(71)));
+        assert!(is_valid_address(&"0".repeat(70)));
+
+        assert!(is_valid_prefix("1cf126"));
+        assert!(is_valid_prefix(""));
+        assert!(!is_valid_prefix("1cf12g"));
+    }
+
+    #[test]
+    fn prefix_matching() {
+        let address = make_address("1cf126", b"name").unwrap();
+        assert!(matches_prefix(&address, &[String::from("abcdef"), String::from("1cf1")]));
+        assert!(matches_prefix(&address, &[address.clone()]));
+        assert!(!matches_prefix(&address, &[String::from("abcdef")]));
+        assert!(!matches_prefix(&address, &[]));
+    }
+}
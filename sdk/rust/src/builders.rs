@@ -0,0 +1,416 @@
+/*
+ * Copyright 2017 Intel Corporation
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Builders for signed transactions and batches, ready to submit to the
+//! validator.
+//!
+//! A TransactionBuilder holds the header fields shared by a family's
+//! transactions, and builds one transaction per payload, hashing the
+//! payload, generating a nonce and signing the header. A BatchBuilder
+//! signs a batch of transactions.
+
+use protobuf;
+use protobuf::Message as M;
+use protobuf::RepeatedField;
+
+use rand;
+use rand::Rng;
+
+use std;
+use std::error::Error as StdError;
+
+use addressing::is_valid_prefix;
+use addressing::sha512_hex;
+use messages::batch::Batch;
+use messages::batch::BatchHeader;
+use messages::batch::BatchList;
+use messages::transaction::Transaction;
+use messages::transaction::TransactionHeader;
+use signing;
+use signing::Signer;
+
+#[derive(Debug)]
+pub enum BuilderError {
+    /// Returned when a required field was not set
+    MissingField(String),
+    /// Returned when an input or output is not a valid address or prefix
+    InvalidAddress(String),
+    /// Returned when a transaction's batcher public key is not the batch
+    /// signer's
+    BatcherMismatch(String),
+    /// Returned when the header cannot be signed
+    SigningError(signing::Error),
+    /// Returned when the header cannot be serialized
+    ProtobufError(protobuf::ProtobufError),
+}
+
+impl StdError for BuilderError {
+    fn description(&self) -> &str {
+        match *self {
+            BuilderError::MissingField(ref msg) => msg,
+            BuilderError::InvalidAddress(ref msg) => msg,
+            BuilderError::BatcherMismatch(ref msg) => msg,
+            BuilderError::SigningError(ref err) => err.description(),
+            BuilderError::ProtobufError(ref err) => err.description(),
+        }
+    }
+
+    fn cause(&self) -> Option<&StdError> {
+        match *self {
+            BuilderError::MissingField(_) => None,
+            BuilderError::InvalidAddress(_) => None,
+            BuilderError::BatcherMismatch(_) => None,
+            BuilderError::SigningError(ref err) => Some(err),
+            BuilderError::ProtobufError(ref err) => Some(err),
+        }
+    }
+}
+
+impl std::fmt::Display for BuilderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            BuilderError::MissingField(ref s) => write!(f, "MissingField: {}", s),
+            BuilderError::InvalidAddress(ref s) => write!(f, "InvalidAddress: {}", s),
+            BuilderError::BatcherMismatch(ref s) => write!(f, "BatcherMismatch: {}", s),
+            BuilderError::SigningError(ref err) => write!(f, "SigningError: {}", err),
+            BuilderError::ProtobufError(ref err) => write!(f, "ProtobufError: {}", err),
+        }
+    }
+}
+
+impl From<signing::Error> for BuilderError {
+    fn from(err: signing::Error) -> Self {
+        BuilderError::SigningError(err)
+    }
+}
+
+impl From<protobuf::ProtobufError> for BuilderError {
+    fn from(err: protobuf::ProtobufError) -> Self {
+        BuilderError::ProtobufError(err)
+    }
+}
+
+/// Returns a random nonce, so that otherwise identical transactions have
+/// different ids.
+pub fn generate_nonce() -> String {
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Builds signed transactions. The family name, family version and payload
+/// must be set; the signer's public key is used as the batcher's unless
+/// another is set. Setters consume and return the builder, so that the
+/// fields a family's transactions share can be set once and the builder
+/// cloned for each payload.
+#[derive(Clone, Debug, Default)]
+pub struct TransactionBuilder {
+    family_name: Option<String>,
+    family_version: Option<String>,
+    inputs: Vec<String>,
+    outputs: Vec<String>,
+    dependencies: Vec<String>,
+    nonce: Option<String>,
+    batcher_public_key: Option<String>,
+    payload: Option<Vec<u8>>,
+}
+
+impl TransactionBuilder {
+    pub fn new() -> TransactionBuilder {
+        TransactionBuilder::default()
+    }
+
+    pub fn family_name(mut self, family_name: &str) -> TransactionBuilder {
+        self.family_name = Some(String::from(family_name));
+        self
+    }
+
+    pub fn family_version(mut self, family_version: &str) -> TransactionBuilder {
+        self.family_version = Some(String::from(family_version));
+        self
+    }
+
+    /// Sets the addresses, or address prefixes, the transaction may read.
+    pub fn inputs(mut self, inputs: Vec<String>) -> TransactionBuilder {
+        self.inputs = inputs;
+        self
+    }
+
+    /// Sets the addresses, or address prefixes, the transaction may write.
+    pub fn outputs(mut self, outputs: Vec<String>) -> TransactionBuilder {
+        self.outputs = outputs;
+        self
+    }
+
+    /// Sets the ids of the transactions which must be committed before this
+    /// one.
+    pub fn dependencies(mut self, dependencies: Vec<String>) -> TransactionBuilder {
+        self.dependencies = dependencies;
+        self
+    }
+
+    /// Sets the nonce. By default, a random one is generated for each
+    /// transaction built.
+    pub fn nonce(mut self, nonce: &str) -> TransactionBuilder {
+        self.nonce = Some(String::from(nonce));
+        self
+    }
+
+    /// Sets the public key of the signer of the batch the transaction will
+    /// be submitted in, if it is not the transaction's signer.
+    pub fn batcher_public_key(mut self, batcher_public_key: &str) -> TransactionBuilder {
+        self.batcher_public_key = Some(String::from(batcher_public_key));
+        self
+    }
+
+    pub fn payload(mut self, payload: Vec<u8>) -> TransactionBuilder {
+        self.payload = Some(payload);
+        self
+    }
+
+    /// Builds the transaction, signing its header.
+    ///
+    /// # Arguments
+    ///
+    /// * signer - signs the transaction header, and is its signer public key
+    pub fn build(&self, signer: &Signer) -> Result<Transaction, BuilderError> {
+        let family_name = required(&self.family_name, "family_name")?;
+        let family_version = required(&self.family_version, "family_version")?;
+        let payload = required(&self.payload, "payload")?;
+        for address in self.inputs.iter().chain(self.outputs.iter()) {
+            if !is_valid_prefix(address) {
+                return Err(BuilderError::InvalidAddress(address.clone()));
+            }
+        }
+
+        let signer_public_key = signer.get_public_key()?.as_hex();
+
+        let mut header = TransactionHeader::new();
+        header.set_family_name(family_name.clone());
+        header.set_family_version(family_version.clone());
+        header.set_inputs(RepeatedField::from_slice(&self.inputs));
+        header.set_outputs(RepeatedField::from_slice(&self.outputs));
+        header.set_dependencies(RepeatedField::from_slice(&self.dependencies));
+        header.set_nonce(self.nonce.clone().unwrap_or_else(generate_nonce));
+        header.set_payload_sha512(sha512_hex(payload));
+        header.set_batcher_public_key(
+            self.batcher_public_key.clone().unwrap_or_else(|| signer_public_key.clone()));
+        header.set_signer_public_key(signer_public_key);
+
+        let header_bytes = header.write_to_bytes()?;
+        let signature = signer.sign(&header_bytes)?;
+
+        let mut transaction = Transaction::new();
+        transaction.set_header(header_bytes);
+        transaction.set_header_signature(signature);
+        transaction.set_payload(payload.clone());
+        Ok(transaction)
+    }
+}
+
+/// Builds signed batches. At least one transaction must be added; each
+/// transaction's batcher public key should be the signer's.
+#[derive(Clone, Debug, Default)]
+pub struct BatchBuilder {
+    transactions: Vec<Transaction>,
+    trace: bool,
+}
+
+impl BatchBuilder {
+    pub fn new() -> BatchBuilder {
+        BatchBuilder::default()
+    }
+
+    /// Sets the transactions, in the order they are to be applied.
+    pub fn transactions(mut self, transactions: Vec<Transaction>) -> BatchBuilder {
+        self.transactions = transactions;
+        self
+    }
+
+    /// Adds a transaction after those already added.
+    pub fn add_transaction(mut self, transaction: Transaction) -> BatchBuilder {
+        self.transactions.push(transaction);
+        self
+    }
+
+    /// Sets whether the validator should log the batch's progress in detail.
+    pub fn trace(mut self, trace: bool) -> BatchBuilder {
+        self.trace = trace;
+        self
+    }
+
+    /// Builds the batch, signing its header. Each transaction must name the
+    /// signer's public key as its batcher, or the validator would reject
+    /// the batch.
+    ///
+    /// # Arguments
+    ///
+    /// * signer - signs the batch header, and is its signer public key
+    pub fn build(self, signer: &Signer) -> Result<Batch, BuilderError> {
+        if self.transactions.is_empty() {
+            return Err(BuilderError::MissingField(String::from("transactions")));
+        }
+
+        let signer_public_key = signer.get_public_key()?.as_hex();
+        for transaction in &self.transactions {
+            let header: TransactionHeader = protobuf::parse_from_bytes(transaction.get_header())?;
+            if header.get_batcher_public_key() != signer_public_key {
+                return Err(BuilderError::BatcherMismatch(format!(
+                    "Transaction {} has batcher public key {}, but the batch signer is {}",
+                    transaction.get_header_signature(),
+                    header.get_batcher_public_key(),
+                    signer_public_key)));
+            }
+        }
+
+        let transaction_ids = self.transactions.iter()
+            .map(|transaction| String::from(transaction.get_header_signature()))
+            .collect();
+
+        let mut header = BatchHeader::new();
+        header.set_signer_public_key(signer_public_key);
+        header.set_transaction_ids(RepeatedField::from_vec(transaction_ids));
+
+        let header_bytes = header.write_to_bytes()?;
+        let signature = signer.sign(&header_bytes)?;
+
+        let mut batch = Batch::new();
+        batch.set_header(header_bytes);
+        batch.set_header_signature(signature);
+        batch.set_transactions(RepeatedField::from_vec(self.transactions));
+        batch.set_trace(self.trace);
+        Ok(batch)
+    }
+
+    /// Builds the batch, as the only batch of a BatchList.
+    pub fn build_list(self, signer: &Signer) -> Result<BatchList, BuilderError> {
+        Ok(create_batch_list(vec![self.build(signer)?]))
+    }
+}
+
+/// Wraps the batches in a BatchList, the form the validator's REST API
+/// accepts them in.
+pub fn create_batch_list(batches: Vec<Batch>) -> BatchList {
+    let mut batch_list = BatchList::new();
+    batch_list.set_batches(RepeatedField::from_vec(batches));
+    batch_list
+}
+
+fn required<'a, T>(field: &'a Option<T>, name: &str) -> Result<&'a T, BuilderError> {
+    field.as_ref().ok_or_else(|| BuilderError::MissingField(String::from(name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use protobuf;
+
+    use addressing::sha512_hex;
+    use messages::batch::BatchHeader;
+    use messages::transaction::TransactionHeader;
+    use signing;
+    use signing::create_context;
+    use signing::secp256k1::Secp256k1PrivateKey;
+
+    use super::BatchBuilder;
+    use super::BuilderError;
+    use super::TransactionBuilder;
+
+    static KEY_PRIV_HEX: &'static str =
+        "2f1e7b7a130d7ba9da0068b3bb0ba1d79e7e77110302c9f746c3c2a63fe40088";
+    static KEY_PUB_HEX: &'static str =
+        "026a2c795a9776f75464aa3bda3534c3154a6e91b357b1181d3f515110f84b67c5";
+    static OTHER_PUB_HEX: &'static str =
+        "03ffe3a6b0b8d3a0c1e7b4b2c6a9f6d0e5e5a1c3b7f2d9e8a4c6b0d2f1e3a5c7b9";
+
+    fn intkey_builder() -> TransactionBuilder {
+        TransactionBuilder::new()
+            .family_name("intkey")
+            .family_version("1.0")
+            .inputs(vec![String::from("1cf126")])
+            .outputs(vec![String::from("1cf126")])
+    }
+
+    #[test]
+    fn build_transaction_and_batch() {
+        let context = create_context("secp256k1").unwrap();
+        let private_key = Secp256k1PrivateKey::from_hex(KEY_PRIV_HEX).unwrap();
+        let signer = signing::Signer::new(context.as_ref(), &private_key);
+
+        let builder = intkey_builder().payload(b"payload".to_vec());
+        let first = builder.build(&signer).unwrap();
+        let second = builder.build(&signer).unwrap();
+
+        let header: TransactionHeader = protobuf::parse_from_bytes(first.get_header()).unwrap();
+        assert_eq!(header.get_family_name(), "intkey");
+        assert_eq!(header.get_payload_sha512(), sha512_hex(b"payload"));
+        assert_eq!(header.get_signer_public_key(), KEY_PUB_HEX);
+        assert_eq!(header.get_batcher_public_key(), KEY_PUB_HEX);
+        // Each transaction gets its own nonce, and so its own id
+        assert_ne!(first.get_header_signature(), second.get_header_signature());
+
+        let batch_list = BatchBuilder::new()
+            .add_transaction(first.clone())
+            .add_transaction(second.clone())
+            .build_list(&signer)
+            .unwrap();
+        let batch = &batch_list.get_batches()[0];
+        let header: BatchHeader = protobuf::parse_from_bytes(batch.get_header()).unwrap();
+        assert_eq!(header.get_signer_public_key(), KEY_PUB_HEX);
+        assert_eq!(header.get_transaction_ids(),
+                   &[String::from(first.get_header_signature()),
+                     String::from(second.get_header_signature())]);
+        assert!(context.verify(batch.get_header_signature(), batch.get_header(),
+                               signer.get_public_key().unwrap().as_ref()).unwrap());
+    }
+
+    #[test]
+    fn missing_and_invalid_fields() {
+        let context = create_context("secp256k1").unwrap();
+        let private_key = Secp256k1PrivateKey::from_hex(KEY_PRIV_HEX).unwrap();
+        let signer = signing::Signer::new(context.as_ref(), &private_key);
+
+        match intkey_builder().build(&signer) {
+            Err(BuilderError::MissingField(ref field)) if field == "payload" => (),
+            result => panic!("Expected MissingField, got {:?}", result)
+        }
+        match intkey_builder().payload(vec![]).inputs(vec![String::from("1CF126")]).build(&signer) {
+            Err(BuilderError::InvalidAddress(ref address)) if address == "1CF126" => (),
+            result => panic!("Expected InvalidAddress, got {:?}", result)
+        }
+        match BatchBuilder::new().build(&signer) {
+            Err(BuilderError::MissingField(ref field)) if field == "transactions" => (),
+            result => panic!("Expected MissingField, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn batcher_must_be_batch_signer() {
+        let context = create_context("secp256k1").unwrap();
+        let private_key = Secp256k1PrivateKey::from_hex(KEY_PRIV_HEX).unwrap();
+        let signer = signing::Signer::new(context.as_ref(), &private_key);
+
+        let transaction = intkey_builder()
+            .payload(b"payload".to_vec())
+            .batcher_public_key(OTHER_PUB_HEX)
+            .build(&signer)
+            .unwrap();
+        match BatchBuilder::new().add_transaction(transaction).build(&signer) {
+            Err(BuilderError::BatcherMismatch(ref msg)) => assert!(msg.contains(KEY_PUB_HEX)),
+            result => panic!("Expected BatcherMismatch, got {:?}", result)
+        }
+    }
+}
@@ -0,0 +1,657 @@
+/*
+ * Copyright 2017 Intel Corporation
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Following the events the validator publishes as blocks are committed.
+//!
+//! An EventSubscriber subscribes to the given event types, and yields the
+//! events of each block as an EventList. If the connection is lost, it
+//! reconnects and subscribes again from the last block it delivered, so
+//! that no block's events are missed.
+
+use protobuf;
+use protobuf::RepeatedField;
+use regex::Regex;
+
+use std::marker::PhantomData;
+use std::thread;
+
+use messages::client_event::*;
+use messages::events::Event;
+use messages::events::EventFilter;
+use messages::events::EventFilter_FilterType;
+use messages::events::EventList;
+use messages::events::EventSubscription;
+use messages::validator::Message;
+use messages::validator::Message_MessageType;
+use messaging::stream::MessageConnection;
+use messaging::stream::MessageReceiver;
+use messaging::stream::MessageSender;
+use messaging::stream::ReceiveError;
+use messaging::zmq_stream::ZmqMessageConnection;
+use messaging::zmq_stream::ZmqMessageSender;
+use processor::dispatch::MessageDispatcher;
+use processor::reconnect::ReconnectPolicy;
+
+use super::check_status;
+use super::ClientError;
+use super::ValidatorClient;
+
+/// Published once for each block committed, with the attributes block_id,
+/// block_num, state_root_hash and previous_block_id.
+pub const BLOCK_COMMIT_EVENT: &str = "sawtooth/block-commit";
+
+/// Published once for each block committed, with the block's state changes
+/// as a serialized StateChangeList.
+pub const STATE_DELTA_EVENT: &str = "sawtooth/state-delta";
+
+/// The event type, and the filters on its attributes, of the events to
+/// receive. An event is received if it passes all of the filters.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Subscription {
+    event_type: String,
+    filters: Vec<EventFilter>,
+}
+
+impl Subscription {
+    /// Creates a subscription to all events of the given type.
+    pub fn new(event_type: &str) -> Subscription {
+        Subscription {
+            event_type: String::from(event_type),
+            filters: Vec::new(),
+        }
+    }
+
+    /// Adds a filter on the attributes with the given key.
+    ///
+    /// # Arguments
+    ///
+    /// * key - the attribute key
+    /// * match_string - the value, or for regex filters the pattern, the
+    ///   attribute values are matched against
+    /// * filter_type - whether any or all of the attribute values must
+    ///   match, and whether match_string is a value or a pattern
+    pub fn filter(mut self, key: &str, match_string: &str, filter_type: EventFilter_FilterType)
+        -> Subscription
+    {
+        let mut filter = EventFilter::new();
+        filter.set_key(String::from(key));
+        filter.set_match_string(String::from(match_string));
+        filter.set_filter_type(filter_type);
+        self.filters.push(filter);
+        self
+    }
+
+    /// Passes events with any attribute of the key equal to the value.
+    pub fn simple_any(self, key: &str, value: &str) -> Subscription {
+        self.filter(key, value, EventFilter_FilterType::SIMPLE_ANY)
+    }
+
+    /// Passes events whose attributes of the key are all equal to the value.
+    pub fn simple_all(self, key: &str, value: &str) -> Subscription {
+        self.filter(key, value, EventFilter_FilterType::SIMPLE_ALL)
+    }
+
+    /// Passes events with any attribute of the key matching the pattern.
+    pub fn regex_any(self, key: &str, pattern: &str) -> Subscription {
+        self.filter(key, pattern, EventFilter_FilterType::REGEX_ANY)
+    }
+
+    /// Passes events whose attributes of the key all match the pattern.
+    pub fn regex_all(self, key: &str, pattern: &str) -> Subscription {
+        self.filter(key, pattern, EventFilter_FilterType::REGEX_ALL)
+    }
+
+    pub fn event_type(&self) -> &str {
+        &self.event_type
+    }
+
+    /// Returns true if the event is of the subscription's type and passes
+    /// all of its filters, as the validator decides.
+    pub fn matches(&self, event: &Event) -> bool {
+        event.get_event_type() == self.event_type
+            && self.filters.iter().all(|filter| filter_matches(filter, event))
+    }
+
+    fn to_proto(&self) -> EventSubscription {
+        let mut subscription = EventSubscription::new();
+        subscription.set_event_type(self.event_type.clone());
+        subscription.set_filters(RepeatedField::from_slice(&self.filters));
+        subscription
+    }
+}
+
+/// Returns true if the event's attributes with the filter's key pass the
+/// filter. The "all" filters pass events without such attributes.
+fn filter_matches(filter: &EventFilter, event: &Event) -> bool {
+    let mut values = event.get_attributes().iter()
+        .filter(|attribute| attribute.get_key() == filter.get_key())
+        .map(|attribute| attribute.get_value());
+    let match_string = filter.get_match_string();
+    match filter.get_filter_type() {
+        EventFilter_FilterType::SIMPLE_ANY => values.any(|value| value == match_string),
+        EventFilter_FilterType::SIMPLE_ALL => values.all(|value| value == match_string),
+        EventFilter_FilterType::REGEX_ANY | EventFilter_FilterType::REGEX_ALL => {
+            let regex = match Regex::new(match_string) {
+                Ok(regex) => regex,
+                Err(err) => {
+                    warn!("Invalid filter pattern {}: {}", match_string, err);
+                    return false;
+                }
+            };
+            if filter.get_filter_type() == EventFilter_FilterType::REGEX_ANY {
+                values.any(|value| regex.is_match(value))
+            } else {
+                values.all(|value| regex.is_match(value))
+            }
+        }
+        EventFilter_FilterType::FILTER_TYPE_UNSET => false,
+    }
+}
+
+/// EventSubscriber is an iterator over the events of each block committed,
+/// as EventLists. It connects and subscribes when first polled, and again
+/// whenever the connection is lost, following its reconnect policy.
+///
+/// The block commit events are always subscribed to, so that the subscriber
+/// knows where to resume; they are only delivered if they match one of the
+/// subscriptions added.
+pub struct EventSubscriber<MC = ZmqMessageConnection, MS = ZmqMessageSender>
+    where MC: MessageConnection<MS>, MS: MessageSender
+{
+    conn: MC,
+    sender_type: PhantomData<fn() -> MS>,
+    subscriptions: Vec<Subscription>,
+    last_block_ids: Vec<String>,
+    reconnect_policy: ReconnectPolicy,
+    dispatcher: MessageDispatcher<'static>,
+    connection: Option<(ValidatorClient<MS>, MessageReceiver)>,
+    closed: bool,
+}
+
+impl EventSubscriber {
+    /// Creates a subscriber to the validator at the given endpoint.
+    pub fn new(endpoint: &str) -> EventSubscriber {
+        EventSubscriber::with_connection(ZmqMessageConnection::new(endpoint))
+    }
+}
+
+impl<MC, MS> EventSubscriber<MC, MS>
+    where MC: MessageConnection<MS>, MS: MessageSender
+{
+    /// Creates a subscriber which connects to the validator with the given
+    /// connection.
+    pub fn with_connection(conn: MC) -> EventSubscriber<MC, MS> {
+        EventSubscriber {
+            conn: conn,
+            sender_type: PhantomData,
+            subscriptions: Vec::new(),
+            last_block_ids: Vec::new(),
+            reconnect_policy: ReconnectPolicy::new(),
+            dispatcher: MessageDispatcher::new(),
+            connection: None,
+            closed: false,
+        }
+    }
+
+    /// Adds a subscription. Takes effect the next time the subscriber
+    /// subscribes.
+    pub fn add_subscription(&mut self, subscription: Subscription) {
+        self.subscriptions.push(subscription);
+    }
+
+    /// Sets the blocks the subscriber last received events for, so that it
+    /// receives the events of the blocks committed since. Several ids may be
+    /// given to walk back a fork. By default, the subscriber starts from the
+    /// next block committed.
+    ///
+    /// # Arguments
+    ///
+    /// * block_ids - the block ids, most recent first
+    pub fn set_last_known_block_ids(&mut self, block_ids: Vec<String>) {
+        self.last_block_ids = block_ids;
+    }
+
+    /// Returns the id of the last block whose events were delivered, which
+    /// can be stored to resume from later.
+    pub fn last_block_id(&self) -> Option<&str> {
+        self.last_block_ids.first().map(|block_id| block_id.as_str())
+    }
+
+    /// Sets the policy for reconnecting to the validator after the
+    /// connection is lost. If the policy gives up, the iterator returns the
+    /// last error and ends.
+    pub fn set_reconnect_policy(&mut self, reconnect_policy: ReconnectPolicy) {
+        self.reconnect_policy = reconnect_policy;
+    }
+
+    /// Unsubscribes and closes the connection. The iterator ends.
+    pub fn close(&mut self) {
+        self.closed = true;
+        if let Some((mut client, _)) = self.connection.take() {
+            let result: Result<ClientEventsUnsubscribeResponse, ClientError> = client.request(
+                Message_MessageType::CLIENT_EVENTS_UNSUBSCRIBE_REQUEST,
+                &ClientEventsUnsubscribeRequest::new(),
+                Message_MessageType::CLIENT_EVENTS_UNSUBSCRIBE_RESPONSE);
+            if let Err(err) = result.and_then(|response| check_status(response.get_status())) {
+                warn!("Unable to unsubscribe: {}", err);
+            }
+            client.close();
+        }
+    }
+
+    fn subscribes_to_block_commits(&self) -> bool {
+        self.subscriptions.iter().any(|subscription| {
+            subscription.event_type == BLOCK_COMMIT_EVENT && subscription.filters.is_empty()
+        })
+    }
+
+    fn subscribe(&self, client: &mut ValidatorClient<MS>) -> Result<(), ClientError> {
+        let mut subscriptions: Vec<EventSubscription> = self.subscriptions.iter()
+            .map(|subscription| subscription.to_proto())
+            .collect();
+        if !self.subscribes_to_block_commits() {
+            subscriptions.push(Subscription::new(BLOCK_COMMIT_EVENT).to_proto());
+        }
+
+        let mut request = ClientEventsSubscribeRequest::new();
+        request.set_subscriptions(RepeatedField::from_vec(subscriptions));
+        request.set_last_known_block_ids(RepeatedField::from_slice(&self.last_block_ids));
+
+        let response: ClientEventsSubscribeResponse = client.request(
+            Message_MessageType::CLIENT_EVENTS_SUBSCRIBE_REQUEST, &request,
+            Message_MessageType::CLIENT_EVENTS_SUBSCRIBE_RESPONSE)?;
+        check_status(response.get_status()).map_err(|err| {
+            warn!("Subscription failed: {}", response.get_response_message());
+            err
+        })
+    }
+
+    /// Connects and subscribes, retrying failed connections as the reconnect
+    /// policy allows. Errors in the subscription itself are not retried.
+    fn connect(&mut self) -> Result<(), ClientError> {
+        // The number of consecutive failed connection attempts
+        let mut attempt = 0;
+        loop {
+            let (sender, receiver) = self.conn.create();
+            let mut client = ValidatorClient::new(sender);
+
+            let err = match self.subscribe(&mut client) {
+                Ok(()) => {
+                    self.connection = Some((client, receiver));
+                    return Ok(());
+                }
+                Err(err @ ClientError::SendError(_)) |
+                Err(err @ ClientError::ReceiveError(_)) => err,
+                Err(err) => {
+                    client.close();
+                    return Err(err);
+                }
+            };
+            client.close();
+
+            attempt += 1;
+            if self.reconnect_policy.is_exhausted(attempt) {
+                error!("Gave up on subscribing after {} attempts: {}", attempt, err);
+                return Err(err);
+            }
+            let delay = self.reconnect_policy.delay(attempt);
+            info!("Subscribing again in {:?} (attempt {})", delay, attempt);
+            thread::sleep(delay);
+        }
+    }
+
+    fn disconnect(&mut self) {
+        if let Some((mut client, _)) = self.connection.take() {
+            client.close();
+        }
+    }
+
+    /// Decodes the events of a block, recording its id and removing its
+    /// block commit event if that does not match a subscription. Returns
+    /// None if no events are left.
+    fn receive_events(&mut self, message: &Message) -> Result<Option<EventList>, ClientError> {
+        let mut event_list: EventList = protobuf::parse_from_bytes(message.get_content())?;
+
+        for event in event_list.get_events() {
+            if event.get_event_type() != BLOCK_COMMIT_EVENT {
+                continue;
+            }
+            let block_id = event.get_attributes().iter()
+                .find(|attribute| attribute.get_key() == "block_id")
+                .map(|attribute| String::from(attribute.get_value()));
+            if let Some(block_id) = block_id {
+                self.last_block_ids = vec![block_id];
+            }
+        }
+
+        if !self.subscribes_to_block_commits() {
+            let subscriptions = &self.subscriptions;
+            let events = event_list.take_events()
+                .into_iter()
+                .filter(|event| {
+                    event.get_event_type() != BLOCK_COMMIT_EVENT
+                        || subscriptions.iter().any(|subscription| subscription.matches(event))
+                })
+                .collect();
+            event_list.set_events(RepeatedField::from_vec(events));
+        }
+
+        if event_list.get_events().is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(event_list))
+        }
+    }
+}
+
+impl<MC, MS> Iterator for EventSubscriber<MC, MS>
+    where MC: MessageConnection<MS>, MS: MessageSender
+{
+    type Item = Result<EventList, ClientError>;
+
+    /// Blocks until the events of the next block arrive.
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.closed {
+                return None;
+            }
+            if self.connection.is_none() {
+                if let Err(err) = self.connect() {
+                    self.closed = true;
+                    return Some(Err(err));
+                }
+            }
+
+            let received = match self.connection {
+                Some((_, ref receiver)) => receiver.recv(),
+                None => continue
+            };
+            let message = match received {
+                Ok(Ok(message)) => message,
+                Ok(Err(ReceiveError::DisconnectedError)) |
+                Ok(Err(ReceiveError::SocketError(_))) |
+                Err(_) => {
+                    info!("Connection lost; subscribing again");
+                    self.disconnect();
+                    continue;
+                }
+                Ok(Err(err)) => return Some(Err(ClientError::ReceiveError(err)))
+            };
+
+            match message.get_message_type() {
+                Message_MessageType::CLIENT_EVENTS => {
+                    match self.receive_events(&message) {
+                        Ok(Some(event_list)) => return Some(Ok(event_list)),
+                        Ok(None) => continue,
+                        Err(err) => return Some(Err(err))
+                    }
+                }
+                _ => {
+                    // The validator pings its clients, and drops those which
+                    // do not answer
+                    if let Some((reply_type, reply)) = self.dispatcher.dispatch(&message) {
+                        if let Some((ref mut client, _)) = self.connection {
+                            if let Err(err) = client.sender.reply(
+                                reply_type, message.get_correlation_id(), &reply)
+                            {
+                                warn!("Unable to reply to {:?}: {}",
+                                      message.get_message_type(), err);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<MC, MS> Drop for EventSubscriber<MC, MS>
+    where MC: MessageConnection<MS>, MS: MessageSender
+{
+    /// Unsubscribes without waiting for the validator's reply, and closes
+    /// the connection.
+    fn drop(&mut self) {
+        self.closed = true;
+        if let Some((mut client, _)) = self.connection.take() {
+            if let Err(err) = client.send_only(
+                Message_MessageType::CLIENT_EVENTS_UNSUBSCRIBE_REQUEST,
+                &ClientEventsUnsubscribeRequest::new())
+            {
+                warn!("Unable to unsubscribe: {}", err);
+            }
+            client.close();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use protobuf;
+    use protobuf::RepeatedField;
+
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    use client::tests::reply;
+    use client::tests::MockSender;
+    use messages::client_event::*;
+    use messages::events::Event;
+    use messages::events::EventList;
+    use messages::events::Event_Attribute;
+    use messages::validator::Message;
+    use messages::validator::Message_MessageType;
+    use messaging::stream::MessageConnection;
+    use messaging::stream::MessageReceiver;
+    use messaging::stream::MessageResult;
+    use messaging::stream::ReceiveError;
+    use processor::reconnect::ReconnectPolicy;
+
+    use super::EventSubscriber;
+    use super::Subscription;
+    use super::BLOCK_COMMIT_EVENT;
+    use super::STATE_DELTA_EVENT;
+
+    /// Creates MockSenders which accept every subscription, with receivers
+    /// which deliver the next script of messages and then disconnect.
+    struct MockConnection {
+        sender: MockSender,
+        scripts: Arc<Mutex<VecDeque<Vec<MessageResult>>>>,
+    }
+
+    impl MessageConnection<MockSender> for MockConnection {
+        fn create(&self) -> (MockSender, MessageReceiver) {
+            let (tx, rx) = channel();
+            for message_result in self.scripts.lock().unwrap().pop_front().unwrap_or_default() {
+                tx.send(message_result).unwrap();
+            }
+            (self.sender.clone(), rx)
+        }
+    }
+
+    fn mock_connection(scripts: Vec<Vec<MessageResult>>) -> MockConnection {
+        let sender = MockSender::new(|message_type, _| {
+            match message_type {
+                Message_MessageType::CLIENT_EVENTS_SUBSCRIBE_REQUEST => {
+                    let mut response = ClientEventsSubscribeResponse::new();
+                    response.set_status(ClientEventsSubscribeResponse_Status::OK);
+                    reply(Message_MessageType::CLIENT_EVENTS_SUBSCRIBE_RESPONSE, &response)
+                }
+                _ => {
+                    let mut response = ClientEventsUnsubscribeResponse::new();
+                    response.set_status(ClientEventsUnsubscribeResponse_Status::OK);
+                    reply(Message_MessageType::CLIENT_EVENTS_UNSUBSCRIBE_RESPONSE, &response)
+                }
+            }
+        });
+        MockConnection {
+            sender: sender,
+            scripts: Arc::new(Mutex::new(scripts.into_iter().collect())),
+        }
+    }
+
+    fn make_event(event_type: &str, key: &str, value: &str) -> Event {
+        let mut attribute = Event_Attribute::new();
+        attribute.set_key(String::from(key));
+        attribute.set_value(String::from(value));
+        let mut event = Event::new();
+        event.set_event_type(String::from(event_type));
+        event.set_attributes(RepeatedField::from_vec(vec![attribute]));
+        event
+    }
+
+    /// Returns the CLIENT_EVENTS message for a block with a state delta.
+    fn block_events(block_id: &str) -> MessageResult {
+        let mut event_list = EventList::new();
+        event_list.set_events(RepeatedField::from_vec(vec![
+            make_event(BLOCK_COMMIT_EVENT, "block_id", block_id),
+            make_event(STATE_DELTA_EVENT, "address", "abcdef"),
+        ]));
+        let mut message = Message::new();
+        message.set_message_type(Message_MessageType::CLIENT_EVENTS);
+        message.set_content(protobuf::Message::write_to_bytes(&event_list).unwrap());
+        Ok(message)
+    }
+
+    fn subscribe_requests(sender: &MockSender) -> Vec<ClientEventsSubscribeRequest> {
+        sender.requests.lock().unwrap()
+            .iter()
+            .filter(|&&(message_type, _)| {
+                message_type == Message_MessageType::CLIENT_EVENTS_SUBSCRIBE_REQUEST
+            })
+            .map(|&(_, ref content)| protobuf::parse_from_bytes(content).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn block_commits_are_tracked_but_not_delivered() {
+        let conn = mock_connection(vec![vec![block_events("b1")]]);
+        let sender = conn.sender.clone();
+        let mut subscriber = EventSubscriber::with_connection(conn);
+        subscriber.add_subscription(Subscription::new(STATE_DELTA_EVENT)
+                                    .regex_any("address", "^abcdef"));
+
+        let event_list = subscriber.next().unwrap().unwrap();
+        assert_eq!(event_list.get_events().len(), 1);
+        assert_eq!(event_list.get_events()[0].get_event_type(), STATE_DELTA_EVENT);
+        assert_eq!(subscriber.last_block_id(), Some("b1"));
+
+        let request = &subscribe_requests(&sender)[0];
+        let event_types: Vec<&str> = request.get_subscriptions()
+            .iter()
+            .map(|subscription| subscription.get_event_type())
+            .collect();
+        assert_eq!(event_types, vec![STATE_DELTA_EVENT, BLOCK_COMMIT_EVENT]);
+        assert_eq!(request.get_subscriptions()[0].get_filters()[0].get_match_string(), "^abcdef");
+    }
+
+    #[test]
+    fn filtered_block_commits_are_delivered() {
+        let block_commit = |block_id: &str, block_num: &str| {
+            let mut event = make_event(BLOCK_COMMIT_EVENT, "block_id", block_id);
+            let mut attribute = Event_Attribute::new();
+            attribute.set_key(String::from("block_num"));
+            attribute.set_value(String::from(block_num));
+            event.mut_attributes().push(attribute);
+
+            let mut event_list = EventList::new();
+            event_list.set_events(RepeatedField::from_vec(vec![event]));
+            let mut message = Message::new();
+            message.set_message_type(Message_MessageType::CLIENT_EVENTS);
+            message.set_content(protobuf::Message::write_to_bytes(&event_list).unwrap());
+            Ok(message)
+        };
+        let conn = mock_connection(vec![vec![block_commit("b4", "4"), block_commit("b5", "5")]]);
+        let sender = conn.sender.clone();
+        let mut subscriber = EventSubscriber::with_connection(conn);
+        subscriber.add_subscription(Subscription::new(BLOCK_COMMIT_EVENT)
+                                    .simple_any("block_num", "5"));
+
+        // Block 4 is only received to track the last block
+        let event_list = subscriber.next().unwrap().unwrap();
+        assert_eq!(event_list.get_events().len(), 1);
+        assert_eq!(event_list.get_events()[0].get_attributes()[0].get_value(), "b5");
+        assert_eq!(subscriber.last_block_id(), Some("b5"));
+
+        let request = &subscribe_requests(&sender)[0];
+        assert_eq!(request.get_subscriptions().len(), 2);
+        assert_eq!(request.get_subscriptions()[0].get_filters().len(), 1);
+        assert!(request.get_subscriptions()[1].get_filters().is_empty());
+    }
+
+    #[test]
+    fn subscription_filters_match_as_validator() {
+        let mut event = make_event(STATE_DELTA_EVENT, "address", "abc123");
+        let mut attribute = Event_Attribute::new();
+        attribute.set_key(String::from("address"));
+        attribute.set_value(String::from("def456"));
+        event.mut_attributes().push(attribute);
+
+        let subscription = Subscription::new(STATE_DELTA_EVENT);
+        assert!(subscription.matches(&event));
+        assert!(!Subscription::new(BLOCK_COMMIT_EVENT).matches(&event));
+
+        assert!(subscription.clone().simple_any("address", "abc123").matches(&event));
+        assert!(!subscription.clone().simple_all("address", "abc123").matches(&event));
+        assert!(subscription.clone().regex_any("address", "^abc").matches(&event));
+        assert!(!subscription.clone().regex_all("address", "^abc").matches(&event));
+        assert!(subscription.clone().regex_all("address", "[0-9]{3}$").matches(&event));
+        // The "all" filters pass events without the key
+        assert!(subscription.clone().simple_all("owner", "me").matches(&event));
+        assert!(!subscription.clone().regex_any("address", "(").matches(&event));
+    }
+
+    #[test]
+    fn drop_unsubscribes() {
+        let conn = mock_connection(vec![vec![block_events("b1")]]);
+        let sender = conn.sender.clone();
+        let mut subscriber = EventSubscriber::with_connection(conn);
+        subscriber.add_subscription(Subscription::new(STATE_DELTA_EVENT));
+        subscriber.next().unwrap().unwrap();
+
+        drop(subscriber);
+        let requests = sender.requests.lock().unwrap();
+        assert_eq!(requests.last().unwrap().0,
+                   Message_MessageType::CLIENT_EVENTS_UNSUBSCRIBE_REQUEST);
+    }
+
+    #[test]
+    fn resubscribes_from_last_block_after_disconnect() {
+        let conn = mock_connection(vec![
+            vec![block_events("b1"), Err(ReceiveError::DisconnectedError)],
+            vec![block_events("b2")],
+        ]);
+        let sender = conn.sender.clone();
+        let mut subscriber = EventSubscriber::with_connection(conn);
+        subscriber.add_subscription(Subscription::new(BLOCK_COMMIT_EVENT));
+        subscriber.set_reconnect_policy(
+            ReconnectPolicy::new().initial_delay(Duration::from_millis(1)));
+
+        assert_eq!(subscriber.next().unwrap().unwrap().get_events().len(), 2);
+        assert_eq!(subscriber.next().unwrap().unwrap().get_events().len(), 2);
+        assert_eq!(subscriber.last_block_id(), Some("b2"));
+
+        let requests = subscribe_requests(&sender);
+        assert_eq!(requests.len(), 2);
+        assert!(requests[0].get_last_known_block_ids().is_empty());
+        assert_eq!(requests[1].get_last_known_block_ids(), &[String::from("b1")]);
+
+        subscriber.close();
+        assert!(subscriber.next().is_none());
+    }
+}
@@ -0,0 +1,932 @@
+/*
+ * Copyright 2017 Intel Corporation
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! A client for the validator's CLIENT_* requests, for applications which
+//! talk to the validator directly rather than through the REST API.
+
+pub mod events;
+pub mod paging;
+
+use protobuf;
+use protobuf::Message as M;
+use protobuf::MessageStatic;
+use protobuf::ProtobufEnum;
+use protobuf::RepeatedField;
+
+use std;
+use std::error::Error as StdError;
+use std::time::Duration;
+
+use messages::batch::Batch;
+use messages::block::Block;
+use messages::block::BlockHeader;
+use messages::client_batch::*;
+use messages::client_batch_submit::*;
+use messages::client_block::*;
+use messages::client_list_control::ClientPagingControls;
+use messages::client_list_control::ClientPagingResponse;
+use messages::client_list_control::ClientSortControls;
+use messages::client_peers::*;
+use messages::client_receipt::*;
+use messages::client_state::*;
+use messages::client_status::*;
+use messages::client_transaction::*;
+use messages::transaction::Transaction;
+use messages::transaction_receipt::TransactionReceipt;
+use messages::validator::Message_MessageType;
+use messaging::stream::MessageConnection;
+use messaging::stream::MessageSender;
+use messaging::stream::ReceiveError;
+use messaging::stream::SendError;
+use messaging::zmq_stream::ZmqMessageConnection;
+use messaging::zmq_stream::ZmqMessageSender;
+use processor::generate_correlation_id;
+use receipts::Receipt;
+use receipts::ReceiptError;
+
+/// How long a client waits for the validator to reply, by default.
+pub const DEFAULT_TIMEOUT_SECS: u64 = 30;
+
+#[derive(Debug)]
+pub enum ClientError {
+    /// Returned when the request cannot be sent
+    SendError(SendError),
+    /// Returned when no reply arrives in time, or the connection fails
+    ReceiveError(ReceiveError),
+    /// Returned when a request cannot be serialized, or a reply parsed
+    ProtobufError(protobuf::ProtobufError),
+    /// Returned when a receipt cannot be decoded
+    ReceiptError(ReceiptError),
+    /// Returned when the validator replies with an unexpected message type
+    UnexpectedReply(Message_MessageType),
+    /// The validator failed to handle the request
+    InternalError,
+    /// The validator has no genesis block yet
+    NotReady,
+    /// The given head block or state root is unknown
+    NoRoot,
+    /// The requested block, batch, transaction or state entry does not exist
+    NotFound,
+    /// The paging controls are invalid
+    InvalidPaging,
+    /// The sort controls are invalid
+    InvalidSort,
+    /// A given id is not a valid block, batch or transaction id
+    InvalidId,
+    /// A given address is not a valid address or address prefix
+    InvalidAddress,
+    /// The given state root is not valid
+    InvalidRoot,
+    /// A submitted batch is not valid
+    InvalidBatch,
+    /// The validator's queue is full; the batches may be submitted later
+    QueueFull,
+    /// An event subscription's filter is not valid
+    InvalidFilter,
+    /// None of the block ids to resume an event subscription from is known
+    UnknownBlock,
+    /// The reply has a status this client does not recognize
+    UnknownStatus(String),
+}
+
+impl StdError for ClientError {
+    fn description(&self) -> &str {
+        match *self {
+            ClientError::SendError(ref err) => err.description(),
+            ClientError::ReceiveError(ref err) => err.description(),
+            ClientError::ProtobufError(ref err) => err.description(),
+            ClientError::ReceiptError(ref err) => err.description(),
+            ClientError::UnexpectedReply(_) => "Unexpected reply",
+            ClientError::InternalError => "Internal error",
+            ClientError::NotReady => "Validator is not ready",
+            ClientError::NoRoot => "Head or state root not found",
+            ClientError::NotFound => "Resource not found",
+            ClientError::InvalidPaging => "Invalid paging controls",
+            ClientError::InvalidSort => "Invalid sort controls",
+            ClientError::InvalidId => "Invalid id",
+            ClientError::InvalidAddress => "Invalid address",
+            ClientError::InvalidRoot => "Invalid state root",
+            ClientError::InvalidBatch => "Invalid batch",
+            ClientError::QueueFull => "Queue full",
+            ClientError::InvalidFilter => "Invalid event filter",
+            ClientError::UnknownBlock => "Unknown block",
+            ClientError::UnknownStatus(ref status) => status,
+        }
+    }
+
+    fn cause(&self) -> Option<&StdError> {
+        match *self {
+            ClientError::SendError(ref err) => Some(err),
+            ClientError::ReceiveError(ref err) => Some(err),
+            ClientError::ProtobufError(ref err) => Some(err),
+            ClientError::ReceiptError(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for ClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            ClientError::SendError(ref err) => write!(f, "SendError: {}", err),
+            ClientError::ReceiveError(ref err) => write!(f, "ReceiveError: {}", err),
+            ClientError::ProtobufError(ref err) => write!(f, "ProtobufError: {}", err),
+            ClientError::ReceiptError(ref err) => write!(f, "ReceiptError: {}", err),
+            ClientError::UnexpectedReply(ref message_type) =>
+                write!(f, "UnexpectedReply: {:?}", message_type),
+            ClientError::UnknownStatus(ref status) => write!(f, "UnknownStatus: {}", status),
+            _ => write!(f, "{}", self.description()),
+        }
+    }
+}
+
+impl From<SendError> for ClientError {
+    fn from(err: SendError) -> Self {
+        ClientError::SendError(err)
+    }
+}
+
+impl From<ReceiveError> for ClientError {
+    fn from(err: ReceiveError) -> Self {
+        ClientError::ReceiveError(err)
+    }
+}
+
+impl From<protobuf::ProtobufError> for ClientError {
+    fn from(err: protobuf::ProtobufError) -> Self {
+        ClientError::ProtobufError(err)
+    }
+}
+
+impl From<ReceiptError> for ClientError {
+    fn from(err: ReceiptError) -> Self {
+        ClientError::ReceiptError(err)
+    }
+}
+
+/// Returns Ok if the status of a reply is OK, or the error for the status.
+/// Each reply has its own status enum, but the values share their names.
+fn check_status<S: ProtobufEnum>(status: S) -> Result<(), ClientError> {
+    match status.descriptor().name() {
+        "OK" => Ok(()),
+        "INTERNAL_ERROR" | "ERROR" => Err(ClientError::InternalError),
+        "NOT_READY" => Err(ClientError::NotReady),
+        "NO_ROOT" => Err(ClientError::NoRoot),
+        "NO_RESOURCE" => Err(ClientError::NotFound),
+        "INVALID_PAGING" => Err(ClientError::InvalidPaging),
+        "INVALID_SORT" => Err(ClientError::InvalidSort),
+        "INVALID_ID" => Err(ClientError::InvalidId),
+        "INVALID_ADDRESS" => Err(ClientError::InvalidAddress),
+        "INVALID_ROOT" => Err(ClientError::InvalidRoot),
+        "INVALID_BATCH" => Err(ClientError::InvalidBatch),
+        "QUEUE_FULL" => Err(ClientError::QueueFull),
+        "INVALID_FILTER" => Err(ClientError::InvalidFilter),
+        "UNKNOWN_BLOCK" => Err(ClientError::UnknownBlock),
+        name => Err(ClientError::UnknownStatus(String::from(name))),
+    }
+}
+
+/// Paging and sorting for the list requests. The defaults list from the
+/// chain head, with the validator's default paging and sorting.
+#[derive(Clone, Debug, Default)]
+pub struct ListOptions {
+    /// The block to list from; the chain head if None
+    pub head_id: Option<String>,
+    /// The paging id of the first item to list, from a Page's next
+    pub start: Option<String>,
+    /// The most items to list; the validator's default if None
+    pub limit: Option<i32>,
+    /// The keys to sort by, in order
+    pub sorting: Vec<ClientSortControls>,
+}
+
+impl ListOptions {
+    /// Adds a sort key, or a tie-breaker if there already is one.
+    ///
+    /// # Arguments
+    ///
+    /// * keys - the field to sort by, as the path to it, e.g.
+    ///   ["header", "block_num"]
+    /// * reverse - whether to sort in descending order
+    pub fn sort_by(mut self, keys: &[&str], reverse: bool) -> ListOptions {
+        let mut sort = ClientSortControls::new();
+        let keys = keys.iter().map(|key| String::from(*key)).collect();
+        sort.set_keys(RepeatedField::from_vec(keys));
+        sort.set_reverse(reverse);
+        self.sorting.push(sort);
+        self
+    }
+
+    fn paging(&self) -> ClientPagingControls {
+        let mut paging = ClientPagingControls::new();
+        if let Some(ref start) = self.start {
+            paging.set_start(start.clone());
+        }
+        if let Some(limit) = self.limit {
+            paging.set_limit(limit);
+        }
+        paging
+    }
+
+    fn sorting(&self) -> RepeatedField<ClientSortControls> {
+        RepeatedField::from_vec(self.sorting.clone())
+    }
+}
+
+/// One page of a list request.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// The id of the block listed from; for state, the state root
+    pub head: String,
+    /// The paging id of the next page, if there is one
+    pub next: Option<String>,
+}
+
+impl<T> Page<T> {
+    fn new(items: Vec<T>, head: &str, paging: &ClientPagingResponse) -> Page<T> {
+        Page {
+            items: items,
+            head: String::from(head),
+            next: match paging.get_next() {
+                "" => None,
+                next => Some(String::from(next)),
+            },
+        }
+    }
+}
+
+/// An address and the data stored at it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StateEntry {
+    pub address: String,
+    pub data: Vec<u8>,
+}
+
+/// The validator's endpoint and its peers' endpoints.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ValidatorStatus {
+    pub endpoint: String,
+    pub peers: Vec<String>,
+}
+
+/// ValidatorClient sends CLIENT_* requests to a validator, waiting for and
+/// decoding each reply. Replies with a status other than OK are returned as
+/// the matching ClientError.
+pub struct ValidatorClient<MS: MessageSender = ZmqMessageSender> {
+    sender: MS,
+    timeout: Duration,
+}
+
+impl ValidatorClient {
+    /// Connects to the validator's client endpoint over ZMQ.
+    ///
+    /// # Arguments
+    ///
+    /// * endpoint - the validator's endpoint, e.g. "tcp://localhost:4004"
+    pub fn connect(endpoint: &str) -> ValidatorClient {
+        let (sender, _) = ZmqMessageConnection::new(endpoint).create();
+        ValidatorClient::new(sender)
+    }
+}
+
+impl<MS: MessageSender> ValidatorClient<MS> {
+    /// Creates a client which sends its requests with the given sender.
+    pub fn new(sender: MS) -> ValidatorClient<MS> {
+        ValidatorClient {
+            sender: sender,
+            timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECS),
+        }
+    }
+
+    /// Sets how long to wait for each reply before returning a
+    /// ReceiveError::TimeoutError.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Closes the client's connection.
+    pub fn close(&mut self) {
+        self.sender.close();
+    }
+
+    /// Sends the request and waits for the reply, which must be of the
+    /// given type.
+    fn request<Q: M, R: M + MessageStatic>(&mut self, request_type: Message_MessageType,
+                                          request: &Q, reply_type: Message_MessageType)
+        -> Result<R, ClientError>
+    {
+        let timeout = self.timeout;
+        self.request_with_timeout(request_type, request, reply_type, timeout)
+    }
+
+    fn request_with_timeout<Q, R>(&mut self, request_type: Message_MessageType, request: &Q,
+                                  reply_type: Message_MessageType, timeout: Duration)
+        -> Result<R, ClientError>
+        where Q: M, R: M + MessageStatic
+    {
+        let content = request.write_to_bytes()?;
+        let mut future = self.sender.send(request_type, &generate_correlation_id(), &content)?;
+        let reply = future.get_timeout(timeout)?;
+        if reply.get_message_type() != reply_type {
+            return Err(ClientError::UnexpectedReply(reply.get_message_type()));
+        }
+        Ok(protobuf::parse_from_bytes(reply.get_content())?)
+    }
+
+    /// Sends the request without waiting for the reply.
+    fn send_only<Q: M>(&mut self, request_type: Message_MessageType, request: &Q)
+        -> Result<(), ClientError>
+    {
+        let content = request.write_to_bytes()?;
+        self.sender.send(request_type, &generate_correlation_id(), &content)?;
+        Ok(())
+    }
+
+    /// Submits the batches to the validator.
+    ///
+    /// # Arguments
+    ///
+    /// * batches - the signed batches to submit
+    pub fn submit_batches(&mut self, batches: &[Batch]) -> Result<(), ClientError> {
+        let mut request = ClientBatchSubmitRequest::new();
+        request.set_batches(RepeatedField::from_slice(batches));
+
+        let response: ClientBatchSubmitResponse = self.request(
+            Message_MessageType::CLIENT_BATCH_SUBMIT_REQUEST, &request,
+            Message_MessageType::CLIENT_BATCH_SUBMIT_RESPONSE)?;
+        check_status(response.get_status())
+    }
+
+    /// Returns the status of each of the given batches.
+    ///
+    /// # Arguments
+    ///
+    /// * batch_ids - the header signatures of the batches
+    /// * wait - if given, how long the validator should wait for the
+    ///   batches to be committed or found invalid before replying; the
+    ///   validator waits in whole seconds, so it is rounded up to at least
+    ///   one second
+    pub fn get_batch_statuses(&mut self, batch_ids: &[String], wait: Option<Duration>)
+        -> Result<Vec<ClientBatchStatus>, ClientError>
+    {
+        let mut request = ClientBatchStatusRequest::new();
+        request.set_batch_ids(RepeatedField::from_slice(batch_ids));
+        let mut timeout = self.timeout;
+        if let Some(wait) = wait {
+            request.set_wait(true);
+            let mut secs = wait.as_secs();
+            if wait.subsec_nanos() > 0 {
+                secs += 1;
+            }
+            request.set_timeout(secs as u32);
+            timeout += wait;
+        }
+
+        let mut response: ClientBatchStatusResponse = self.request_with_timeout(
+            Message_MessageType::CLIENT_BATCH_STATUS_REQUEST, &request,
+            Message_MessageType::CLIENT_BATCH_STATUS_RESPONSE, timeout)?;
+        check_status(response.get_status())?;
+        Ok(response.take_batch_statuses().into_vec())
+    }
+
+    /// Lists blocks, newest first unless sorted otherwise.
+    ///
+    /// # Arguments
+    ///
+    /// * block_ids - the blocks to list; all of them if empty
+    /// * options - the head to list from, and the paging and sorting
+    pub fn list_blocks(&mut self, block_ids: &[String], options: &ListOptions)
+        -> Result<Page<Block>, ClientError>
+    {
+        let mut request = ClientBlockListRequest::new();
+        request.set_head_id(options.head_id.clone().unwrap_or_default());
+        request.set_block_ids(RepeatedField::from_slice(block_ids));
+        request.set_paging(options.paging());
+        request.set_sorting(options.sorting());
+
+        let mut response: ClientBlockListResponse = self.request(
+            Message_MessageType::CLIENT_BLOCK_LIST_REQUEST, &request,
+            Message_MessageType::CLIENT_BLOCK_LIST_RESPONSE)?;
+        check_status(response.get_status())?;
+        Ok(Page::new(response.take_blocks().into_vec(),
+                     response.get_head_id(), response.get_paging()))
+    }
+
+    fn get_block_by<Q: M>(&mut self, request_type: Message_MessageType, request: &Q)
+        -> Result<Block, ClientError>
+    {
+        let mut response: ClientBlockGetResponse = self.request(
+            request_type, request, Message_MessageType::CLIENT_BLOCK_GET_RESPONSE)?;
+        check_status(response.get_status())?;
+        Ok(response.take_block())
+    }
+
+    /// Returns the block with the given id.
+    pub fn get_block(&mut self, block_id: &str) -> Result<Block, ClientError> {
+        let mut request = ClientBlockGetByIdRequest::new();
+        request.set_block_id(String::from(block_id));
+        self.get_block_by(Message_MessageType::CLIENT_BLOCK_GET_BY_ID_REQUEST, &request)
+    }
+
+    /// Returns the block with the given number on the current chain.
+    pub fn get_block_by_num(&mut self, block_num: u64) -> Result<Block, ClientError> {
+        let mut request = ClientBlockGetByNumRequest::new();
+        request.set_block_num(block_num);
+        self.get_block_by(Message_MessageType::CLIENT_BLOCK_GET_BY_NUM_REQUEST, &request)
+    }
+
+    /// Returns the block containing the transaction with the given id.
+    pub fn get_block_by_transaction_id(&mut self, transaction_id: &str)
+        -> Result<Block, ClientError>
+    {
+        let mut request = ClientBlockGetByTransactionIdRequest::new();
+        request.set_transaction_id(String::from(transaction_id));
+        self.get_block_by(
+            Message_MessageType::CLIENT_BLOCK_GET_BY_TRANSACTION_ID_REQUEST, &request)
+    }
+
+    /// Returns the block containing the batch with the given id.
+    pub fn get_block_by_batch_id(&mut self, batch_id: &str) -> Result<Block, ClientError> {
+        let mut request = ClientBlockGetByBatchIdRequest::new();
+        request.set_batch_id(String::from(batch_id));
+        self.get_block_by(Message_MessageType::CLIENT_BLOCK_GET_BY_BATCH_ID_REQUEST, &request)
+    }
+
+    /// Lists batches, newest first unless sorted otherwise.
+    ///
+    /// # Arguments
+    ///
+    /// * batch_ids - the batches to list; all of them if empty
+    /// * options - the head to list from, and the paging and sorting
+    pub fn list_batches(&mut self, batch_ids: &[String], options: &ListOptions)
+        -> Result<Page<Batch>, ClientError>
+    {
+        let mut request = ClientBatchListRequest::new();
+        request.set_head_id(options.head_id.clone().unwrap_or_default());
+        request.set_batch_ids(RepeatedField::from_slice(batch_ids));
+        request.set_paging(options.paging());
+        request.set_sorting(options.sorting());
+
+        let mut response: ClientBatchListResponse = self.request(
+            Message_MessageType::CLIENT_BATCH_LIST_REQUEST, &request,
+            Message_MessageType::CLIENT_BATCH_LIST_RESPONSE)?;
+        check_status(response.get_status())?;
+        Ok(Page::new(response.take_batches().into_vec(),
+                     response.get_head_id(), response.get_paging()))
+    }
+
+    /// Returns the batch with the given id.
+    pub fn get_batch(&mut self, batch_id: &str) -> Result<Batch, ClientError> {
+        let mut request = ClientBatchGetRequest::new();
+        request.set_batch_id(String::from(batch_id));
+
+        let mut response: ClientBatchGetResponse = self.request(
+            Message_MessageType::CLIENT_BATCH_GET_REQUEST, &request,
+            Message_MessageType::CLIENT_BATCH_GET_RESPONSE)?;
+        check_status(response.get_status())?;
+        Ok(response.take_batch())
+    }
+
+    /// Lists transactions, newest first unless sorted otherwise.
+    ///
+    /// # Arguments
+    ///
+    /// * transaction_ids - the transactions to list; all of them if empty
+    /// * options - the head to list from, and the paging and sorting
+    pub fn list_transactions(&mut self, transaction_ids: &[String], options: &ListOptions)
+        -> Result<Page<Transaction>, ClientError>
+    {
+        let mut request = ClientTransactionListRequest::new();
+        request.set_head_id(options.head_id.clone().unwrap_or_default());
+        request.set_transaction_ids(RepeatedField::from_slice(transaction_ids));
+        request.set_paging(options.paging());
+        request.set_sorting(options.sorting());
+
+        let mut response: ClientTransactionListResponse = self.request(
+            Message_MessageType::CLIENT_TRANSACTION_LIST_REQUEST, &request,
+            Message_MessageType::CLIENT_TRANSACTION_LIST_RESPONSE)?;
+        check_status(response.get_status())?;
+        Ok(Page::new(response.take_transactions().into_vec(),
+                     response.get_head_id(), response.get_paging()))
+    }
+
+    /// Returns the transaction with the given id.
+    pub fn get_transaction(&mut self, transaction_id: &str) -> Result<Transaction, ClientError> {
+        let mut request = ClientTransactionGetRequest::new();
+        request.set_transaction_id(String::from(transaction_id));
+
+        let mut response: ClientTransactionGetResponse = self.request(
+            Message_MessageType::CLIENT_TRANSACTION_GET_REQUEST, &request,
+            Message_MessageType::CLIENT_TRANSACTION_GET_RESPONSE)?;
+        check_status(response.get_status())?;
+        Ok(response.take_transaction())
+    }
+
+    /// Returns the state root of the given block, or an empty string, which
+    /// the validator takes to mean the chain head's, if none is given.
+    fn state_root(&mut self, head_id: Option<&str>) -> Result<String, ClientError> {
+        match head_id {
+            Some(head_id) => {
+                let block = self.get_block(head_id)?;
+                let mut header: BlockHeader = protobuf::parse_from_bytes(block.get_header())?;
+                Ok(header.take_state_root_hash())
+            }
+            None => Ok(String::new())
+        }
+    }
+
+    /// Lists the state entries under the address prefix, in address order.
+    ///
+    /// # Arguments
+    ///
+    /// * address - the address prefix to list; all of state if empty
+    /// * options - the head whose state to list, and the paging and sorting
+    pub fn list_state(&mut self, address: &str, options: &ListOptions)
+        -> Result<Page<StateEntry>, ClientError>
+    {
+        let state_root = self.state_root(options.head_id.as_ref().map(|id| id.as_str()))?;
+        self.list_state_at(state_root, address, options)
+    }
+
+    /// Lists the state entries under the address prefix in the given state,
+    /// ignoring the options' head_id.
+    fn list_state_at(&mut self, state_root: String, address: &str, options: &ListOptions)
+        -> Result<Page<StateEntry>, ClientError>
+    {
+        let mut request = ClientStateListRequest::new();
+        request.set_state_root(state_root);
+        request.set_address(String::from(address));
+        request.set_paging(options.paging());
+        request.set_sorting(options.sorting());
+
+        let mut response: ClientStateListResponse = self.request(
+            Message_MessageType::CLIENT_STATE_LIST_REQUEST, &request,
+            Message_MessageType::CLIENT_STATE_LIST_RESPONSE)?;
+        check_status(response.get_status())?;
+        let entries = response.take_entries()
+            .into_iter()
+            .map(|mut entry| StateEntry {
+                address: entry.take_address(),
+                data: entry.take_data(),
+            })
+            .collect();
+        Ok(Page::new(entries, response.get_state_root(), response.get_paging()))
+    }
+
+    /// Returns the data at the address, or None if it is not set.
+    ///
+    /// # Arguments
+    ///
+    /// * address - the address to read
+    /// * head_id - the block whose state to read; the chain head if None
+    pub fn get_state(&mut self, address: &str, head_id: Option<&str>)
+        -> Result<Option<Vec<u8>>, ClientError>
+    {
+        let mut request = ClientStateGetRequest::new();
+        request.set_state_root(self.state_root(head_id)?);
+        request.set_address(String::from(address));
+
+        let mut response: ClientStateGetResponse = self.request(
+            Message_MessageType::CLIENT_STATE_GET_REQUEST, &request,
+            Message_MessageType::CLIENT_STATE_GET_RESPONSE)?;
+        match check_status(response.get_status()) {
+            Ok(()) => Ok(Some(response.take_value())),
+            Err(ClientError::NotFound) => Ok(None),
+            Err(err) => Err(err)
+        }
+    }
+
+    /// Returns the receipts of the given committed transactions.
+    ///
+    /// # Arguments
+    ///
+    /// * transaction_ids - the header signatures of the transactions
+    pub fn get_receipts(&mut self, transaction_ids: &[String])
+        -> Result<Vec<TransactionReceipt>, ClientError>
+    {
+        let mut request = ClientReceiptGetRequest::new();
+        request.set_transaction_ids(RepeatedField::from_slice(transaction_ids));
+
+        let mut response: ClientReceiptGetResponse = self.request(
+            Message_MessageType::CLIENT_RECEIPT_GET_REQUEST, &request,
+            Message_MessageType::CLIENT_RECEIPT_GET_RESPONSE)?;
+        check_status(response.get_status())?;
+        Ok(response.take_receipts().into_vec())
+    }
+
+    /// Returns the decoded receipts of the given committed transactions, in
+    /// the same order.
+    pub fn get_decoded_receipts(&mut self, transaction_ids: &[String])
+        -> Result<Vec<Receipt>, ClientError>
+    {
+        let mut receipts = Vec::with_capacity(transaction_ids.len());
+        for receipt in self.get_receipts(transaction_ids)? {
+            receipts.push(Receipt::from_proto(receipt)?);
+        }
+        Ok(receipts)
+    }
+
+    /// Returns the decoded receipt of the committed transaction.
+    pub fn get_receipt(&mut self, transaction_id: &str) -> Result<Receipt, ClientError> {
+        let mut receipts = self.get_decoded_receipts(&[String::from(transaction_id)])?;
+        receipts.pop().ok_or(ClientError::NotFound)
+    }
+
+    /// Returns the endpoints of the validator's peers.
+    pub fn get_peers(&mut self) -> Result<Vec<String>, ClientError> {
+        let mut response: ClientPeersGetResponse = self.request(
+            Message_MessageType::CLIENT_PEERS_GET_REQUEST, &ClientPeersGetRequest::new(),
+            Message_MessageType::CLIENT_PEERS_GET_RESPONSE)?;
+        check_status(response.get_status())?;
+        Ok(response.take_peers().into_vec())
+    }
+
+    /// Returns the validator's endpoint and its peers' endpoints.
+    pub fn get_status(&mut self) -> Result<ValidatorStatus, ClientError> {
+        let mut response: ClientStatusGetResponse = self.request(
+            Message_MessageType::CLIENT_STATUS_GET_REQUEST, &ClientStatusGetRequest::new(),
+            Message_MessageType::CLIENT_STATUS_GET_RESPONSE)?;
+        check_status(response.get_status())?;
+        Ok(ValidatorStatus {
+            endpoint: response.take_endpoint(),
+            peers: response.take_peers()
+                .into_iter()
+                .map(|mut peer| peer.take_endpoint())
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use protobuf;
+    use protobuf::Message as M;
+    use protobuf::MessageStatic;
+    use protobuf::RepeatedField;
+
+    use std::sync::{Arc, Mutex};
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    use messages::batch::Batch;
+    use messages::block::Block;
+    use messages::block::BlockHeader;
+    use messages::client_batch_submit::*;
+    use messages::client_block::*;
+    use messages::client_list_control::ClientPagingResponse;
+    use messages::client_receipt::*;
+    use messages::client_state::*;
+    use messages::transaction_receipt::StateChange;
+    use messages::transaction_receipt::StateChange_Type;
+    use messages::transaction_receipt::TransactionReceipt;
+    use messages::validator::Message;
+    use messages::validator::Message_MessageType;
+    use messaging::stream::MessageFuture;
+    use messaging::stream::MessageSender;
+    use messaging::stream::SendError;
+
+    use super::ClientError;
+    use super::ListOptions;
+    use super::ValidatorClient;
+
+    type Handler = Fn(Message_MessageType, &[u8]) -> (Message_MessageType, Vec<u8>) + Send + Sync;
+
+    /// Replies to each request with the handler's reply, and records the
+    /// requests sent.
+    #[derive(Clone)]
+    pub struct MockSender {
+        handler: Arc<Handler>,
+        pub requests: Arc<Mutex<Vec<(Message_MessageType, Vec<u8>)>>>,
+    }
+
+    impl MockSender {
+        pub fn new<F>(handler: F) -> MockSender
+            where F: Fn(Message_MessageType, &[u8]) -> (Message_MessageType, Vec<u8>)
+                     + Send + Sync + 'static
+        {
+            MockSender {
+                handler: Arc::new(handler),
+                requests: Arc::new(Mutex::new(Vec::new())),
+            }
+        }
+
+        /// Returns the last request sent, parsed as R.
+        pub fn last_request<R: M + MessageStatic>(&self) -> R {
+            let requests = self.requests.lock().unwrap();
+            protobuf::parse_from_bytes(&requests.last().unwrap().1).unwrap()
+        }
+    }
+
+    impl MessageSender for MockSender {
+        fn send(&mut self, destination: Message_MessageType, correlation_id: &str,
+                contents: &[u8])
+            -> Result<MessageFuture, SendError>
+        {
+            self.requests.lock().unwrap().push((destination, Vec::from(contents)));
+            let (message_type, content) = (self.handler)(destination, contents);
+
+            let mut message = Message::new();
+            message.set_message_type(message_type);
+            message.set_correlation_id(String::from(correlation_id));
+            message.set_content(content);
+
+            let (tx, rx) = channel();
+            tx.send(Ok(message)).unwrap();
+            Ok(MessageFuture::new(rx))
+        }
+
+        fn reply(&mut self, _destination: Message_MessageType, _correlation_id: &str,
+                 _contents: &[u8])
+            -> Result<(), SendError>
+        {
+            Ok(())
+        }
+
+        fn close(&mut self) {}
+    }
+
+    pub fn reply<R: M>(message_type: Message_MessageType, response: &R)
+        -> (Message_MessageType, Vec<u8>)
+    {
+        (message_type, response.write_to_bytes().unwrap())
+    }
+
+    #[test]
+    fn submit_batches_status() {
+        let sender = MockSender::new(|_, contents| {
+            let request: ClientBatchSubmitRequest = protobuf::parse_from_bytes(contents).unwrap();
+            let mut response = ClientBatchSubmitResponse::new();
+            response.set_status(if request.get_batches().len() > 1 {
+                ClientBatchSubmitResponse_Status::QUEUE_FULL
+            } else {
+                ClientBatchSubmitResponse_Status::OK
+            });
+            reply(Message_MessageType::CLIENT_BATCH_SUBMIT_RESPONSE, &response)
+        });
+        let mut client = ValidatorClient::new(sender);
+
+        assert!(client.submit_batches(&[Batch::new()]).is_ok());
+        match client.submit_batches(&[Batch::new(), Batch::new()]) {
+            Err(ClientError::QueueFull) => (),
+            result => panic!("Expected QueueFull, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn unexpected_reply_type() {
+        let sender = MockSender::new(|_, _| {
+            (Message_MessageType::PING_RESPONSE, Vec::new())
+        });
+        let mut client = ValidatorClient::new(sender);
+
+        match client.get_peers() {
+            Err(ClientError::UnexpectedReply(Message_MessageType::PING_RESPONSE)) => (),
+            result => panic!("Expected UnexpectedReply, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn list_blocks_page() {
+        let sender = MockSender::new(|_, _| {
+            let mut paging = ClientPagingResponse::new();
+            paging.set_next(String::from("0x0002"));
+            let mut response = ClientBlockListResponse::new();
+            response.set_status(ClientBlockListResponse_Status::OK);
+            response.set_blocks(RepeatedField::from_vec(vec![Block::new()]));
+            response.set_head_id(String::from("head"));
+            response.set_paging(paging);
+            reply(Message_MessageType::CLIENT_BLOCK_LIST_RESPONSE, &response)
+        });
+        let mut client = ValidatorClient::new(sender.clone());
+
+        let options = ListOptions {
+            start: Some(String::from("0x0001")),
+            limit: Some(1),
+            ..ListOptions::default()
+        };
+        let page = client.list_blocks(&[], &options).unwrap();
+        assert_eq!(page.items.len(), 1);
+        assert_eq!(page.head, "head");
+        assert_eq!(page.next, Some(String::from("0x0002")));
+
+        let request: ClientBlockListRequest = sender.last_request();
+        assert_eq!(request.get_paging().get_start(), "0x0001");
+        assert_eq!(request.get_paging().get_limit(), 1);
+    }
+
+    #[test]
+    fn get_state_at_head() {
+        let sender = MockSender::new(|message_type, contents| {
+            match message_type {
+                Message_MessageType::CLIENT_BLOCK_GET_BY_ID_REQUEST => {
+                    let mut header = BlockHeader::new();
+                    header.set_state_root_hash(String::from("root"));
+                    let mut block = Block::new();
+                    block.set_header(header.write_to_bytes().unwrap());
+                    let mut response = ClientBlockGetResponse::new();
+                    response.set_status(ClientBlockGetResponse_Status::OK);
+                    response.set_block(block);
+                    reply(Message_MessageType::CLIENT_BLOCK_GET_RESPONSE, &response)
+                }
+                _ => {
+                    let request: ClientStateGetRequest =
+                        protobuf::parse_from_bytes(contents).unwrap();
+                    let mut response = ClientStateGetResponse::new();
+                    if request.get_address() == "abcdef" {
+                        response.set_status(ClientStateGetResponse_Status::OK);
+                        response.set_value(vec![1, 2, 3]);
+                    } else {
+                        response.set_status(ClientStateGetResponse_Status::NO_RESOURCE);
+                    }
+                    reply(Message_MessageType::CLIENT_STATE_GET_RESPONSE, &response)
+                }
+            }
+        });
+        let mut client = ValidatorClient::new(sender.clone());
+
+        assert_eq!(client.get_state("abcdef", Some("head")).unwrap(), Some(vec![1, 2, 3]));
+        let request: ClientStateGetRequest = sender.last_request();
+        assert_eq!(request.get_state_root(), "root");
+
+        assert_eq!(client.get_state("012345", None).unwrap(), None);
+        let request: ClientStateGetRequest = sender.last_request();
+        assert_eq!(request.get_state_root(), "");
+    }
+
+    #[test]
+    fn batch_status_wait() {
+        let sender = MockSender::new(|_, _| {
+            let mut response = ClientBatchStatusResponse::new();
+            response.set_status(ClientBatchStatusResponse_Status::INVALID_ID);
+            reply(Message_MessageType::CLIENT_BATCH_STATUS_RESPONSE, &response)
+        });
+        let mut client = ValidatorClient::new(sender.clone());
+
+        match client.get_batch_statuses(&[String::from("bad")], Some(Duration::from_secs(5))) {
+            Err(ClientError::InvalidId) => (),
+            result => panic!("Expected InvalidId, got {:?}", result)
+        }
+        let request: ClientBatchStatusRequest = sender.last_request();
+        assert!(request.get_wait());
+        assert_eq!(request.get_timeout(), 5);
+    }
+
+    #[test]
+    fn batch_status_wait_rounds_up() {
+        let sender = MockSender::new(|_, _| {
+            let mut response = ClientBatchStatusResponse::new();
+            response.set_status(ClientBatchStatusResponse_Status::OK);
+            reply(Message_MessageType::CLIENT_BATCH_STATUS_RESPONSE, &response)
+        });
+        let mut client = ValidatorClient::new(sender.clone());
+
+        client.get_batch_statuses(&[String::from("batch")], Some(Duration::from_millis(500)))
+            .unwrap();
+        let request: ClientBatchStatusRequest = sender.last_request();
+        assert!(request.get_wait());
+        assert_eq!(request.get_timeout(), 1);
+
+        client.get_batch_statuses(&[String::from("batch")], Some(Duration::from_millis(1500)))
+            .unwrap();
+        let request: ClientBatchStatusRequest = sender.last_request();
+        assert_eq!(request.get_timeout(), 2);
+    }
+
+    #[test]
+    fn get_receipt_decoded() {
+        let sender = MockSender::new(|_, contents| {
+            let request: ClientReceiptGetRequest = protobuf::parse_from_bytes(contents).unwrap();
+            let mut change = StateChange::new();
+            change.set_address(String::from("abc123"));
+            change.set_field_type(StateChange_Type::DELETE);
+            let mut receipt = TransactionReceipt::new();
+            receipt.set_transaction_id(String::from(request.get_transaction_ids()[0].as_str()));
+            receipt.set_state_changes(RepeatedField::from_vec(vec![change]));
+
+            let mut response = ClientReceiptGetResponse::new();
+            response.set_status(ClientReceiptGetResponse_Status::OK);
+            response.set_receipts(RepeatedField::from_vec(vec![receipt]));
+            reply(Message_MessageType::CLIENT_RECEIPT_GET_RESPONSE, &response)
+        });
+        let mut client = ValidatorClient::new(sender);
+
+        let receipt = client.get_receipt("txn").unwrap();
+        assert_eq!(receipt.transaction_id, "txn");
+        assert_eq!(receipt.state_changes[0].address(), "abc123");
+    }
+}
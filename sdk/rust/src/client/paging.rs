@@ -0,0 +1,274 @@
+/*
+ * Copyright 2017 Intel Corporation
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Iterating over everything a list request returns, page by page.
+//!
+//! Each iterator requests the next page when it runs out of items, starting
+//! it at the paging id the last page returned. All the pages are listed from
+//! the head the first page was listed from, so that blocks committed while
+//! iterating do not shift the pages.
+
+use std::vec;
+
+use messages::batch::Batch;
+use messages::block::Block;
+use messages::transaction::Transaction;
+use messaging::stream::MessageSender;
+
+use super::ClientError;
+use super::ListOptions;
+use super::Page;
+use super::StateEntry;
+use super::ValidatorClient;
+
+/// Requests the page of items starting at the options' start, from the
+/// options' head. It may pin its own head once it has listed a page.
+type ListPage<'a, T, MS> =
+    FnMut(&mut ValidatorClient<MS>, &ListOptions) -> Result<Page<T>, ClientError> + 'a;
+
+/// An iterator over the items of a list request, which requests each page
+/// as it is needed. After an error, the iterator ends.
+pub struct Pages<'a, T, MS: MessageSender + 'a> {
+    client: &'a mut ValidatorClient<MS>,
+    list_page: Box<ListPage<'a, T, MS>>,
+    options: ListOptions,
+    items: vec::IntoIter<T>,
+    done: bool,
+}
+
+impl<'a, T, MS: MessageSender> Pages<'a, T, MS> {
+    fn new(client: &'a mut ValidatorClient<MS>, options: ListOptions,
+           list_page: Box<ListPage<'a, T, MS>>)
+        -> Pages<'a, T, MS>
+    {
+        Pages {
+            client: client,
+            list_page: list_page,
+            options: options,
+            items: Vec::new().into_iter(),
+            done: false,
+        }
+    }
+
+    /// Returns the head the items are listed from, once the first page has
+    /// been listed; for state, the state root.
+    pub fn head(&self) -> Option<&str> {
+        self.options.head_id.as_ref().map(|head| head.as_str())
+    }
+
+    fn next_page(&mut self) -> Result<(), ClientError> {
+        let page = (self.list_page)(self.client, &self.options)?;
+        self.options.head_id = Some(page.head);
+        match page.next {
+            Some(next) => self.options.start = Some(next),
+            None => self.done = true,
+        }
+        self.items = page.items.into_iter();
+        Ok(())
+    }
+}
+
+impl<'a, T, MS: MessageSender> Iterator for Pages<'a, T, MS> {
+    type Item = Result<T, ClientError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(item) = self.items.next() {
+                return Some(Ok(item));
+            }
+            if self.done {
+                return None;
+            }
+            if let Err(err) = self.next_page() {
+                self.done = true;
+                return Some(Err(err));
+            }
+        }
+    }
+}
+
+impl<MS: MessageSender> ValidatorClient<MS> {
+    /// Iterates over the blocks, newest first unless sorted otherwise.
+    ///
+    /// # Arguments
+    ///
+    /// * options - the head to list from, the page size and the sorting
+    pub fn blocks(&mut self, options: ListOptions) -> Pages<Block, MS> {
+        Pages::new(self, options, Box::new(|client, options| client.list_blocks(&[], options)))
+    }
+
+    /// Iterates over the batches, newest first unless sorted otherwise.
+    ///
+    /// # Arguments
+    ///
+    /// * options - the head to list from, the page size and the sorting
+    pub fn batches(&mut self, options: ListOptions) -> Pages<Batch, MS> {
+        Pages::new(self, options, Box::new(|client, options| client.list_batches(&[], options)))
+    }
+
+    /// Iterates over the transactions, newest first unless sorted otherwise.
+    ///
+    /// # Arguments
+    ///
+    /// * options - the head to list from, the page size and the sorting
+    pub fn transactions(&mut self, options: ListOptions) -> Pages<Transaction, MS> {
+        Pages::new(self, options,
+                   Box::new(|client, options| client.list_transactions(&[], options)))
+    }
+
+    /// Iterates over the state entries under the address prefix, in address
+    /// order unless sorted otherwise.
+    ///
+    /// # Arguments
+    ///
+    /// * address - the address prefix to list; all of state if empty
+    /// * options - the head whose state to list, the page size and the
+    ///   sorting
+    pub fn state_entries(&mut self, address: &str, options: ListOptions)
+        -> Pages<StateEntry, MS>
+    {
+        let address = String::from(address);
+        // The head is resolved to its state root for the first page, which
+        // the later pages are then listed from
+        let mut state_root: Option<String> = None;
+        Pages::new(self, options, Box::new(move |client, options| {
+            let root = match state_root {
+                Some(ref root) => root.clone(),
+                None => client.state_root(options.head_id.as_ref().map(|id| id.as_str()))?,
+            };
+            let page = client.list_state_at(root, &address, options)?;
+            state_root = Some(page.head.clone());
+            Ok(page)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use protobuf;
+    use protobuf::RepeatedField;
+
+    use messages::block::Block;
+    use messages::client_block::*;
+    use messages::client_list_control::ClientPagingResponse;
+    use messages::client_state::*;
+    use messages::validator::Message_MessageType;
+
+    use client::tests::reply;
+    use client::tests::MockSender;
+    use client::ClientError;
+    use client::ListOptions;
+    use client::ValidatorClient;
+
+    fn make_block(header_signature: &str) -> Block {
+        let mut block = Block::new();
+        block.set_header_signature(String::from(header_signature));
+        block
+    }
+
+    /// Lists three blocks, a page of two and then a page of one, from the
+    /// head "b3".
+    fn block_pages() -> MockSender {
+        MockSender::new(|_, contents| {
+            let request: ClientBlockListRequest = protobuf::parse_from_bytes(contents).unwrap();
+            let mut paging = ClientPagingResponse::new();
+            let mut response = ClientBlockListResponse::new();
+            response.set_status(ClientBlockListResponse_Status::OK);
+            response.set_head_id(String::from("b3"));
+            match request.get_paging().get_start() {
+                "" => {
+                    response.set_blocks(RepeatedField::from_vec(vec![
+                        make_block("b3"), make_block("b2")]));
+                    paging.set_next(String::from("b1"));
+                }
+                "b1" => response.set_blocks(RepeatedField::from_vec(vec![make_block("b1")])),
+                start => panic!("Unexpected start {}", start)
+            }
+            response.set_paging(paging);
+            reply(Message_MessageType::CLIENT_BLOCK_LIST_RESPONSE, &response)
+        })
+    }
+
+    #[test]
+    fn blocks_follow_next_and_pin_head() {
+        let sender = block_pages();
+        let mut client = ValidatorClient::new(sender.clone());
+
+        let options = ListOptions { limit: Some(2), ..ListOptions::default() }
+            .sort_by(&["block_num"], true);
+        let ids: Vec<String> = client.blocks(options)
+            .map(|block| String::from(block.unwrap().get_header_signature()))
+            .collect();
+        assert_eq!(ids, vec!["b3", "b2", "b1"]);
+
+        let request: ClientBlockListRequest = sender.last_request();
+        assert_eq!(request.get_head_id(), "b3");
+        assert_eq!(request.get_paging().get_limit(), 2);
+        assert_eq!(request.get_sorting()[0].get_keys(), &[String::from("block_num")]);
+        assert!(request.get_sorting()[0].get_reverse());
+    }
+
+    #[test]
+    fn state_entries_pin_state_root() {
+        let sender = MockSender::new(|_, contents| {
+            let request: ClientStateListRequest = protobuf::parse_from_bytes(contents).unwrap();
+            assert_eq!(request.get_address(), "1cf126");
+
+            let mut entry = ClientStateListResponse_Entry::new();
+            entry.set_address(format!("1cf126{}", request.get_paging().get_start()));
+            let mut paging = ClientPagingResponse::new();
+            if request.get_paging().get_start() == "" {
+                paging.set_next(String::from("01"));
+            }
+            let mut response = ClientStateListResponse::new();
+            response.set_status(ClientStateListResponse_Status::OK);
+            response.set_entries(RepeatedField::from_vec(vec![entry]));
+            response.set_state_root(String::from("root"));
+            response.set_paging(paging);
+            reply(Message_MessageType::CLIENT_STATE_LIST_RESPONSE, &response)
+        });
+        let mut client = ValidatorClient::new(sender.clone());
+
+        let addresses: Vec<String> = client.state_entries("1cf126", ListOptions::default())
+            .map(|entry| entry.unwrap().address)
+            .collect();
+        assert_eq!(addresses, vec!["1cf126", "1cf12601"]);
+
+        let requests = sender.requests.lock().unwrap();
+        let first: ClientStateListRequest = protobuf::parse_from_bytes(&requests[0].1).unwrap();
+        let second: ClientStateListRequest = protobuf::parse_from_bytes(&requests[1].1).unwrap();
+        assert_eq!(first.get_state_root(), "");
+        assert_eq!(second.get_state_root(), "root");
+    }
+
+    #[test]
+    fn error_ends_iteration() {
+        let sender = MockSender::new(|_, _| {
+            let mut response = ClientBlockListResponse::new();
+            response.set_status(ClientBlockListResponse_Status::INVALID_SORT);
+            reply(Message_MessageType::CLIENT_BLOCK_LIST_RESPONSE, &response)
+        });
+        let mut client = ValidatorClient::new(sender);
+
+        let mut blocks = client.blocks(ListOptions::default());
+        match blocks.next() {
+            Some(Err(ClientError::InvalidSort)) => (),
+            result => panic!("Expected InvalidSort, got {:?}", result)
+        }
+        assert!(blocks.next().is_none());
+    }
+}
@@ -0,0 +1,191 @@
+/*
+ * Copyright 2017 Bitwise IO, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Codecs for storing typed values in state, used with
+//! TransactionContext::get_typed and set_typed.
+//!
+//! ProtobufCodec is always available. JsonCodec and CborCodec work with
+//! any serde type, and are enabled by the "json" and "cbor" features.
+
+use protobuf;
+use protobuf::MessageStatic;
+
+use std;
+use std::error::Error as StdError;
+
+#[cfg(any(feature = "json", feature = "cbor"))]
+use serde::Serialize;
+#[cfg(any(feature = "json", feature = "cbor"))]
+use serde::de::DeserializeOwned;
+#[cfg(feature = "cbor")]
+use serde_cbor;
+#[cfg(feature = "json")]
+use serde_json;
+
+#[derive(Debug)]
+pub enum CodecError {
+    /// Returned when a value cannot be encoded
+    EncodingError(Box<StdError + Send + Sync>),
+    /// Returned when data cannot be decoded into a value
+    DecodingError(Box<StdError + Send + Sync>),
+}
+
+impl StdError for CodecError {
+    fn description(&self) -> &str {
+        match *self {
+            CodecError::EncodingError(ref err) => err.description(),
+            CodecError::DecodingError(ref err) => err.description(),
+        }
+    }
+
+    fn cause(&self) -> Option<&StdError> {
+        match *self {
+            CodecError::EncodingError(ref err) => Some(&**err),
+            CodecError::DecodingError(ref err) => Some(&**err),
+        }
+    }
+}
+
+impl std::fmt::Display for CodecError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            CodecError::EncodingError(ref err) =>
+                write!(f, "EncodingError: {}", err),
+            CodecError::DecodingError(ref err) =>
+                write!(f, "DecodingError: {}", err),
+        }
+    }
+}
+
+/// A Codec converts values of type T to and from the bytes stored in state.
+pub trait Codec<T> {
+    fn encode(&self, value: &T) -> Result<Vec<u8>, CodecError>;
+
+    fn decode(&self, data: &[u8]) -> Result<T, CodecError>;
+}
+
+/// Stores protobuf messages in their binary encoding.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ProtobufCodec;
+
+impl<T: protobuf::Message + MessageStatic> Codec<T> for ProtobufCodec {
+    fn encode(&self, value: &T) -> Result<Vec<u8>, CodecError> {
+        value.write_to_bytes().map_err(|err| CodecError::EncodingError(Box::new(err)))
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<T, CodecError> {
+        protobuf::parse_from_bytes(data).map_err(|err| CodecError::DecodingError(Box::new(err)))
+    }
+}
+
+/// Stores serde values as JSON.
+#[cfg(feature = "json")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct JsonCodec;
+
+#[cfg(feature = "json")]
+impl<T: Serialize + DeserializeOwned> Codec<T> for JsonCodec {
+    fn encode(&self, value: &T) -> Result<Vec<u8>, CodecError> {
+        serde_json::to_vec(value).map_err(|err| CodecError::EncodingError(Box::new(err)))
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<T, CodecError> {
+        serde_json::from_slice(data).map_err(|err| CodecError::DecodingError(Box::new(err)))
+    }
+}
+
+/// Stores serde values as CBOR.
+#[cfg(feature = "cbor")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CborCodec;
+
+#[cfg(feature = "cbor")]
+impl<T: Serialize + DeserializeOwned> Codec<T> for CborCodec {
+    fn encode(&self, value: &T) -> Result<Vec<u8>, CodecError> {
+        serde_cbor::to_vec(value).map_err(|err| CodecError::EncodingError(Box::new(err)))
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<T, CodecError> {
+        serde_cbor::from_slice(data).map_err(|err| CodecError::DecodingError(Box::new(err)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use messages::transaction::TransactionHeader;
+    use processor::handler::ContextError;
+    use testing::MockValidator;
+
+    use super::Codec;
+    use super::ProtobufCodec;
+
+    #[test]
+    fn protobuf_round_trip() {
+        let mut header = TransactionHeader::new();
+        header.set_family_name(String::from("intkey"));
+
+        let data = ProtobufCodec.encode(&header).unwrap();
+        let decoded: TransactionHeader = ProtobufCodec.decode(&data).unwrap();
+        assert_eq!(decoded, header);
+    }
+
+    #[test]
+    fn typed_context_state() {
+        let validator = MockValidator::new();
+        let mut context = validator.context("context");
+
+        let mut header = TransactionHeader::new();
+        header.set_family_name(String::from("intkey"));
+        context.set_typed("abcdef", &header, &ProtobufCodec).unwrap();
+
+        let stored: Option<TransactionHeader> = context.get_typed("abcdef", &ProtobufCodec).unwrap();
+        assert_eq!(stored, Some(header));
+
+        let unset: Option<TransactionHeader> = context.get_typed("012345", &ProtobufCodec).unwrap();
+        assert_eq!(unset, None);
+
+        validator.set_state("012345", &[0xff]);
+        let result: Result<Option<TransactionHeader>, _> = context.get_typed("012345", &ProtobufCodec);
+        match result {
+            Err(ContextError::CodecError(_)) => (),
+            result => panic!("Expected a CodecError, got {:?}", result)
+        }
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json_round_trip() {
+        use super::JsonCodec;
+
+        let value = vec![(String::from("a"), 1u32)];
+        let data = JsonCodec.encode(&value).unwrap();
+        assert_eq!(data, br#"[["a",1]]"#.to_vec());
+        let decoded: Vec<(String, u32)> = JsonCodec.decode(&data).unwrap();
+        assert_eq!(decoded, value);
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn cbor_round_trip() {
+        use super::CborCodec;
+
+        let value = vec![(String::from("a"), 1u32)];
+        let data = CborCodec.encode(&value).unwrap();
+        let decoded: Vec<(String, u32)> = CborCodec.decode(&data).unwrap();
+        assert_eq!(decoded, value);
+    }
+}
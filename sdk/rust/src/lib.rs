@@ -16,16 +16,34 @@
  */
 
 extern crate crypto;
+#[cfg(feature = "async")]
+extern crate futures;
 extern crate libc;
 #[macro_use]
 extern crate log;
 extern crate protobuf;
 extern crate rand;
+extern crate regex;
 extern crate secp256k1;
+#[cfg(any(feature = "json", feature = "cbor"))]
+extern crate serde;
+#[cfg(feature = "cbor")]
+extern crate serde_cbor;
+#[cfg(feature = "json")]
+extern crate serde_json;
+#[cfg(feature = "async")]
+extern crate tokio;
 extern crate uuid;
 extern crate zmq;
 
+pub mod addressing;
+pub mod builders;
+pub mod client;
+pub mod codec;
 pub mod messages;
 pub mod messaging;
 pub mod signing;
 pub mod processor;
+pub mod receipts;
+pub mod testing;
+pub mod validation;
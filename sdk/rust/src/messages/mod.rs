@@ -30,6 +30,7 @@ pub mod client_transaction;
 pub mod client_batch_submit;
 pub mod client_list_control;
 pub mod client_peers;
+pub mod client_status;
 pub mod network;
 pub mod events;
 pub mod client_event;
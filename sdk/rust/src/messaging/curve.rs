@@ -0,0 +1,290 @@
+/*
+ * Copyright 2017 Intel Corporation
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Settings for encrypting and authenticating connections to the validator
+//! with ZMQ CURVE.
+//!
+//! Keys are 32 bytes, written either in Z85, as 40 characters, or in hex, as
+//! 64 characters. Key files hold a single key; surrounding whitespace is
+//! ignored.
+
+use zmq;
+
+use std;
+use std::error::Error as StdError;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+/// The number of bytes in a CURVE key.
+pub const CURVE_KEY_LENGTH: usize = 32;
+
+const Z85_CHARS: &[u8] =
+    b"0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ.-:+=^!/*?&<>()[]{}@%$#";
+
+#[derive(Debug)]
+pub enum CurveError {
+    /// Returned when a key file cannot be read
+    IoError(io::Error),
+    /// Returned when a key is not 32 bytes in Z85 or hex
+    KeyError(String),
+}
+
+impl StdError for CurveError {
+    fn description(&self) -> &str {
+        match *self {
+            CurveError::IoError(ref err) => err.description(),
+            CurveError::KeyError(ref msg) => msg,
+        }
+    }
+
+    fn cause(&self) -> Option<&StdError> {
+        match *self {
+            CurveError::IoError(ref err) => Some(err),
+            CurveError::KeyError(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for CurveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            CurveError::IoError(ref err) => write!(f, "IoError: {}", err),
+            CurveError::KeyError(ref msg) => write!(f, "KeyError: {}", msg),
+        }
+    }
+}
+
+impl From<io::Error> for CurveError {
+    fn from(err: io::Error) -> Self {
+        CurveError::IoError(err)
+    }
+}
+
+/// A CURVE public or secret key.
+#[derive(Clone, PartialEq)]
+pub struct CurveKey {
+    bytes: [u8; CURVE_KEY_LENGTH]
+}
+
+impl CurveKey {
+    /// Creates a key from its 32 bytes.
+    pub fn from_bytes(bytes: &[u8]) -> Result<CurveKey, CurveError> {
+        if bytes.len() != CURVE_KEY_LENGTH {
+            return Err(CurveError::KeyError(format!(
+                "Key is {} bytes; expected {}", bytes.len(), CURVE_KEY_LENGTH)));
+        }
+        let mut key = CurveKey { bytes: [0; CURVE_KEY_LENGTH] };
+        key.bytes.copy_from_slice(bytes);
+        Ok(key)
+    }
+
+    /// Creates a key from 40 characters of Z85.
+    pub fn from_z85(s: &str) -> Result<CurveKey, CurveError> {
+        CurveKey::from_bytes(&z85_decode(s)?)
+    }
+
+    /// Creates a key from 64 hex characters.
+    pub fn from_hex(s: &str) -> Result<CurveKey, CurveError> {
+        CurveKey::from_bytes(&hex_decode(s)?)
+    }
+
+    /// Creates a key from either its Z85 or hex encoding, told apart by
+    /// their lengths.
+    pub fn parse(s: &str) -> Result<CurveKey, CurveError> {
+        let s = s.trim();
+        if s.len() == CURVE_KEY_LENGTH * 2 {
+            CurveKey::from_hex(s)
+        } else {
+            CurveKey::from_z85(s)
+        }
+    }
+
+    /// Reads a key in Z85 or hex from the file at the given path.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<CurveKey, CurveError> {
+        let mut contents = String::new();
+        File::open(path)?.read_to_string(&mut contents)?;
+        CurveKey::parse(&contents)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+// Keys are not printed, as they may be secret
+impl fmt::Debug for CurveKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CurveKey(..)")
+    }
+}
+
+/// The keys a client needs to connect to a validator with CURVE: the
+/// validator's public key, and the client's own keypair.
+#[derive(Clone, Debug)]
+pub struct CurveSettings {
+    server_public_key: CurveKey,
+    public_key: CurveKey,
+    secret_key: CurveKey,
+}
+
+impl CurveSettings {
+    /// # Arguments
+    ///
+    /// * `server_public_key` - the validator's public key
+    /// * `public_key` - the client's public key
+    /// * `secret_key` - the client's secret key
+    pub fn new(server_public_key: CurveKey, public_key: CurveKey, secret_key: CurveKey)
+        -> CurveSettings
+    {
+        CurveSettings {
+            server_public_key: server_public_key,
+            public_key: public_key,
+            secret_key: secret_key,
+        }
+    }
+
+    /// Reads the keys from files, each holding a single key in Z85 or hex.
+    ///
+    /// # Arguments
+    ///
+    /// * `server_public_key` - the path to the validator's public key
+    /// * `public_key` - the path to the client's public key
+    /// * `secret_key` - the path to the client's secret key
+    pub fn from_files<P, Q, R>(server_public_key: P, public_key: Q, secret_key: R)
+        -> Result<CurveSettings, CurveError>
+        where P: AsRef<Path>, Q: AsRef<Path>, R: AsRef<Path>
+    {
+        Ok(CurveSettings::new(
+            CurveKey::from_file(server_public_key)?,
+            CurveKey::from_file(public_key)?,
+            CurveKey::from_file(secret_key)?))
+    }
+
+    pub fn server_public_key(&self) -> &CurveKey {
+        &self.server_public_key
+    }
+
+    pub fn public_key(&self) -> &CurveKey {
+        &self.public_key
+    }
+
+    /// Configures the socket as a CURVE client. This must be done before
+    /// the socket connects.
+    pub fn apply(&self, socket: &zmq::Socket) -> Result<(), zmq::Error> {
+        socket.set_curve_serverkey(self.server_public_key.as_bytes())?;
+        socket.set_curve_publickey(self.public_key.as_bytes())?;
+        socket.set_curve_secretkey(self.secret_key.as_bytes())?;
+        Ok(())
+    }
+}
+
+fn z85_decode(s: &str) -> Result<Vec<u8>, CurveError> {
+    let s = s.as_bytes();
+    if s.len() % 5 != 0 {
+        return Err(CurveError::KeyError(format!(
+            "Z85 length {} is not a multiple of 5", s.len())));
+    }
+
+    let mut decoded = Vec::with_capacity(s.len() / 5 * 4);
+    for chunk in s.chunks(5) {
+        let mut value: u64 = 0;
+        for c in chunk {
+            match Z85_CHARS.iter().position(|z| z == c) {
+                Some(digit) => value = value * 85 + digit as u64,
+                None => return Err(CurveError::KeyError(format!(
+                    "Invalid Z85 character {:?}", *c as char)))
+            }
+        }
+        if value > u64::from(std::u32::MAX) {
+            return Err(CurveError::KeyError(String::from("Invalid Z85 encoding")));
+        }
+        decoded.extend_from_slice(&[
+            (value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8]);
+    }
+    Ok(decoded)
+}
+
+fn hex_decode(s: &str) -> Result<Vec<u8>, CurveError> {
+    if s.len() % 2 != 0 {
+        return Err(CurveError::KeyError(format!("Hex length {} is odd", s.len())));
+    }
+    let digits: Result<Vec<u8>, CurveError> = s.chars()
+        .map(|c| c.to_digit(16)
+             .map(|digit| digit as u8)
+             .ok_or_else(|| CurveError::KeyError(format!("Invalid hex character {:?}", c))))
+        .collect();
+    Ok(digits?.chunks(2).map(|pair| (pair[0] << 4) | pair[1]).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::File;
+    use std::io::Write;
+
+    use super::*;
+
+    // The client keypair from the ZMQ CURVE examples
+    static CLIENT_PUBLIC_Z85: &'static str = "Yne@$w-vo<fVvi]a<NY6T1ed:M$fCG*[IaLV{hID";
+    static CLIENT_SECRET_Z85: &'static str = "D:)Q[IlAW!ahhC2ac:9*A}h:p?([4%wOTJ%JR%cs";
+
+    #[test]
+    fn z85_decoding() {
+        assert_eq!(z85_decode("HelloWorld").unwrap(),
+                   vec![0x86, 0x4f, 0xd2, 0x6f, 0xb5, 0x59, 0xf7, 0x5b]);
+        assert!(z85_decode("Hello").is_ok());
+        assert!(z85_decode("Hell").is_err());
+        assert!(z85_decode("Hell~").is_err());
+        assert!(z85_decode("#####").is_err());
+    }
+
+    #[test]
+    fn parse_z85_and_hex() {
+        let key = CurveKey::parse(CLIENT_PUBLIC_Z85).unwrap();
+        let hex: String = key.as_bytes().iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(CurveKey::parse(&hex).unwrap(), key);
+        assert_eq!(CurveKey::parse(&hex.to_uppercase()).unwrap(), key);
+
+        assert!(CurveKey::parse("HelloWorld").is_err());
+        assert!(CurveKey::parse(&hex[..62]).is_err());
+    }
+
+    #[test]
+    fn settings_from_files() {
+        let dir = env::temp_dir();
+        let public_path = dir.join(format!("curve-test-{}.pub", std::process::id()));
+        let secret_path = dir.join(format!("curve-test-{}.key", std::process::id()));
+        writeln!(File::create(&public_path).unwrap(), "{}", CLIENT_PUBLIC_Z85).unwrap();
+        writeln!(File::create(&secret_path).unwrap(), "  {}  ", CLIENT_SECRET_Z85).unwrap();
+
+        let settings = CurveSettings::from_files(&public_path, &public_path, &secret_path);
+        let missing = CurveSettings::from_files(
+            dir.join("curve-test-missing.pub"), &public_path, &secret_path);
+        std::fs::remove_file(&public_path).unwrap();
+        std::fs::remove_file(&secret_path).unwrap();
+
+        let settings = settings.unwrap();
+        assert_eq!(settings.public_key(), &CurveKey::from_z85(CLIENT_PUBLIC_Z85).unwrap());
+        match missing {
+            Err(CurveError::IoError(_)) => (),
+            result => panic!("Expected an IoError, got {:?}", result)
+        }
+    }
+}
@@ -14,5 +14,7 @@
  * limitations under the License.
  * ------------------------------------------------------------------------------
  */
+pub mod curve;
+pub mod shared;
 pub mod stream;
 pub mod zmq_stream;
@@ -0,0 +1,361 @@
+/*
+ * Copyright 2017 Intel Corporation
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Sharing one ZMQ socket among many logical clients.
+//!
+//! Each ZmqMessageConnection::create starts a thread and connects a socket.
+//! A SharedConnection does that once, and then hands out any number of
+//! SharedMessageSenders over the same socket. Replies are routed to the
+//! client which sent the request by correlation id, as on any connection;
+//! messages which are not replies are delivered to each client subscribed
+//! to their message type.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{sync_channel, RecvTimeoutError, SyncSender, TrySendError};
+use std::thread;
+use std::time::Duration;
+
+use messages::validator::Message_MessageType;
+use messaging::stream::*;
+use messaging::zmq_stream::ZmqMessageConnection;
+use messaging::zmq_stream::ZmqMessageSender;
+
+const CHANNEL_BUFFER_SIZE: usize = 128;
+
+const RECV_TIMEOUT_MILLIS: u64 = 100;
+
+/// A cloneable handle to one ZMQ connection, shared by the clients it
+/// creates. The socket is connected when the first client is created, and
+/// again by the next client created after the connection is lost.
+#[derive(Clone)]
+pub struct SharedConnection {
+    conn: ZmqMessageConnection,
+    inner: Arc<Mutex<Option<Shared>>>,
+}
+
+/// The socket, and the clients using it.
+struct Shared {
+    sender: ZmqMessageSender,
+    subscribers: Arc<Mutex<Subscribers>>,
+}
+
+impl SharedConnection {
+    /// # Arguments
+    ///
+    /// * conn - the connection to share, with any settings already applied
+    pub fn new(conn: ZmqMessageConnection) -> SharedConnection {
+        SharedConnection {
+            conn: conn,
+            inner: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Creates a client which receives the messages of the given types that
+    /// are not replies to its own requests.
+    ///
+    /// # Arguments
+    ///
+    /// * message_types - the types of unsolicited message to receive
+    pub fn create_filtered(&self, message_types: &[Message_MessageType])
+        -> (SharedMessageSender, MessageReceiver)
+    {
+        self.subscribe(Some(message_types.to_vec()))
+    }
+
+    /// Returns the number of clients using the socket.
+    pub fn client_count(&self) -> usize {
+        match *self.inner.lock().unwrap() {
+            Some(ref shared) => shared.subscribers.lock().unwrap().entries.len(),
+            None => 0
+        }
+    }
+
+    /// Closes the socket. Each client receives a DisconnectedError, and its
+    /// requests awaiting replies fail.
+    pub fn close(&self) {
+        if let Some(mut shared) = self.inner.lock().unwrap().take() {
+            shared.subscribers.lock().unwrap().disconnect(ReceiveError::DisconnectedError);
+            shared.sender.close();
+        }
+    }
+
+    fn subscribe(&self, message_types: Option<Vec<Message_MessageType>>)
+        -> (SharedMessageSender, MessageReceiver)
+    {
+        let mut inner = self.inner.lock().unwrap();
+
+        let connected = match *inner {
+            Some(ref shared) => shared.subscribers.lock().unwrap().connected,
+            None => false
+        };
+        if !connected {
+            *inner = Some(self.connect());
+        }
+
+        let shared = inner.as_ref().unwrap();
+        let (tx, rx) = sync_channel(CHANNEL_BUFFER_SIZE);
+        let id = shared.subscribers.lock().unwrap().add(message_types, tx);
+
+        let sender = SharedMessageSender {
+            sender: shared.sender.clone(),
+            subscribers: shared.subscribers.clone(),
+            id: id,
+        };
+        (sender, rx)
+    }
+
+    fn connect(&self) -> Shared {
+        let (sender, receiver) = self.conn.create();
+        let subscribers = Arc::new(Mutex::new(Subscribers::new()));
+
+        let thread_subscribers = subscribers.clone();
+        thread::spawn(move || distribute(receiver, thread_subscribers));
+
+        Shared {
+            sender: sender,
+            subscribers: subscribers,
+        }
+    }
+}
+
+impl MessageConnection<SharedMessageSender> for SharedConnection {
+    /// Creates a client which receives all the messages that are not
+    /// replies to its own requests.
+    fn create(&self) -> (SharedMessageSender, MessageReceiver) {
+        self.subscribe(None)
+    }
+}
+
+/// Delivers the messages from the socket to the subscribed clients, until
+/// the connection is lost or closed.
+fn distribute(receiver: MessageReceiver, subscribers: Arc<Mutex<Subscribers>>) {
+    loop {
+        let message_result = match receiver.recv_timeout(
+            Duration::from_millis(RECV_TIMEOUT_MILLIS))
+        {
+            Ok(message_result) => message_result,
+            Err(RecvTimeoutError::Timeout) => {
+                if subscribers.lock().unwrap().connected {
+                    continue;
+                }
+                break;
+            }
+            Err(RecvTimeoutError::Disconnected) => Err(ReceiveError::DisconnectedError)
+        };
+
+        let mut subscribers = subscribers.lock().unwrap();
+        match message_result {
+            Err(ReceiveError::DisconnectedError) | Err(ReceiveError::SocketError(_)) => {
+                subscribers.disconnect(message_result.unwrap_err());
+                break;
+            }
+            _ => subscribers.deliver(&message_result)
+        }
+    }
+    debug!("Exited shared connection distributor");
+}
+
+/// A client of a SharedConnection. Clones of a client share its
+/// subscription.
+#[derive(Clone)]
+pub struct SharedMessageSender {
+    sender: ZmqMessageSender,
+    subscribers: Arc<Mutex<Subscribers>>,
+    id: usize,
+}
+
+impl MessageSender for SharedMessageSender {
+    fn send(&mut self, destination: Message_MessageType, correlation_id: &str,
+            contents: &[u8])
+        -> Result<MessageFuture, SendError>
+    {
+        self.sender.send(destination, correlation_id, contents)
+    }
+
+    fn reply(&mut self, destination: Message_MessageType, correlation_id: &str,
+             contents: &[u8])
+        -> Result<(), SendError>
+    {
+        self.sender.reply(destination, correlation_id, contents)
+    }
+
+    /// Removes this client from the connection, closing its receiver. The
+    /// socket stays open for the other clients; use SharedConnection::close
+    /// to close it.
+    fn close(&mut self) {
+        self.subscribers.lock().unwrap().remove(self.id);
+    }
+}
+
+struct Subscriber {
+    // None if the client receives every type
+    message_types: Option<Vec<Message_MessageType>>,
+    sender: SyncSender<MessageResult>,
+}
+
+impl Subscriber {
+    fn wants(&self, message_result: &MessageResult) -> bool {
+        match (message_result, &self.message_types) {
+            (_, &None) => true,
+            (&Ok(ref message), &Some(ref message_types)) =>
+                message_types.contains(&message.get_message_type()),
+            // Errors concern every client
+            (&Err(_), _) => true
+        }
+    }
+}
+
+/// The clients of one socket, by id.
+struct Subscribers {
+    next_id: usize,
+    entries: HashMap<usize, Subscriber>,
+    connected: bool,
+}
+
+impl Subscribers {
+    fn new() -> Subscribers {
+        Subscribers {
+            next_id: 0,
+            entries: HashMap::new(),
+            connected: true,
+        }
+    }
+
+    fn add(&mut self, message_types: Option<Vec<Message_MessageType>>,
+           sender: SyncSender<MessageResult>)
+        -> usize
+    {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.entries.insert(id, Subscriber {
+            message_types: message_types,
+            sender: sender,
+        });
+        id
+    }
+
+    fn remove(&mut self, id: usize) {
+        self.entries.remove(&id);
+    }
+
+    /// Sends the message to each client subscribed to its type. A client
+    /// whose channel is full misses the message, rather than holding up
+    /// the others; a client whose receiver was dropped is removed.
+    fn deliver(&mut self, message_result: &MessageResult) {
+        let mut closed = Vec::new();
+        for (id, subscriber) in &self.entries {
+            if !subscriber.wants(message_result) {
+                continue;
+            }
+            match subscriber.sender.try_send(message_result.clone()) {
+                Ok(()) => (),
+                Err(TrySendError::Full(_)) =>
+                    warn!("Client {} is not keeping up; dropping message", id),
+                Err(TrySendError::Disconnected(_)) => closed.push(*id)
+            }
+        }
+        for id in closed {
+            self.entries.remove(&id);
+        }
+    }
+
+    /// Sends the error to every client, then closes their receivers.
+    fn disconnect(&mut self, err: ReceiveError) {
+        self.connected = false;
+        for (_, subscriber) in self.entries.drain() {
+            let _ = subscriber.sender.try_send(Err(err.clone()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::sync_channel;
+
+    use messages::validator::Message;
+    use messages::validator::Message_MessageType;
+    use messaging::stream::MessageConnection;
+    use messaging::stream::MessageSender;
+    use messaging::stream::ReceiveError;
+    use messaging::zmq_stream::ZmqMessageConnection;
+
+    use super::SharedConnection;
+    use super::Subscribers;
+
+    fn make_message(message_type: Message_MessageType) -> Message {
+        let mut message = Message::new();
+        message.set_message_type(message_type);
+        message
+    }
+
+    #[test]
+    fn deliver_by_message_type() {
+        let mut subscribers = Subscribers::new();
+        let (events_tx, events_rx) = sync_channel(4);
+        let (all_tx, all_rx) = sync_channel(4);
+        subscribers.add(Some(vec![Message_MessageType::CLIENT_EVENTS]), events_tx);
+        subscribers.add(None, all_tx);
+
+        subscribers.deliver(&Ok(make_message(Message_MessageType::PING_REQUEST)));
+        subscribers.deliver(&Ok(make_message(Message_MessageType::CLIENT_EVENTS)));
+
+        assert_eq!(events_rx.try_recv().unwrap().unwrap().get_message_type(),
+                   Message_MessageType::CLIENT_EVENTS);
+        assert!(events_rx.try_recv().is_err());
+        assert_eq!(all_rx.try_iter().count(), 2);
+    }
+
+    #[test]
+    fn dropped_and_disconnected_clients_are_removed() {
+        let mut subscribers = Subscribers::new();
+        let (dropped_tx, dropped_rx) = sync_channel(1);
+        let (tx, rx) = sync_channel(1);
+        subscribers.add(None, dropped_tx);
+        subscribers.add(None, tx);
+        drop(dropped_rx);
+
+        subscribers.deliver(&Ok(make_message(Message_MessageType::PING_REQUEST)));
+        assert_eq!(subscribers.entries.len(), 1);
+        assert!(rx.recv().unwrap().is_ok());
+
+        subscribers.disconnect(ReceiveError::DisconnectedError);
+        assert!(!subscribers.connected);
+        match rx.recv().unwrap() {
+            Err(ReceiveError::DisconnectedError) => (),
+            result => panic!("Expected a DisconnectedError, got {:?}", result)
+        }
+        assert!(rx.recv().is_err());
+    }
+
+    #[test]
+    fn clients_share_one_socket() {
+        let shared = SharedConnection::new(ZmqMessageConnection::new("tcp://127.0.0.1:4004"));
+        let (mut first, first_rx) = shared.create();
+        let (_second, _second_rx) =
+            shared.create_filtered(&[Message_MessageType::CLIENT_EVENTS]);
+        assert_eq!(shared.client_count(), 2);
+
+        // Closing a client leaves the others connected
+        first.close();
+        assert!(first_rx.recv().is_err());
+        assert_eq!(shared.client_count(), 1);
+
+        shared.close();
+        assert_eq!(shared.client_count(), 0);
+    }
+}
@@ -22,6 +22,17 @@ use std::sync::mpsc::Receiver;
 use std::sync::mpsc::RecvError;
 use std::time::Duration;
 
+#[cfg(feature = "async")]
+use futures::channel::mpsc::UnboundedReceiver;
+#[cfg(feature = "async")]
+use futures::channel::oneshot;
+#[cfg(feature = "async")]
+use std::future::Future;
+#[cfg(feature = "async")]
+use std::pin::Pin;
+#[cfg(feature = "async")]
+use std::task::{Context, Poll};
+
 /// A Message Sender
 ///
 /// A message
@@ -51,11 +62,38 @@ pub trait MessageConnection<MS: MessageSender> {
     fn create(&self) -> (MS, MessageReceiver);
 }
 
+/// A message Receiver which can be polled as a futures Stream.
+#[cfg(feature = "async")]
+pub type AsyncMessageReceiver = UnboundedReceiver<MessageResult>;
+
+/// An Async Message Sender
+///
+/// A MessageSender whose replies can be awaited, rather than blocked on.
+/// Its `close` may be called from a runtime thread, so must not wait for
+/// the connection to shut down.
+#[cfg(feature = "async")]
+pub trait AsyncMessageSender: MessageSender {
+    fn send_async(&mut self, destination: Message_MessageType, correlation_id: &str,
+                  contents: &[u8])
+        -> Result<ReplyFuture, SendError>;
+}
+
+/// An Async Message Connection
+///
+/// This denotes a connection which can create a MessageSender and a
+/// Receiver that is polled as a Stream.
+#[cfg(feature = "async")]
+pub trait AsyncMessageConnection<MS: AsyncMessageSender> {
+    fn create_async(&self) -> (MS, AsyncMessageReceiver);
+}
+
 /// Errors that occur on sending a message.
 #[derive(Debug)]
 pub enum SendError {
     DisconnectedError,
     TimeoutError,
+    /// Returned when too many sent messages are already awaiting replies
+    QueueFullError,
     UnknownError
 }
 
@@ -64,6 +102,7 @@ impl std::error::Error for SendError {
         match *self {
             SendError::DisconnectedError => "DisconnectedError",
             SendError::TimeoutError => "TimeoutError",
+            SendError::QueueFullError => "QueueFullError",
             SendError::UnknownError  => "UnknownError"
         }
     }
@@ -72,6 +111,7 @@ impl std::error::Error for SendError {
         match *self {
             SendError::DisconnectedError => None,
             SendError::TimeoutError => None,
+            SendError::QueueFullError => None,
             SendError::UnknownError => None
         }
     }
@@ -84,6 +124,8 @@ impl std::fmt::Display for SendError {
                 write!(f, "DisconnectedError"),
             SendError::TimeoutError =>
                 write!(f,"TimeoutError"),
+            SendError::QueueFullError =>
+                write!(f, "QueueFullError"),
             SendError::UnknownError =>
                 write!(f, "UnknownError")
         }
@@ -95,7 +137,12 @@ impl std::fmt::Display for SendError {
 pub enum ReceiveError {
     TimeoutError,
     ChannelError(RecvError),
-    DisconnectedError
+    DisconnectedError,
+    /// Returned when a frame received from the validator is not a valid
+    /// message
+    ParseError(String),
+    /// Returned when the socket fails; the connection is closed
+    SocketError(String)
 }
 
 impl std::error::Error for ReceiveError {
@@ -103,7 +150,9 @@ impl std::error::Error for ReceiveError {
         match *self {
             ReceiveError::TimeoutError => "TimeoutError",
             ReceiveError::ChannelError(ref err) => err.description(),
-            ReceiveError::DisconnectedError=> "DisconnectedError"
+            ReceiveError::DisconnectedError=> "DisconnectedError",
+            ReceiveError::ParseError(ref msg) => msg,
+            ReceiveError::SocketError(ref msg) => msg
         }
     }
 
@@ -112,6 +161,8 @@ impl std::error::Error for ReceiveError {
             ReceiveError::TimeoutError => None,
             ReceiveError::ChannelError(ref err) => Some(err),
             ReceiveError::DisconnectedError => None,
+            ReceiveError::ParseError(_) => None,
+            ReceiveError::SocketError(_) => None,
         }
     }
 }
@@ -125,20 +176,42 @@ impl std::fmt::Display for ReceiveError {
                 write!(f, "ChannelError: {}", err.description()),
             ReceiveError::DisconnectedError=>
                 write!(f, "DisconnectedError"),
+            ReceiveError::ParseError(ref msg) =>
+                write!(f, "ParseError: {}", msg),
+            ReceiveError::SocketError(ref msg) =>
+                write!(f, "SocketError: {}", msg),
         }
     }
 }
+/// Called when the future for a reply is dropped, so that the connection
+/// can stop waiting for a reply nobody will read.
+pub type CancelReply = Box<FnMut() + Send>;
+
 /// MessageFuture is a promise for the reply to a sent message on connection.
+///
+/// A future which times out may be waited on again. The connection keeps
+/// waiting for the reply until the future is dropped.
 pub struct MessageFuture {
     inner:  Receiver<MessageResult>,
-    result: Option<MessageResult>
+    result: Option<MessageResult>,
+    cancel: Option<CancelReply>
 }
 
 impl MessageFuture {
     pub fn new(inner: Receiver<MessageResult>) -> Self {
         MessageFuture {
             inner: inner,
-            result: None
+            result: None,
+            cancel: None
+        }
+    }
+
+    /// Creates a MessageFuture which calls cancel when it is dropped.
+    pub fn with_cancel(inner: Receiver<MessageResult>, cancel: CancelReply) -> Self {
+        MessageFuture {
+            inner: inner,
+            result: None,
+            cancel: Some(cancel)
         }
     }
 
@@ -171,6 +244,63 @@ impl MessageFuture {
     }
 }
 
+impl Drop for MessageFuture {
+    fn drop(&mut self) {
+        if let Some(mut cancel) = self.cancel.take() {
+            cancel();
+        }
+    }
+}
+
+/// ReplyFuture is a std::future::Future for the reply to a message sent on
+/// a connection. It resolves to a DisconnectedError if the connection is
+/// closed before the reply arrives.
+#[cfg(feature = "async")]
+pub struct ReplyFuture {
+    inner: oneshot::Receiver<MessageResult>,
+    cancel: Option<CancelReply>
+}
+
+#[cfg(feature = "async")]
+impl ReplyFuture {
+    pub fn new(inner: oneshot::Receiver<MessageResult>) -> Self {
+        ReplyFuture {
+            inner: inner,
+            cancel: None
+        }
+    }
+
+    /// Creates a ReplyFuture which calls cancel when it is dropped.
+    pub fn with_cancel(inner: oneshot::Receiver<MessageResult>, cancel: CancelReply) -> Self {
+        ReplyFuture {
+            inner: inner,
+            cancel: Some(cancel)
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl Drop for ReplyFuture {
+    fn drop(&mut self) {
+        if let Some(mut cancel) = self.cancel.take() {
+            cancel();
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl Future for ReplyFuture {
+    type Output = MessageResult;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<MessageResult> {
+        match Pin::new(&mut self.get_mut().inner).poll(cx) {
+            Poll::Ready(Ok(result)) => Poll::Ready(result),
+            Poll::Ready(Err(_)) => Poll::Ready(Err(ReceiveError::DisconnectedError)),
+            Poll::Pending => Poll::Pending
+        }
+    }
+}
+
 /// Queue for inbound messages, sent directly to this stream.
 
 #[cfg(test)]
@@ -209,4 +339,44 @@ mod tests {
 
         assert_eq!(msg, make_ping("my_test"));
     }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn reply_future() {
+        use futures::channel::oneshot;
+        use futures::executor::block_on;
+
+        use super::ReplyFuture;
+
+        let (tx, rx) = oneshot::channel();
+        let fut = ReplyFuture::new(rx);
+
+        let t = thread::spawn(move || {
+            tx.send(Ok(make_ping("my_test"))).unwrap();
+        });
+
+        let msg = block_on(fut).expect("Should have a message");
+
+        t.join().unwrap();
+
+        assert_eq!(msg, make_ping("my_test"));
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn reply_future_disconnected() {
+        use futures::channel::oneshot;
+        use futures::executor::block_on;
+
+        use super::ReceiveError;
+        use super::ReplyFuture;
+
+        let (tx, rx) = oneshot::channel();
+        drop(tx);
+
+        match block_on(ReplyFuture::new(rx)) {
+            Err(ReceiveError::DisconnectedError) => (),
+            other => panic!("Expected a DisconnectedError, got {:?}", other)
+        }
+    }
 }
@@ -20,43 +20,97 @@ use zmq;
 use std::sync::{Arc, Mutex};
 use std::sync::mpsc::{Receiver, SyncSender, Sender,
                       sync_channel, channel,
-                      RecvTimeoutError};
+                      TryRecvError};
 use std::thread;
-use std::time::Duration;
-use std::collections::HashMap;
+use std::thread::JoinHandle;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::error::Error;
 
 use protobuf;
 use messages::validator::Message;
 use messages::validator::Message_MessageType;
 
+use messaging::curve::CurveSettings;
 use messaging::stream::*;
 
+#[cfg(feature = "async")]
+use std::future::Future;
+#[cfg(feature = "async")]
+use std::os::unix::io::RawFd;
+#[cfg(feature = "async")]
+use std::pin::Pin;
+#[cfg(feature = "async")]
+use std::task::{self, Poll};
+
+#[cfg(feature = "async")]
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+#[cfg(feature = "async")]
+use futures::channel::oneshot;
+#[cfg(feature = "async")]
+use futures::stream::StreamExt;
+#[cfg(feature = "async")]
+use tokio::io::Interest;
+#[cfg(feature = "async")]
+use tokio::io::unix::AsyncFd;
+#[cfg(feature = "async")]
+use tokio::runtime::Handle;
+
 /// A MessageConnection over ZMQ sockets
+#[derive(Clone)]
 pub struct ZmqMessageConnection {
     address: String,
     context: zmq::Context,
+    max_outstanding_replies: usize,
+    curve: Option<CurveSettings>,
 }
 
 const CHANNEL_BUFFER_SIZE: usize = 128;
 
+/// The default number of sent messages which may await replies at once on
+/// each connection.
+pub const DEFAULT_MAX_OUTSTANDING_REPLIES: usize = 1024;
+
 impl ZmqMessageConnection {
     /// Create a new ZmqMessageConnection
     pub fn new(address: &str) -> Self {
         ZmqMessageConnection {
             address: String::from(address),
             context: zmq::Context::new(),
+            max_outstanding_replies: DEFAULT_MAX_OUTSTANDING_REPLIES,
+            curve: None,
         }
     }
+
+    /// Encrypts the connection, and authenticates it to the validator, with
+    /// ZMQ CURVE. Applies to the senders created after it is set.
+    ///
+    /// # Arguments
+    ///
+    /// * curve - the validator's public key and this client's keypair
+    pub fn set_curve(&mut self, curve: CurveSettings) {
+        self.curve = Some(curve);
+    }
+
+    /// Sets the number of sent messages which may await replies at once on
+    /// the senders this connection creates. Sending another fails with a
+    /// QueueFullError.
+    ///
+    /// # Arguments
+    ///
+    /// * max_outstanding_replies - the most replies awaited at once
+    pub fn set_max_outstanding_replies(&mut self, max_outstanding_replies: usize) {
+        self.max_outstanding_replies = max_outstanding_replies;
+    }
 }
 
 impl MessageConnection<ZmqMessageSender> for ZmqMessageConnection {
     fn create(&self) -> (ZmqMessageSender, MessageReceiver) {
         // Create the channel for request messages (i.e. non-reply messages)
         let (request_tx, request_rx) = sync_channel(CHANNEL_BUFFER_SIZE);
-        let router = InboundRouter::new(request_tx);
+        let router = InboundRouter::new(
+            InboundSender::Blocking(request_tx), self.max_outstanding_replies);
         let mut sender = ZmqMessageSender::new(
-            self.context.clone(), self.address.clone(), router);
+            self.context.clone(), self.address.clone(), self.curve.clone(), router);
 
         sender.start();
 
@@ -64,48 +118,124 @@ impl MessageConnection<ZmqMessageSender> for ZmqMessageConnection {
     }
 }
 
+#[cfg(feature = "async")]
+impl AsyncMessageConnection<ZmqAsyncMessageSender> for ZmqMessageConnection {
+    /// Creates a sender whose connection is driven by the tokio runtime it
+    /// is called from. Outside of a runtime, the receiver is given a
+    /// SocketError instead.
+    fn create_async(&self) -> (ZmqAsyncMessageSender, AsyncMessageReceiver) {
+        // Create the stream for request messages (i.e. non-reply messages)
+        let (request_tx, request_rx) = unbounded();
+        let router = InboundRouter::new(
+            InboundSender::Async(request_tx), self.max_outstanding_replies);
+        let sender = ZmqAsyncMessageSender::start(
+            &self.context, &self.address, self.curve.as_ref(), router);
+
+        (sender, request_rx)
+    }
+}
+
 #[derive(Debug)]
 enum SocketCommand {
     Send(Message),
     Shutdown
 }
 
+/// Passes commands to the stream thread. Each command is followed by a
+/// frame on an inproc PAIR socket in the stream's poll set, so that the
+/// stream wakes as soon as there is something to send.
+#[derive(Clone)]
+struct OutboundSender {
+    commands: SyncSender<SocketCommand>,
+    wake_socket: Arc<Mutex<zmq::Socket>>,
+}
+
+impl OutboundSender {
+    fn send(&self, command: SocketCommand) -> Result<(), SocketCommand> {
+        self.commands.send(command).map_err(|err| err.0)?;
+        // If the wake socket is full, the stream has yet to wake for the
+        // frames already sent, and will find this command when it does.
+        if let Err(err) = self.wake_socket.lock().unwrap().send(&[], zmq::DONTWAIT) {
+            trace!("Unable to wake stream: {}", err.description());
+        }
+        Ok(())
+    }
+}
+
 #[derive(Clone)]
 pub struct ZmqMessageSender {
     context: zmq::Context,
     address: String,
+    curve: Option<CurveSettings>,
     inbound_router: InboundRouter,
-    outbound_sender: Option<SyncSender<SocketCommand>>,
+    outbound_sender: Option<OutboundSender>,
+    stream_thread: Arc<Mutex<Option<JoinHandle<()>>>>,
 }
 
 impl ZmqMessageSender {
 
-    fn new(ctx: zmq::Context, address: String, router: InboundRouter) -> Self {
+    fn new(ctx: zmq::Context, address: String, curve: Option<CurveSettings>,
+           router: InboundRouter)
+        -> Self
+    {
         ZmqMessageSender {
            context: ctx,
            address: address,
+           curve: curve,
            inbound_router: router,
-           outbound_sender: None
+           outbound_sender: None,
+           stream_thread: Arc::new(Mutex::new(None))
         }
     }
 
+    /// Returns the counts of the replies this sender's connection is
+    /// waiting for. The connection is shared by all clones of the sender.
+    pub fn reply_metrics(&self) -> ReplyMetrics {
+        self.inbound_router.metrics()
+    }
+
     /// Start the message stream instance
     fn start(&mut self) {
+        let (wake_send, wake_recv) = match wake_sockets(&self.context) {
+            Ok(sockets) => sockets,
+            Err(err) => {
+                error!("Unable to start stream: {}", err);
+                self.inbound_router.route(Err(err));
+                return;
+            }
+        };
         let (outbound_send, outbound_recv) = sync_channel(CHANNEL_BUFFER_SIZE);
-        self.outbound_sender = Some(outbound_send);
+        self.outbound_sender = Some(OutboundSender {
+            commands: outbound_send,
+            wake_socket: Arc::new(Mutex::new(wake_send)),
+        });
 
         let ctx = self.context.clone();
         let address = self.address.clone();
+        let curve = self.curve.clone();
         let inbound_router = self.inbound_router.clone();
-        thread::spawn(move || {
-            let mut inner_stream = SendReceiveStream::new(
+        let handle = thread::spawn(move || {
+            let mut router = inbound_router.clone();
+            let result = SendReceiveStream::new(
                 ctx,
                 &address,
+                curve.as_ref(),
                 outbound_recv,
+                wake_recv,
                 inbound_router,
-            );
-            inner_stream.run();
+            ).and_then(|mut inner_stream| inner_stream.run());
+
+            // The stream, and with it the outbound channel, has been dropped,
+            // so no message can be sent after the pending replies are failed.
+            match result {
+                Ok(()) => router.fail_all(ReceiveError::DisconnectedError),
+                Err(err) => {
+                    error!("Stream exited: {}", err);
+                    router.route(Err(err));
+                }
+            }
         });
+        *self.stream_thread.lock().unwrap() = Some(handle);
     }
 }
 
@@ -122,12 +252,15 @@ impl MessageSender for ZmqMessageSender {
             msg.set_correlation_id(String::from(correlation_id));
             msg.set_content(Vec::from(contents));
 
-            let future = MessageFuture::new(self.inbound_router.expect_reply(
-                    String::from(correlation_id)));
+            let future = self.inbound_router.expect_reply(String::from(correlation_id))?;
 
-            sender.send(SocketCommand::Send(msg)).unwrap();
-
-            Ok(future)
+            match sender.send(SocketCommand::Send(msg)) {
+                Ok(_) => Ok(future),
+                Err(_) => {
+                    self.inbound_router.fail(correlation_id, ReceiveError::DisconnectedError);
+                    Err(SendError::DisconnectedError)
+                }
+            }
         } else {
             Err(SendError::DisconnectedError)
         }
@@ -143,15 +276,20 @@ impl MessageSender for ZmqMessageSender {
             msg.set_correlation_id(String::from(correlation_id));
             msg.set_content(Vec::from(contents));
 
+            // The outbound channel only closes once the stream thread has
+            // exited
             match sender.send(SocketCommand::Send(msg)) {
                 Ok(_) => Ok(()),
-                Err(_) => Err(SendError::UnknownError)
+                Err(_) => Err(SendError::DisconnectedError)
             }
         } else {
             Err(SendError::DisconnectedError)
         }
     }
 
+    /// Closes the connection, waiting for the stream thread to exit. The
+    /// stream is shared by all clones of this sender, so they are closed
+    /// as well.
     fn close(&mut self) {
         if let Some(ref sender) = self.outbound_sender.take() {
             match sender.send(SocketCommand::Shutdown){
@@ -159,137 +297,1039 @@ impl MessageSender for ZmqMessageSender {
                 Err(_) => info!("Sender has already closed.")
             }
         }
+
+        let handle = self.stream_thread.lock().unwrap().take();
+        if let Some(handle) = handle {
+            if let Err(_) = handle.join() {
+                error!("Stream thread panicked");
+            }
+        }
+    }
+}
+
+/// Where the InboundRouter delivers messages that are not replies.
+#[derive(Clone)]
+enum InboundSender {
+    Blocking(SyncSender<MessageResult>),
+    #[cfg(feature = "async")]
+    Async(UnboundedSender<MessageResult>),
+}
+
+impl InboundSender {
+    fn send(&self, message_result: MessageResult) -> Result<(), MessageResult> {
+        match *self {
+            InboundSender::Blocking(ref sender) =>
+                sender.send(message_result).map_err(|err| err.0),
+            #[cfg(feature = "async")]
+            InboundSender::Async(ref sender) =>
+                sender.unbounded_send(message_result).map_err(|err| err.into_inner()),
+        }
+    }
+}
+
+/// Where the InboundRouter delivers the reply to a sent message.
+enum ReplySender {
+    Blocking(Sender<MessageResult>),
+    #[cfg(feature = "async")]
+    Async(oneshot::Sender<MessageResult>),
+}
+
+impl ReplySender {
+    fn send(self, message_result: MessageResult) -> Result<(), MessageResult> {
+        match self {
+            ReplySender::Blocking(sender) =>
+                sender.send(message_result).map_err(|err| err.0),
+            #[cfg(feature = "async")]
+            ReplySender::Async(sender) => sender.send(message_result),
+        }
+    }
+}
+
+/// Counts of the replies a connection is waiting for.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ReplyMetrics {
+    /// Messages sent whose replies have not yet arrived
+    pub outstanding: usize,
+    /// The most replies awaited at once
+    pub peak_outstanding: usize,
+    /// Messages whose futures were dropped before their replies arrived
+    pub abandoned: u64,
+    /// Replies which arrived after their futures were dropped, and were
+    /// discarded
+    pub late_replies: u64,
+    /// Messages not sent because too many replies were already awaited
+    pub rejected: u64,
+}
+
+/// The senders for the replies a connection is waiting for, by correlation
+/// id.
+struct ExpectedReplies {
+    senders: HashMap<String, ReplySender>,
+    max_outstanding: usize,
+    // The correlation ids of the most recently abandoned messages, so that
+    // their late replies are not mistaken for requests from the validator
+    abandoned_ids: HashSet<String>,
+    abandoned_order: VecDeque<String>,
+    metrics: ReplyMetrics,
+}
+
+impl ExpectedReplies {
+    fn insert(&mut self, correlation_id: String, sender: ReplySender) -> Result<(), SendError> {
+        if self.senders.len() >= self.max_outstanding {
+            self.metrics.rejected += 1;
+            warn!("{} replies are already outstanding", self.senders.len());
+            return Err(SendError::QueueFullError);
+        }
+        self.senders.insert(correlation_id, sender);
+        if self.senders.len() > self.metrics.peak_outstanding {
+            self.metrics.peak_outstanding = self.senders.len();
+        }
+        Ok(())
+    }
+
+    fn cancel(&mut self, correlation_id: &str) {
+        if self.senders.remove(correlation_id).is_some() {
+            self.metrics.abandoned += 1;
+            self.abandoned_ids.insert(String::from(correlation_id));
+            self.abandoned_order.push_back(String::from(correlation_id));
+            if self.abandoned_order.len() > self.max_outstanding {
+                if let Some(oldest) = self.abandoned_order.pop_front() {
+                    self.abandoned_ids.remove(&oldest);
+                }
+            }
+        }
+    }
+
+    /// Returns true, and counts the reply as late, if the message with the
+    /// given correlation id was abandoned.
+    fn discard_late(&mut self, correlation_id: &str) -> bool {
+        if self.abandoned_ids.remove(correlation_id) {
+            self.metrics.late_replies += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn clear(&mut self) -> HashMap<String, ReplySender> {
+        self.abandoned_ids.clear();
+        self.abandoned_order.clear();
+        self.senders.drain().collect()
     }
 }
 
 #[derive(Clone)]
 struct InboundRouter {
-    inbound_tx: SyncSender<MessageResult>,
-    expected_replies: Arc<Mutex<HashMap<String, Sender<MessageResult>>>>,
+    inbound_tx: InboundSender,
+    expected_replies: Arc<Mutex<ExpectedReplies>>,
 }
 
 impl InboundRouter {
-    fn new(inbound_tx: SyncSender<MessageResult>) -> Self {
+    fn new(inbound_tx: InboundSender, max_outstanding: usize) -> Self {
         InboundRouter {
             inbound_tx: inbound_tx,
-            expected_replies: Arc::new(Mutex::new(HashMap::new()))
+            expected_replies: Arc::new(Mutex::new(ExpectedReplies {
+                senders: HashMap::new(),
+                max_outstanding: max_outstanding,
+                abandoned_ids: HashSet::new(),
+                abandoned_order: VecDeque::new(),
+                metrics: ReplyMetrics::default(),
+            }))
         }
     }
+
     fn route(&mut self, message_result: MessageResult) {
         match message_result  {
             Ok(message) => {
-                let mut expected_replies = self.expected_replies.lock().unwrap();
-                match expected_replies.remove(message.get_correlation_id()) {
-                    Some(sender) => sender.send(Ok(message)).unwrap(),
-                    None => self.inbound_tx.send(Ok(message)).unwrap()
+                let sender = {
+                    let mut expected_replies = self.expected_replies.lock().unwrap();
+                    let correlation_id = message.get_correlation_id();
+                    let sender = expected_replies.senders.remove(correlation_id);
+                    if sender.is_none() && expected_replies.discard_late(correlation_id) {
+                        debug!("Dropping reply {} which arrived after its future was dropped",
+                               correlation_id);
+                        return;
+                    }
+                    sender
+                };
+                match sender {
+                    Some(sender) => {
+                        // The future may be dropped after the reply arrives,
+                        // but before it is delivered.
+                        if let Err(_) = sender.send(Ok(message)) {
+                            debug!("Reply arrived after its future was dropped");
+                            self.expected_replies.lock().unwrap().metrics.late_replies += 1;
+                        }
+                    }
+                    None => self.forward(Ok(message))
                 }
             }
             Err(ReceiveError::DisconnectedError) => {
-                let mut expected_replies = self.expected_replies.lock().unwrap();
-                for (_, sender) in expected_replies.iter_mut(){
-                    sender.send(Err(ReceiveError::DisconnectedError)).unwrap();
-                }
-                self.inbound_tx.send(Err(ReceiveError::DisconnectedError)).unwrap();
+                self.fail_all(ReceiveError::DisconnectedError);
+                self.forward(Err(ReceiveError::DisconnectedError));
+            }
+            Err(ReceiveError::SocketError(msg)) => {
+                self.fail_all(ReceiveError::SocketError(msg.clone()));
+                self.forward(Err(ReceiveError::SocketError(msg)));
             }
+            // The reply to a frame which cannot be parsed cannot be
+            // identified, so only the inbound channel is told
+            Err(ReceiveError::ParseError(msg)) => self.forward(Err(ReceiveError::ParseError(msg))),
             Err(err) => error!("Error: {}", err.description())
         }
     }
 
-    fn expect_reply(&mut self, correlation_id: String) -> Receiver<MessageResult> {
+    /// Delivers a message which is not a reply to the inbound channel.
+    fn forward(&self, message_result: MessageResult) {
+        if let Err(_) = self.inbound_tx.send(message_result) {
+            debug!("Inbound channel is closed; dropping message");
+        }
+    }
+
+    /// Stops waiting for the reply with the given correlation id, giving its
+    /// future the error instead.
+    fn fail(&mut self, correlation_id: &str, err: ReceiveError) {
+        let sender = self.expected_replies.lock().unwrap().senders.remove(correlation_id);
+        if let Some(sender) = sender {
+            // Only futures which are still waiting need be told
+            let _ = sender.send(Err(err));
+        }
+    }
+
+    /// Stops waiting for all replies, giving each of their futures the error
+    /// instead.
+    fn fail_all(&mut self, err: ReceiveError) {
+        let senders = self.expected_replies.lock().unwrap().clear();
+        for (_, sender) in senders {
+            // Only futures which are still waiting need be told
+            let _ = sender.send(Err(err.clone()));
+        }
+    }
+
+    fn metrics(&self) -> ReplyMetrics {
+        let expected_replies = self.expected_replies.lock().unwrap();
+        ReplyMetrics {
+            outstanding: expected_replies.senders.len(),
+            ..expected_replies.metrics
+        }
+    }
+
+    /// Returns a function which stops waiting for the reply with the given
+    /// correlation id.
+    fn canceller(&self, correlation_id: String) -> CancelReply {
+        let expected_replies = self.expected_replies.clone();
+        Box::new(move || {
+            expected_replies.lock().unwrap().cancel(&correlation_id);
+        })
+    }
+
+    fn expect_reply(&mut self, correlation_id: String) -> Result<MessageFuture, SendError> {
         let (expect_tx, expect_rx) = channel();
-        let mut expected_replies = self.expected_replies.lock().unwrap();
-        expected_replies.insert(correlation_id, expect_tx);
+        self.expected_replies.lock().unwrap()
+            .insert(correlation_id.clone(), ReplySender::Blocking(expect_tx))?;
+
+        Ok(MessageFuture::with_cancel(expect_rx, self.canceller(correlation_id)))
+    }
+
+    #[cfg(feature = "async")]
+    fn expect_async_reply(&mut self, correlation_id: String) -> Result<ReplyFuture, SendError> {
+        let (expect_tx, expect_rx) = oneshot::channel();
+        self.expected_replies.lock().unwrap()
+            .insert(correlation_id.clone(), ReplySender::Async(expect_tx))?;
 
-        expect_rx
+        Ok(ReplyFuture::with_cancel(expect_rx, self.canceller(correlation_id)))
     }
 }
 
+/// Creates a connected pair of inproc sockets, the first for waking the
+/// stream and the second for the stream to poll.
+fn wake_sockets(context: &zmq::Context) -> Result<(zmq::Socket, zmq::Socket), ReceiveError> {
+    let address = format!("inproc://wake-{}", uuid::Uuid::new_v4());
+    let wake_recv = context.socket(zmq::PAIR).map_err(socket_error)?;
+    wake_recv.bind(&address).map_err(socket_error)?;
+    let wake_send = context.socket(zmq::PAIR).map_err(socket_error)?;
+    wake_send.connect(&address).map_err(socket_error)?;
+    Ok((wake_send, wake_recv))
+}
+
 /// Internal stream, guarding a zmq socket.
 struct SendReceiveStream {
     address: String,
     socket: zmq::Socket,
     outbound_recv: Receiver<SocketCommand>,
+    wake_socket: zmq::Socket,
     inbound_router: InboundRouter,
+    monitor_address: String,
     monitor_socket: zmq::Socket
 }
 
-const POLL_TIMEOUT: i64 = 10;
+/// The longest the stream waits without an event, after which it checks
+/// whether all the senders have been dropped.
+const IDLE_TIMEOUT: i64 = 1000;
+
+fn socket_error(err: zmq::Error) -> ReceiveError {
+    ReceiveError::SocketError(String::from(err.description()))
+}
+
+/// Creates the DEALER socket for a connection, with its identity, CURVE
+/// settings and a monitor for disconnects. Returns the socket, the monitor's
+/// PAIR socket and the monitor's endpoint; neither is connected yet.
+fn open_sockets(context: &zmq::Context, curve: Option<&CurveSettings>)
+    -> Result<(zmq::Socket, zmq::Socket, String), ReceiveError>
+{
+    // Connections made from the same context each need their own
+    // monitor endpoint
+    let monitor_address = format!("inproc://monitor-{}", uuid::Uuid::new_v4());
+    let socket = context.socket(zmq::DEALER).map_err(socket_error)?;
+    // Without the monitor, a lost connection would go unnoticed
+    socket.monitor(&monitor_address, zmq::SocketEvent::DISCONNECTED as i32)
+        .map_err(socket_error)?;
+    let monitor_socket = context.socket(zmq::PAIR).map_err(socket_error)?;
+
+    // Without an identity set, zmq assigns one
+    if let Some(identity) = uuid::Uuid::new(uuid::UuidVersion::Random) {
+        socket.set_identity(identity.as_bytes()).map_err(socket_error)?;
+    }
+    if let Some(curve) = curve {
+        curve.apply(&socket).map_err(socket_error)?;
+    }
+
+    Ok((socket, monitor_socket, monitor_address))
+}
+
+/// Parses the message in the last of the received frames, and routes it.
+fn route_frames(inbound_router: &mut InboundRouter, mut received_parts: Vec<Vec<u8>>) {
+    // Grab the last part, which should contain our message
+    if let Some(received_bytes) = received_parts.pop() {
+        trace!("Received {} bytes", received_bytes.len());
+        if received_bytes.len() != 0 {
+            match protobuf::parse_from_bytes::<Message>(&received_bytes) {
+                Ok(message) => inbound_router.route(Ok(message)),
+                Err(err) => {
+                    warn!("Unable to parse received message: {}", err.description());
+                    inbound_router.route(
+                        Err(ReceiveError::ParseError(String::from(err.description()))));
+                }
+            }
+        }
+    } else {
+        debug!("Empty frame received.");
+    }
+}
+
+/// Serializes an outbound message, failing its future if it cannot be.
+fn serialize(inbound_router: &mut InboundRouter, msg: &Message) -> Option<Vec<u8>> {
+    match protobuf::Message::write_to_bytes(msg) {
+        Ok(message_bytes) => Some(message_bytes),
+        Err(err) => {
+            error!("Unable to serialize message: {}", err.description());
+            inbound_router.fail(
+                msg.get_correlation_id(),
+                ReceiveError::ParseError(String::from(err.description())));
+            None
+        }
+    }
+}
 
 impl SendReceiveStream {
     fn new(context: zmq::Context, address: &str,
+           curve: Option<&CurveSettings>,
            outbound_recv: Receiver<SocketCommand>,
+           wake_socket: zmq::Socket,
            inbound_router: InboundRouter)
-        -> Self
+        -> Result<Self, ReceiveError>
     {
-        let socket = context.socket(zmq::DEALER).unwrap();
-        socket.monitor("inproc://monitor-socket", zmq::SocketEvent::DISCONNECTED as i32).is_ok();
-        let monitor_socket = context.socket(zmq::PAIR).unwrap();
+        let (socket, monitor_socket, monitor_address) = open_sockets(&context, curve)?;
 
-        let identity = uuid::Uuid::new(uuid::UuidVersion::Random).unwrap();
-        socket.set_identity(identity.as_bytes()).unwrap();
-
-        SendReceiveStream {
+        Ok(SendReceiveStream {
             address: String::from(address),
             socket: socket,
             outbound_recv: outbound_recv,
+            wake_socket: wake_socket,
             inbound_router: inbound_router,
+            monitor_address: monitor_address,
             monitor_socket: monitor_socket
+        })
+    }
+
+    /// Runs the stream until it is shut down, returning Ok, or until the
+    /// connection is lost or the socket fails, returning the error to give
+    /// the inbound channel and pending futures.
+    fn run(&mut self) -> Result<(), ReceiveError> {
+        self.socket.connect(&self.address).map_err(socket_error)?;
+        if let Err(err) = self.monitor_socket.connect(&self.monitor_address) {
+            let _ = self.socket.disconnect(&self.address);
+            return Err(socket_error(err));
+        }
+
+        let result = self.poll();
+
+        debug!("Exited stream");
+        if let Err(err) = self.socket.disconnect(&self.address) {
+            debug!("Unable to disconnect socket: {}", err.description());
         }
+        if let Err(err) = self.monitor_socket.disconnect(&self.monitor_address) {
+            debug!("Unable to disconnect monitor socket: {}", err.description());
+        }
+        result
     }
 
-    fn run(&mut self) {
-        self.socket.connect(&self.address).unwrap();
-        self.monitor_socket.connect("inproc://monitor-socket").unwrap();
+    /// Waits on the socket, the monitor and the wake socket together, so
+    /// that each inbound message, disconnect or outbound command is handled
+    /// as soon as it arrives.
+    fn poll(&mut self) -> Result<(), ReceiveError> {
         loop {
-            let mut poll_items = [self.socket.as_poll_item(zmq::POLLIN), self.monitor_socket.as_poll_item(zmq::POLLIN)];
-            zmq::poll(&mut poll_items, POLL_TIMEOUT).unwrap();
+            let mut poll_items = [
+                self.socket.as_poll_item(zmq::POLLIN),
+                self.monitor_socket.as_poll_item(zmq::POLLIN),
+                self.wake_socket.as_poll_item(zmq::POLLIN),
+            ];
+            zmq::poll(&mut poll_items, IDLE_TIMEOUT).map_err(socket_error)?;
             if poll_items[0].is_readable() {
                 trace!("Readable!");
-                let mut received_parts = self.socket.recv_multipart(0).unwrap();
-
-                // Grab the last part, which should contain our message
-                if let Some(received_bytes) = received_parts.pop() {
-                    trace!("Received {} bytes", received_bytes.len());
-                    if received_bytes.len() != 0 {
-                        let message = protobuf::parse_from_bytes(&received_bytes).unwrap();
-                        self.inbound_router.route(Ok(message));
-                    }
-                } else {
-                    debug!("Empty frame received.");
-                }
+                let received_parts = self.socket.recv_multipart(0).map_err(socket_error)?;
+                route_frames(&mut self.inbound_router, received_parts);
             }
             if poll_items[1].is_readable(){
-                self.monitor_socket.recv_multipart(0).unwrap();
-                let message_result = Err(ReceiveError::DisconnectedError);
+                if let Err(err) = self.monitor_socket.recv_multipart(0) {
+                    debug!("Unable to read monitor event: {}", err.description());
+                }
                 info!("Received Disconnect");
-                self.inbound_router.route(message_result);
-                break;
+                return Err(ReceiveError::DisconnectedError);
+            }
+            if poll_items[2].is_readable() {
+                // The wake frames are drained before the commands, so that
+                // a command sent after the commands are drained leaves a
+                // frame to wake the next poll.
+                while let Ok(_) = self.wake_socket.recv_bytes(zmq::DONTWAIT) {}
+            }
 
+            loop {
+                match self.outbound_recv.try_recv() {
+                    Ok(SocketCommand::Send(msg)) => {
+                        let message_bytes = match serialize(&mut self.inbound_router, &msg) {
+                            Some(message_bytes) => message_bytes,
+                            None => continue
+                        };
+                        trace!("Sending {} bytes", message_bytes.len());
+                        self.socket.send(&message_bytes, 0).map_err(socket_error)?;
+                    }
+                    Ok(SocketCommand::Shutdown) => {
+                        trace!("Shutdown Signal Received");
+                        return Ok(());
+                    }
+                    Err(TryRecvError::Disconnected) => {
+                        debug!("Disconnected outbound channel");
+                        return Ok(());
+                    }
+                    Err(TryRecvError::Empty) => break
+                }
             }
+        }
+    }
+}
 
-            match self.outbound_recv.recv_timeout(
-                Duration::from_millis(POLL_TIMEOUT as u64))
-            {
-                Ok(SocketCommand::Send(msg)) => {
-                    let message_bytes = protobuf::Message::write_to_bytes(&msg).unwrap();
-                    trace!("Sending {} bytes", message_bytes.len());
-                    self.socket.send(&message_bytes, 0).unwrap();
+/// A MessageSender whose connection is driven by a tokio runtime. The
+/// sockets are registered with the runtime's reactor, so the connection
+/// needs no thread of its own.
+#[cfg(feature = "async")]
+#[derive(Clone)]
+pub struct ZmqAsyncMessageSender {
+    inbound_router: InboundRouter,
+    outbound_sender: Option<UnboundedSender<SocketCommand>>,
+}
+
+#[cfg(feature = "async")]
+impl ZmqAsyncMessageSender {
+    /// Opens the sockets and spawns the stream driving them onto the
+    /// current tokio runtime. If either fails, the error is routed to the
+    /// inbound channel, and the sender is left disconnected.
+    fn start(context: &zmq::Context, address: &str, curve: Option<&CurveSettings>,
+             mut router: InboundRouter)
+        -> Self
+    {
+        let (outbound_send, outbound_recv) = unbounded();
+        let result = Handle::try_current()
+            .map_err(|err| ReceiveError::SocketError(format!("{}", err)))
+            .and_then(|handle| {
+                let stream = AsyncSendReceiveStream::new(
+                    context, address, curve, outbound_recv, router.clone())?;
+                handle.spawn(stream);
+                Ok(())
+            });
+
+        match result {
+            Ok(()) => ZmqAsyncMessageSender {
+                inbound_router: router,
+                outbound_sender: Some(outbound_send),
+            },
+            Err(err) => {
+                error!("Unable to start stream: {}", err);
+                router.route(Err(err));
+                ZmqAsyncMessageSender {
+                    inbound_router: router,
+                    outbound_sender: None,
                 }
-                Ok(SocketCommand::Shutdown) => {
-                    trace!("Shutdown Signal Received");
-                    break;
+            }
+        }
+    }
+
+    /// Returns the counts of the replies this sender's connection is
+    /// waiting for. The connection is shared by all clones of the sender.
+    pub fn reply_metrics(&self) -> ReplyMetrics {
+        self.inbound_router.metrics()
+    }
+
+    fn send_command(&mut self, msg: Message) -> Result<(), SendError> {
+        match self.outbound_sender {
+            // The outbound channel only closes once the stream has exited
+            Some(ref sender) => sender.unbounded_send(SocketCommand::Send(msg))
+                .map_err(|_| SendError::DisconnectedError),
+            None => Err(SendError::DisconnectedError)
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl MessageSender for ZmqAsyncMessageSender {
+    fn send(&mut self, destination: Message_MessageType, correlation_id: &str,
+            contents: &[u8])
+        -> Result<MessageFuture, SendError>
+    {
+        if self.outbound_sender.is_none() {
+            return Err(SendError::DisconnectedError);
+        }
+        let future = self.inbound_router.expect_reply(String::from(correlation_id))?;
+        match self.send_command(make_message(destination, correlation_id, contents)) {
+            Ok(()) => Ok(future),
+            Err(err) => {
+                self.inbound_router.fail(correlation_id, ReceiveError::DisconnectedError);
+                Err(err)
+            }
+        }
+    }
+
+    fn reply(&mut self, destination: Message_MessageType, correlation_id: &str,
+             contents: &[u8])
+        -> Result<(), SendError>
+    {
+        self.send_command(make_message(destination, correlation_id, contents))
+    }
+
+    /// Tells the stream to shut down once the messages already sent have
+    /// been handed to the socket. Unlike ZmqMessageSender, this does not
+    /// wait for the stream to exit, so it may be called from a runtime
+    /// thread. The stream is shared by all clones of this sender, so they
+    /// are closed as well.
+    fn close(&mut self) {
+        if let Some(sender) = self.outbound_sender.take() {
+            if let Err(_) = sender.unbounded_send(SocketCommand::Shutdown) {
+                info!("Sender has already closed.");
+            }
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl AsyncMessageSender for ZmqAsyncMessageSender {
+    fn send_async(&mut self, destination: Message_MessageType, correlation_id: &str,
+                  contents: &[u8])
+        -> Result<ReplyFuture, SendError>
+    {
+        if self.outbound_sender.is_none() {
+            return Err(SendError::DisconnectedError);
+        }
+        let future = self.inbound_router.expect_async_reply(String::from(correlation_id))?;
+        match self.send_command(make_message(destination, correlation_id, contents)) {
+            Ok(()) => Ok(future),
+            Err(err) => {
+                self.inbound_router.fail(correlation_id, ReceiveError::DisconnectedError);
+                Err(err)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+fn make_message(destination: Message_MessageType, correlation_id: &str, contents: &[u8])
+    -> Message
+{
+    let mut msg = Message::new();
+    msg.set_message_type(destination);
+    msg.set_correlation_id(String::from(correlation_id));
+    msg.set_content(Vec::from(contents));
+    msg
+}
+
+/// The most messages the async stream receives before yielding to the other
+/// tasks on its runtime.
+#[cfg(feature = "async")]
+const MAX_RECEIVES_PER_POLL: usize = 64;
+
+/// Internal stream, guarding a zmq socket, which is polled as a future on
+/// a tokio runtime. ZMQ_FD is registered with the reactor for each socket,
+/// and ZMQ_EVENTS read whenever it signals.
+#[cfg(feature = "async")]
+struct AsyncSendReceiveStream {
+    // The registrations are declared before the sockets, so that they are
+    // dropped before the sockets close their descriptors
+    socket_fd: AsyncFd<RawFd>,
+    monitor_fd: AsyncFd<RawFd>,
+    address: String,
+    socket: zmq::Socket,
+    monitor_address: String,
+    monitor_socket: zmq::Socket,
+    outbound_recv: UnboundedReceiver<SocketCommand>,
+    // A serialized message the socket could not yet take
+    unsent: Option<Vec<u8>>,
+    inbound_router: InboundRouter,
+}
+
+#[cfg(feature = "async")]
+fn io_error(err: ::std::io::Error) -> ReceiveError {
+    ReceiveError::SocketError(format!("{}", err))
+}
+
+#[cfg(feature = "async")]
+impl AsyncSendReceiveStream {
+    /// Opens and connects the sockets, and registers them with the current
+    /// runtime's reactor.
+    fn new(context: &zmq::Context, address: &str,
+           curve: Option<&CurveSettings>,
+           outbound_recv: UnboundedReceiver<SocketCommand>,
+           inbound_router: InboundRouter)
+        -> Result<Self, ReceiveError>
+    {
+        let (socket, monitor_socket, monitor_address) = open_sockets(context, curve)?;
+
+        // ZMQ_FD only ever signals readable, whichever events are pending
+        let socket_fd = AsyncFd::with_interest(
+            socket.get_fd().map_err(socket_error)?, Interest::READABLE).map_err(io_error)?;
+        let monitor_fd = AsyncFd::with_interest(
+            monitor_socket.get_fd().map_err(socket_error)?, Interest::READABLE)
+            .map_err(io_error)?;
+
+        socket.connect(address).map_err(socket_error)?;
+        if let Err(err) = monitor_socket.connect(&monitor_address) {
+            let _ = socket.disconnect(address);
+            return Err(socket_error(err));
+        }
+
+        Ok(AsyncSendReceiveStream {
+            socket_fd: socket_fd,
+            monitor_fd: monitor_fd,
+            address: String::from(address),
+            socket: socket,
+            monitor_address: monitor_address,
+            monitor_socket: monitor_socket,
+            outbound_recv: outbound_recv,
+            unsent: None,
+            inbound_router: inbound_router,
+        })
+    }
+
+    /// Hands the socket the outbound messages until it can take no more, or
+    /// none are waiting. Returns Ready once the stream has been shut down.
+    fn poll_outbound(&mut self, cx: &mut task::Context) -> Poll<Result<(), ReceiveError>> {
+        loop {
+            if self.unsent.is_none() {
+                match self.outbound_recv.poll_next_unpin(cx) {
+                    Poll::Ready(Some(SocketCommand::Send(msg))) => {
+                        self.unsent = serialize(&mut self.inbound_router, &msg);
+                    }
+                    Poll::Ready(Some(SocketCommand::Shutdown)) => {
+                        trace!("Shutdown Signal Received");
+                        return Poll::Ready(Ok(()));
+                    }
+                    Poll::Ready(None) => {
+                        debug!("Disconnected outbound channel");
+                        return Poll::Ready(Ok(()));
+                    }
+                    Poll::Pending => return Poll::Pending
                 }
-                Err(RecvTimeoutError::Disconnected) => {
-                    debug!("Disconnected outbound channel");
-                    break;
+            }
+            if let Some(message_bytes) = self.unsent.take() {
+                match self.socket.send(&message_bytes, zmq::DONTWAIT) {
+                    Ok(()) => trace!("Sent {} bytes", message_bytes.len()),
+                    Err(zmq::Error::EAGAIN) => {
+                        // The socket signals once it can take the message
+                        self.unsent = Some(message_bytes);
+                        return Poll::Pending;
+                    }
+                    Err(err) => return Poll::Ready(Err(socket_error(err)))
                 }
-                _ => continue
+            }
+        }
+    }
 
+    /// Runs the stream until it is shut down, returning Ok, or until the
+    /// connection is lost or the socket fails, returning the error to give
+    /// the inbound channel and pending futures.
+    fn poll_stream(&mut self, cx: &mut task::Context) -> Poll<Result<(), ReceiveError>> {
+        let mut received = 0;
+        loop {
+            // ZMQ_FD is edge triggered, and only says that ZMQ_EVENTS may
+            // have changed, so the events are read again before each wait
+            let monitor_events = self.monitor_socket.get_events().map_err(socket_error)?;
+            if monitor_events & zmq::POLLIN != 0 {
+                if let Err(err) = self.monitor_socket.recv_multipart(zmq::DONTWAIT) {
+                    debug!("Unable to read monitor event: {}", err.description());
+                }
+                info!("Received Disconnect");
+                return Poll::Ready(Err(ReceiveError::DisconnectedError));
+            }
+
+            if let Poll::Ready(result) = self.poll_outbound(cx) {
+                return Poll::Ready(result);
+            }
+
+            let events = self.socket.get_events().map_err(socket_error)?;
+            if events & zmq::POLLIN != 0 {
+                match self.socket.recv_multipart(zmq::DONTWAIT) {
+                    Ok(received_parts) => route_frames(&mut self.inbound_router, received_parts),
+                    Err(zmq::Error::EAGAIN) => (),
+                    Err(err) => return Poll::Ready(Err(socket_error(err)))
+                }
+                received += 1;
+                if received >= MAX_RECEIVES_PER_POLL {
+                    cx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+                continue;
+            }
+            if events & zmq::POLLOUT != 0 && self.unsent.is_some() {
+                continue;
+            }
+
+            // Readiness is cleared before the events are read again, so that
+            // a signal arriving in between is not lost
+            let mut signalled = false;
+            for fd in &[&self.monitor_fd, &self.socket_fd] {
+                match fd.poll_read_ready(cx) {
+                    Poll::Ready(Ok(mut guard)) => {
+                        guard.clear_ready();
+                        signalled = true;
+                    }
+                    Poll::Ready(Err(err)) => return Poll::Ready(Err(io_error(err))),
+                    Poll::Pending => ()
+                }
+            }
+            if !signalled {
+                return Poll::Pending;
             }
         }
+    }
+}
+
+#[cfg(feature = "async")]
+impl Future for AsyncSendReceiveStream {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut task::Context) -> Poll<()> {
+        let this = self.get_mut();
+        let result = match this.poll_stream(cx) {
+            Poll::Ready(result) => result,
+            Poll::Pending => return Poll::Pending
+        };
 
         debug!("Exited stream");
-        self.socket.disconnect(&self.address).unwrap();
-        self.monitor_socket.disconnect("inproc://monitor-socket").unwrap();
+        // The outbound channel is closed, so no message can be sent after
+        // the pending replies are failed.
+        this.outbound_recv.close();
+        match result {
+            Ok(()) => this.inbound_router.fail_all(ReceiveError::DisconnectedError),
+            Err(err) => {
+                error!("Stream exited: {}", err);
+                this.inbound_router.route(Err(err));
+            }
+        }
+        Poll::Ready(())
+    }
+}
+
+#[cfg(feature = "async")]
+impl Drop for AsyncSendReceiveStream {
+    fn drop(&mut self) {
+        // The stream is dropped without exiting if its runtime shuts down
+        self.inbound_router.fail_all(ReceiveError::DisconnectedError);
+        if let Err(err) = self.socket.disconnect(&self.address) {
+            debug!("Unable to disconnect socket: {}", err.description());
+        }
+        if let Err(err) = self.monitor_socket.disconnect(&self.monitor_address) {
+            debug!("Unable to disconnect monitor socket: {}", err.description());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::sync_channel;
+    #[cfg(feature = "async")]
+    use std::thread;
+    use std::time::Duration;
+
+    use protobuf;
+    #[cfg(feature = "async")]
+    use tokio::runtime::Builder;
+    #[cfg(feature = "async")]
+    use tokio::time::timeout;
+    use zmq;
+
+    use messages::validator::Message;
+    use messages::validator::Message_MessageType;
+    use messaging::curve::CurveKey;
+    use messaging::curve::CurveSettings;
+    #[cfg(feature = "async")]
+    use messaging::stream::AsyncMessageConnection;
+    #[cfg(feature = "async")]
+    use messaging::stream::AsyncMessageSender;
+    use messaging::stream::MessageConnection;
+    use messaging::stream::MessageSender;
+    use messaging::stream::ReceiveError;
+    use messaging::stream::SendError;
+
+    use super::InboundRouter;
+    use super::InboundSender;
+    use super::ReplyMetrics;
+    use super::ZmqMessageConnection;
+
+    // The keypairs from the ZMQ CURVE examples
+    static SERVER_PUBLIC_Z85: &'static str = "rq:rM>}U?@Lns47E1%kR.o@n%FcmmsL/@{H8]yf7";
+    static SERVER_SECRET_Z85: &'static str = "JTKVSB%%)wK0E.X)V>+}o?pNmC{O&4W4b!Ni{Lh6";
+    static CLIENT_PUBLIC_Z85: &'static str = "Yne@$w-vo<fVvi]a<NY6T1ed:M$fCG*[IaLV{hID";
+    static CLIENT_SECRET_Z85: &'static str = "D:)Q[IlAW!ahhC2ac:9*A}h:p?([4%wOTJ%JR%cs";
+
+    fn make_reply(correlation_id: &str) -> Message {
+        let mut message = Message::new();
+        message.set_correlation_id(String::from(correlation_id));
+        message
+    }
+
+    #[test]
+    fn reply_is_routed_to_future() {
+        let (inbound_tx, _inbound_rx) = sync_channel(1);
+        let mut router = InboundRouter::new(InboundSender::Blocking(inbound_tx), 10);
+
+        let mut future = router.expect_reply(String::from("1")).unwrap();
+        assert_eq!(router.metrics().outstanding, 1);
+
+        router.route(Ok(make_reply("1")));
+        assert_eq!(future.get().unwrap().get_correlation_id(), "1");
+        drop(future);
+
+        assert_eq!(router.metrics(), ReplyMetrics {
+            outstanding: 0,
+            peak_outstanding: 1,
+            abandoned: 0,
+            late_replies: 0,
+            rejected: 0
+        });
+    }
+
+    #[test]
+    fn dropped_future_is_removed() {
+        let (inbound_tx, inbound_rx) = sync_channel(1);
+        let mut router = InboundRouter::new(InboundSender::Blocking(inbound_tx), 10);
+
+        let mut future = router.expect_reply(String::from("1")).unwrap();
+        match future.get_timeout(Duration::from_millis(1)) {
+            Err(ReceiveError::TimeoutError) => (),
+            result => panic!("Expected a TimeoutError, got {:?}", result)
+        }
+        drop(future);
+
+        let metrics = router.metrics();
+        assert_eq!(metrics.outstanding, 0);
+        assert_eq!(metrics.abandoned, 1);
+
+        // A late reply is dropped, rather than taken for a request
+        router.route(Ok(make_reply("1")));
+        assert!(inbound_rx.try_recv().is_err());
+        assert_eq!(router.metrics().late_replies, 1);
+
+        // Messages from the validator are still forwarded
+        router.route(Ok(make_reply("2")));
+        assert_eq!(inbound_rx.recv().unwrap().unwrap().get_correlation_id(), "2");
+    }
+
+    #[test]
+    fn outstanding_replies_are_bounded() {
+        let (inbound_tx, _inbound_rx) = sync_channel(1);
+        let mut router = InboundRouter::new(InboundSender::Blocking(inbound_tx), 2);
+
+        let _first = router.expect_reply(String::from("1")).unwrap();
+        let second = router.expect_reply(String::from("2")).unwrap();
+        match router.expect_reply(String::from("3")) {
+            Err(SendError::QueueFullError) => (),
+            Err(err) => panic!("Expected a QueueFullError, got {:?}", err),
+            Ok(_) => panic!("Expected a QueueFullError")
+        }
+
+        drop(second);
+        assert!(router.expect_reply(String::from("3")).is_ok());
+        assert_eq!(router.metrics().rejected, 1);
+    }
+
+    #[test]
+    fn socket_error_fails_pending_futures() {
+        let (inbound_tx, inbound_rx) = sync_channel(2);
+        let mut router = InboundRouter::new(InboundSender::Blocking(inbound_tx), 10);
+
+        let mut future = router.expect_reply(String::from("1")).unwrap();
+        router.route(Err(ReceiveError::ParseError(String::from("bad frame"))));
+        match inbound_rx.recv().unwrap() {
+            Err(ReceiveError::ParseError(_)) => (),
+            result => panic!("Expected a ParseError, got {:?}", result)
+        }
+        assert_eq!(router.metrics().outstanding, 1);
+
+        router.route(Err(ReceiveError::SocketError(String::from("failed"))));
+        match future.get() {
+            Err(ReceiveError::SocketError(_)) => (),
+            result => panic!("Expected a SocketError, got {:?}", result)
+        }
+        match inbound_rx.recv().unwrap() {
+            Err(ReceiveError::SocketError(_)) => (),
+            result => panic!("Expected a SocketError, got {:?}", result)
+        }
+        assert_eq!(router.metrics().outstanding, 0);
+    }
+
+    #[test]
+    fn closed_inbound_channel_does_not_panic() {
+        let (inbound_tx, inbound_rx) = sync_channel(1);
+        let mut router = InboundRouter::new(InboundSender::Blocking(inbound_tx), 10);
+        drop(inbound_rx);
+
+        router.route(Ok(make_reply("1")));
+        router.route(Err(ReceiveError::DisconnectedError));
+    }
+
+    #[test]
+    fn send_after_stream_exits_is_disconnected() {
+        let (mut sender, _receiver) = ZmqMessageConnection::new("tcp://127.0.0.1:1").create();
+        let mut clone = sender.clone();
+
+        // Closing one sender shuts down the stream thread they share
+        sender.close();
+
+        match clone.reply(Message_MessageType::PING_RESPONSE, "1", &[]) {
+            Err(SendError::DisconnectedError) => (),
+            result => panic!("Expected a DisconnectedError, got {:?}", result)
+        }
+        match clone.send(Message_MessageType::PING_REQUEST, "2", &[]) {
+            Err(SendError::DisconnectedError) => (),
+            Err(err) => panic!("Expected a DisconnectedError, got {:?}", err),
+            Ok(_) => panic!("Expected a DisconnectedError")
+        }
+    }
+
+    /// Binds a ROUTER socket, standing in for a validator which requires
+    /// CURVE, returning it and its endpoint.
+    fn curve_router(context: &zmq::Context) -> (zmq::Socket, String) {
+        let router = context.socket(zmq::ROUTER).unwrap();
+        router.set_curve_server(true).unwrap();
+        router.set_curve_secretkey(CurveKey::from_z85(SERVER_SECRET_Z85).unwrap().as_bytes())
+            .unwrap();
+        router.bind("tcp://127.0.0.1:*").unwrap();
+        let endpoint = router.get_last_endpoint().unwrap().unwrap();
+        (router, endpoint)
+    }
+
+    fn client_curve(server_public_z85: &str) -> CurveSettings {
+        CurveSettings::new(
+            CurveKey::from_z85(server_public_z85).unwrap(),
+            CurveKey::from_z85(CLIENT_PUBLIC_Z85).unwrap(),
+            CurveKey::from_z85(CLIENT_SECRET_Z85).unwrap())
+    }
+
+    /// Waits up to timeout milliseconds for a message on the router,
+    /// returning the sender's identity and the message.
+    fn recv_request(router: &zmq::Socket, timeout: i64) -> Option<(Vec<u8>, Message)> {
+        let mut poll_items = [router.as_poll_item(zmq::POLLIN)];
+        zmq::poll(&mut poll_items, timeout).unwrap();
+        if !poll_items[0].is_readable() {
+            return None;
+        }
+        let mut parts = router.recv_multipart(0).unwrap();
+        let message = protobuf::parse_from_bytes(&parts.pop().unwrap()).unwrap();
+        Some((parts.remove(0), message))
+    }
+
+    #[test]
+    fn curve_connection_to_router() {
+        let context = zmq::Context::new();
+        let (router, endpoint) = curve_router(&context);
+
+        let mut conn = ZmqMessageConnection::new(&endpoint);
+        conn.set_curve(client_curve(SERVER_PUBLIC_Z85));
+        let (mut sender, _receiver) = conn.create();
+        let mut future = sender.send(Message_MessageType::PING_REQUEST, "1", &[]).unwrap();
+
+        let (identity, request) = recv_request(&router, 5000).expect("No request received");
+        assert_eq!(request.get_correlation_id(), "1");
+
+        let mut reply = Message::new();
+        reply.set_message_type(Message_MessageType::PING_RESPONSE);
+        reply.set_correlation_id(String::from("1"));
+        let reply_bytes = protobuf::Message::write_to_bytes(&reply).unwrap();
+        router.send_multipart(&[&identity, &reply_bytes], 0).unwrap();
+
+        let response = future.get_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(response.get_message_type(), Message_MessageType::PING_RESPONSE);
+        sender.close();
+    }
+
+    #[test]
+    fn curve_connection_with_wrong_server_key() {
+        let context = zmq::Context::new();
+        let (router, endpoint) = curve_router(&context);
+
+        // The handshake fails, so nothing is delivered
+        let mut conn = ZmqMessageConnection::new(&endpoint);
+        conn.set_curve(client_curve(CLIENT_PUBLIC_Z85));
+        let (mut sender, _receiver) = conn.create();
+        let _future = sender.send(Message_MessageType::PING_REQUEST, "1", &[]).unwrap();
+
+        assert!(recv_request(&router, 500).is_none());
+        sender.close();
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn async_connection_to_router() {
+        let context = zmq::Context::new();
+        let router = context.socket(zmq::ROUTER).unwrap();
+        router.bind("tcp://127.0.0.1:*").unwrap();
+        let endpoint = router.get_last_endpoint().unwrap().unwrap();
+
+        // Answers the first request, standing in for the validator
+        let validator = thread::spawn(move || {
+            let (identity, request) = recv_request(&router, 5000).expect("No request received");
+            let mut reply = Message::new();
+            reply.set_message_type(Message_MessageType::PING_RESPONSE);
+            reply.set_correlation_id(String::from(request.get_correlation_id()));
+            let reply_bytes = protobuf::Message::write_to_bytes(&reply).unwrap();
+            router.send_multipart(&[&identity, &reply_bytes], 0).unwrap();
+        });
+
+        let runtime = Builder::new_current_thread().enable_all().build().unwrap();
+        let _context = runtime.enter();
+        let (mut sender, _receiver) = ZmqMessageConnection::new(&endpoint).create_async();
+        let future = sender.send_async(Message_MessageType::PING_REQUEST, "1", &[]).unwrap();
+
+        let response = runtime.block_on(timeout(Duration::from_secs(5), future))
+            .expect("No reply received")
+            .unwrap();
+        assert_eq!(response.get_message_type(), Message_MessageType::PING_RESPONSE);
+        validator.join().unwrap();
+
+        // Closing does not wait for the stream, which exits on the runtime
+        sender.close();
+        match sender.send_async(Message_MessageType::PING_REQUEST, "2", &[]) {
+            Err(SendError::DisconnectedError) => (),
+            Err(err) => panic!("Expected a DisconnectedError, got {:?}", err),
+            Ok(_) => panic!("Expected a DisconnectedError")
+        }
     }
 }
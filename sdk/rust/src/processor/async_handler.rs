@@ -0,0 +1,213 @@
+/*
+ * Copyright 2017 Bitwise IO, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+use futures::future::{self, BoxFuture, FutureExt};
+use protobuf::Message as M;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use messages::processor::TpProcessRequest;
+use messages::validator::Message_MessageType;
+
+use messaging::stream::AsyncMessageSender;
+use messaging::stream::SendError;
+
+use super::generate_correlation_id;
+use super::handler::*;
+use super::requests::*;
+
+/// The future returned by each AsyncTransactionContext call.
+pub type ContextFuture<T> = BoxFuture<'static, Result<T, ContextError>>;
+
+/// The future returned by AsyncTransactionHandler::apply.
+pub type ApplyFuture = BoxFuture<'static, Result<(), ApplyError>>;
+
+#[derive(Clone)]
+pub struct AsyncTransactionContext {
+    context_id: String,
+    sender: Arc<Mutex<AsyncMessageSender + Send>>
+}
+
+impl AsyncTransactionContext {
+    /// AsyncTransactionContext provides the same interface as
+    /// TransactionContext for getting, setting, and deleting validator
+    /// state, but each call returns a future for the validator's reply
+    /// instead of blocking on it.
+    ///
+    /// # Arguments
+    ///
+    /// * `context_id` - the context_id passed in from the validator
+    /// * `sender` - the sender for the connection to the validator
+    pub fn new<MS>(context_id: &str, sender: MS) -> AsyncTransactionContext
+        where MS: AsyncMessageSender + Send + 'static
+    {
+        AsyncTransactionContext {
+            context_id: String::from(context_id),
+            sender: Arc::new(Mutex::new(sender))
+        }
+    }
+
+    /// get_state queries the validator state for data at the given
+    /// address. Resolves to None if the address has not been set.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - the address to fetch
+    pub fn get_state(&mut self, address: &str) -> ContextFuture<Option<Vec<u8>>> {
+        let address = String::from(address);
+        self.get_state_entries(&[address.clone()])
+            .map(move |result| result.map(|mut entries| entries.remove(&address)))
+            .boxed()
+    }
+
+    /// get_state_entries queries the validator state for data at each of
+    /// the addresses in the given list, in a single request.
+    ///
+    /// # Arguments
+    ///
+    /// * `addresses` - the addresses to fetch
+    pub fn get_state_entries(&mut self, addresses: &[String])
+        -> ContextFuture<HashMap<String, Vec<u8>>>
+    {
+        let request = state_get_request(&self.context_id, addresses);
+        let addresses = Vec::from(addresses);
+        self.send_request(Message_MessageType::TP_STATE_GET_REQUEST, &request,
+                          move |content| decode_state_get_response(&addresses, content))
+    }
+
+    /// set_state requests that the provided address be set in validator
+    /// state to the given payload.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - address of where to store the data
+    /// * `payload` - payload is the data to store at the address
+    pub fn set_state(&mut self, address: &str, payload: &[u8]) -> ContextFuture<()> {
+        self.set_state_entries(vec![(String::from(address), Vec::from(payload))])
+    }
+
+    /// set_state_entries requests that each address in the provided list be
+    /// set in validator state to its corresponding value, in a single
+    /// request.
+    ///
+    /// # Arguments
+    ///
+    /// * `entries` - the address and data pairs to store
+    pub fn set_state_entries(&mut self, entries: Vec<(String, Vec<u8>)>) -> ContextFuture<()> {
+        let (addresses, request) = state_set_request(&self.context_id, entries);
+        self.send_request(Message_MessageType::TP_STATE_SET_REQUEST, &request,
+                          move |content| decode_state_set_response(&addresses, content))
+    }
+
+    /// delete_state requests that each of the provided addresses be unset
+    /// in validator state. Resolves to the list of successfully deleted
+    /// addresses.
+    ///
+    /// # Arguments
+    ///
+    /// * `addresses` - the addresses to delete
+    pub fn delete_state(&mut self, addresses: Vec<String>) -> ContextFuture<Option<Vec<String>>> {
+        let request = state_delete_request(&self.context_id, &addresses);
+        self.send_request(Message_MessageType::TP_STATE_DELETE_REQUEST, &request,
+                          move |content| decode_state_delete_response(&addresses, content))
+    }
+
+    /// add_receipt_data adds a blob to the execution result for this transaction
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - the data to add
+    pub fn add_receipt_data(&mut self, data: &[u8]) -> ContextFuture<()> {
+        let request = receipt_add_data_request(&self.context_id, data);
+        let data = Vec::from(data);
+        self.send_request(Message_MessageType::TP_RECEIPT_ADD_DATA_REQUEST, &request,
+                          move |content| decode_receipt_add_data_response(&data, content))
+    }
+
+    /// add_event adds a new event to the execution result for this
+    /// transaction. See TransactionContext::add_event for a description of
+    /// the arguments.
+    pub fn add_event(&mut self, event_type: String, attributes: Vec<(String, String)>,
+                     data: &[u8])
+        -> ContextFuture<()>
+    {
+        let request = event_add_request(&self.context_id, event_type, attributes, data);
+        let event = request.get_event().clone();
+        self.send_request(Message_MessageType::TP_EVENT_ADD_REQUEST, &request,
+                          move |content| decode_event_add_response(&event, content))
+    }
+
+    /// Sends the request to the validator, returning a future which decodes
+    /// the content of its reply with the given function.
+    fn send_request<R, T, F>(&mut self, message_type: Message_MessageType, request: &R,
+                             decode: F)
+        -> ContextFuture<T>
+        where R: M,
+              T: Send + 'static,
+              F: FnOnce(&[u8]) -> Result<T, ContextError> + Send + 'static
+    {
+        let serialized = match request.write_to_bytes() {
+            Ok(serialized) => serialized,
+            Err(err) => return future::ready(Err(ContextError::from(err))).boxed()
+        };
+
+        // The sender is only locked while sending; the reply is awaited
+        // without it.
+        let sent = match self.sender.lock() {
+            Ok(mut sender) => sender.send_async(
+                message_type, &generate_correlation_id(), &serialized),
+            Err(_) => Err(SendError::UnknownError)
+        };
+
+        match sent {
+            Ok(reply) => reply
+                .map(move |result| {
+                    let message = result?;
+                    decode(message.get_content())
+                })
+                .boxed(),
+            Err(err) => future::ready(Err(ContextError::from(err))).boxed()
+        }
+    }
+}
+
+pub trait AsyncTransactionHandler: Send + Sync {
+    /// AsyncTransactionHandler defines the business logic for a transaction
+    /// family, like TransactionHandler, for handlers that are driven by an
+    /// async runtime. The family_name, family_versions, and namespaces
+    /// functions are used by the processor to route processing requests to
+    /// the handler.
+
+    /// family_name should return the name of the transaction family that this
+    /// handler can process, e.g. "intkey"
+    fn family_name(&self) -> String;
+
+    /// family_versions should return a list of versions this transaction
+    /// family handler can process, e.g. ["1.0"]
+    fn family_versions(&self) -> Vec<String>;
+
+    /// namespaces should return a list containing all the handler's
+    /// namespaces, e.g. ["abcdef"]
+    fn namespaces(&self) -> Vec<String>;
+
+    /// Apply is the single method where all the business logic for a
+    /// transaction family is defined. The returned future is driven by the
+    /// AsyncTransactionProcessor, concurrently with other transactions,
+    /// and its result is sent to the validator when it resolves.
+    fn apply(&self, request: TpProcessRequest, context: AsyncTransactionContext) -> ApplyFuture;
+}
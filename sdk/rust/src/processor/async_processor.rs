@@ -0,0 +1,1008 @@
+/*
+ * Copyright 2017 Bitwise IO, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+use futures::future::{self, BoxFuture, FutureExt};
+use futures::stream::{FuturesUnordered, StreamExt};
+use protobuf;
+use protobuf::Message as M;
+use tokio::time::{sleep, Sleep};
+
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::mem;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use messages::processor::TpProcessRequest;
+use messages::processor::TpRegisterRequest;
+use messages::processor::TpUnregisterRequest;
+use messages::validator::Message;
+use messages::validator::Message_MessageType;
+use messaging::curve::CurveSettings;
+use messaging::stream::AsyncMessageConnection;
+use messaging::stream::AsyncMessageReceiver;
+use messaging::stream::AsyncMessageSender;
+use messaging::stream::ReceiveError;
+use messaging::stream::ReplyFuture;
+use messaging::zmq_stream::ZmqMessageConnection;
+use messaging::zmq_stream::ZmqAsyncMessageSender;
+
+use super::build_process_response;
+use super::dispatch::MessageDispatcher;
+use super::dispatch::MessageHandler;
+use super::check_register_response;
+use super::generate_correlation_id;
+use super::ProcessorError;
+use super::DEFAULT_REGISTER_TIMEOUT_MILLIS;
+use super::async_handler::AsyncTransactionContext;
+use super::async_handler::AsyncTransactionHandler;
+use super::handler::ApplyError;
+use super::reconnect::ReconnectPolicy;
+use super::requests::register_request;
+
+/// Connects to a validator and routes transaction processing requests to
+/// the registered async handlers. By default it connects over ZMQ; any
+/// other AsyncMessageConnection may be used through
+/// AsyncTransactionProcessor::with_connection.
+pub struct AsyncTransactionProcessor<MC = ZmqMessageConnection, MS = ZmqAsyncMessageSender> {
+    endpoint: String,
+    conn: MC,
+    // Senders are created by the connection; the processor holds none
+    // itself.
+    sender_type: PhantomData<fn() -> MS>,
+    // The family versions to register, in the order they were added
+    family_versions: Vec<(String, String)>,
+    handler_map: HashMap<(String, String), Arc<AsyncTransactionHandler>>,
+    max_occupancy: u32,
+    reconnect_policy: ReconnectPolicy,
+    register_timeout: Duration,
+    dispatcher: MessageDispatcher<'static>
+}
+
+impl AsyncTransactionProcessor {
+    /// AsyncTransactionProcessor is for communicating with a validator and
+    /// routing transaction processing requests to registered async
+    /// handlers. It does no work on its own: `run` returns a future, which
+    /// must be driven on a tokio runtime with IO and time enabled, e.g.
+    /// with `tokio::spawn`.
+    ///
+    /// The ZMQ socket is registered with the runtime's reactor, so the
+    /// connection needs no thread of its own; any other connection may be
+    /// supplied through `with_connection` instead.
+    pub fn new(endpoint: &str) -> AsyncTransactionProcessor {
+        AsyncTransactionProcessor::with_connection(endpoint, ZmqMessageConnection::new(endpoint))
+    }
+
+    /// Encrypts the connection to the validator, and authenticates this
+    /// processor to it, with ZMQ CURVE.
+    ///
+    /// # Arguments
+    ///
+    /// * curve - the validator's public key and this processor's keypair
+    pub fn set_curve(&mut self, curve: CurveSettings) {
+        self.conn.set_curve(curve);
+    }
+}
+
+impl<MC, MS> AsyncTransactionProcessor<MC, MS>
+    where MC: AsyncMessageConnection<MS>,
+          MS: AsyncMessageSender + Clone + Send + 'static
+{
+    /// Creates an AsyncTransactionProcessor which communicates with the
+    /// validator over the given connection, rather than over ZMQ.
+    ///
+    /// # Arguments
+    ///
+    /// * endpoint - the name of the validator endpoint, used when logging
+    /// * conn - the connection which creates a sender and receiver each
+    ///   time the processor connects
+    pub fn with_connection(endpoint: &str, conn: MC) -> AsyncTransactionProcessor<MC, MS> {
+        AsyncTransactionProcessor {
+            endpoint: String::from(endpoint),
+            conn: conn,
+            sender_type: PhantomData,
+            family_versions: Vec::new(),
+            handler_map: HashMap::new(),
+            max_occupancy: 0,
+            reconnect_policy: ReconnectPolicy::new(),
+            register_timeout: Duration::from_millis(DEFAULT_REGISTER_TIMEOUT_MILLIS),
+            dispatcher: MessageDispatcher::new()
+        }
+    }
+
+    /// Adds a transaction family handler
+    ///
+    /// The handler is registered for each of its family versions, and
+    /// TpProcessRequests are routed to it by the family name and version in
+    /// the transaction header. A handler added for a family version which
+    /// already has one replaces it, and the version is registered once.
+    ///
+    /// # Arguments
+    ///
+    /// * handler - the handler to be added
+    pub fn add_handler(&mut self, handler: Arc<AsyncTransactionHandler>) {
+        for version in handler.family_versions() {
+            let key = (handler.family_name(), version);
+            if self.handler_map.contains_key(&key) {
+                warn!("Replacing handler for family {} version {}", key.0, key.1);
+            } else {
+                self.family_versions.push(key.clone());
+            }
+            self.handler_map.insert(key, handler.clone());
+        }
+    }
+
+    /// Adds a handler for requests of the given message type from the
+    /// validator. PingRequests are answered by default; TpProcessRequests
+    /// are always routed to the transaction handlers.
+    ///
+    /// # Arguments
+    ///
+    /// * message_type - the type of request the handler answers
+    /// * handler - the handler, which returns the reply to send
+    pub fn add_message_handler(&mut self, message_type: Message_MessageType,
+                               handler: Box<MessageHandler>)
+    {
+        if message_type == Message_MessageType::TP_PROCESS_REQUEST {
+            warn!("TpProcessRequests are routed to the transaction handlers; \
+                   ignoring the message handler");
+            return;
+        }
+        self.dispatcher.add_handler(message_type, handler);
+    }
+
+    /// Sets the maximum number of transactions this processor will apply
+    /// at once. The value is sent to the validator on registration, and no
+    /// further requests are read from the connection while that many are
+    /// in flight.
+    ///
+    /// # Arguments
+    ///
+    /// * max_occupancy - the number of transactions to apply at once
+    pub fn set_max_occupancy(&mut self, max_occupancy: u32) {
+        self.max_occupancy = max_occupancy;
+    }
+
+    /// Sets the policy for reconnecting to the validator after the
+    /// connection is lost, or registration fails.
+    ///
+    /// # Arguments
+    ///
+    /// * reconnect_policy - the delays and limits for reconnect attempts
+    pub fn set_reconnect_policy(&mut self, reconnect_policy: ReconnectPolicy) {
+        self.reconnect_policy = reconnect_policy;
+    }
+
+    /// Sets how long the processor waits for the validator to answer each
+    /// registration request. If it does not answer in time, the connection
+    /// attempt counts as failed, and the processor reconnects as the
+    /// reconnect policy allows. Defaults to 10 seconds.
+    ///
+    /// # Arguments
+    ///
+    /// * timeout - the longest wait for each TpRegisterResponse
+    pub fn set_register_timeout(&mut self, timeout: Duration) {
+        self.register_timeout = timeout;
+    }
+
+    /// Returns a future which connects to the validator, registers the
+    /// handlers and applies transactions as requests arrive, reconnecting
+    /// as the reconnect policy allows if the validator goes away. The
+    /// future only completes, with an error, if the validator rejects a
+    /// registration or the reconnect policy gives up; dropping it
+    /// unregisters the processor and closes the connection.
+    pub fn run(self) -> ProcessorFuture<MC, MS> {
+        ProcessorFuture {
+            processor: self,
+            state: State::Connecting { attempt: 0 },
+            in_flight: FuturesUnordered::new()
+        }
+    }
+
+    fn register_requests(&self) -> VecDeque<TpRegisterRequest> {
+        self.family_versions
+            .iter()
+            .map(|&(ref family, ref version)| {
+                let handler = &self.handler_map[&(family.clone(), version.clone())];
+                register_request(family, version, handler.namespaces(), self.max_occupancy)
+            })
+            .collect()
+    }
+
+    /// Returns a future which applies the TpProcessRequest in the given
+    /// message with the matching handler and replies to the validator with
+    /// the TpProcessResponse.
+    fn process_request(&self, sender: &MS, message: &Message) -> BoxFuture<'static, ()> {
+        let request: TpProcessRequest = match protobuf::parse_from_bytes(
+            &message.get_content()) {
+            Ok(request) => request,
+            Err(err) => {
+                error!("Cannot parse TpProcessRequest: {}", err.description());
+                return future::ready(()).boxed()
+            }
+        };
+
+        let context = AsyncTransactionContext::new(
+            request.get_context_id(), sender.clone());
+
+        let key = (String::from(request.get_header().get_family_name()),
+                   String::from(request.get_header().get_family_version()));
+        let apply = match self.handler_map.get(&key) {
+            Some(handler) => handler.apply(request, context),
+            None => future::ready(Err(ApplyError::InternalError(format!(
+                "No handler registered for family {} version {}", key.0, key.1)))).boxed()
+        };
+
+        let mut sender = sender.clone();
+        let correlation_id = String::from(message.get_correlation_id());
+        apply.map(move |result| {
+            let response = build_process_response(result);
+            let serialized = match response.write_to_bytes() {
+                Ok(serialized) => serialized,
+                Err(err) => {
+                    error!("Serialization failed: {}", err.description());
+                    return
+                }
+            };
+            if let Err(err) = sender.reply(
+                Message_MessageType::TP_PROCESS_RESPONSE, &correlation_id, &serialized)
+            {
+                error!("Failed to send TpProcessResponse: {}", err.description());
+            }
+        }).boxed()
+    }
+
+    /// Passes a request other than a TpProcessRequest to the dispatcher,
+    /// and sends its reply.
+    fn dispatch(&self, sender: &mut MS, message: &Message) {
+        if let Some((reply_type, reply)) = self.dispatcher.dispatch(message) {
+            if let Err(err) = sender.reply(reply_type, message.get_correlation_id(), &reply) {
+                error!("Failed to send {:?}: {}", reply_type, err.description());
+            }
+        }
+    }
+}
+
+/// The connection state of a ProcessorFuture. Each state before Running
+/// carries the number of consecutive failed connection attempts.
+enum State<MS> {
+    Connecting {
+        attempt: u32
+    },
+    Waiting {
+        attempt: u32,
+        delay: Pin<Box<Sleep>>
+    },
+    Registering {
+        attempt: u32,
+        sender: MS,
+        receiver: AsyncMessageReceiver,
+        requests: VecDeque<TpRegisterRequest>,
+        // The request awaiting its reply, and the timer for giving up on it
+        pending: Option<(TpRegisterRequest, ReplyFuture, Pin<Box<Sleep>>)>
+    },
+    Running {
+        sender: MS,
+        receiver: AsyncMessageReceiver
+    }
+}
+
+/// The future returned by AsyncTransactionProcessor::run.
+pub struct ProcessorFuture<MC = ZmqMessageConnection, MS = ZmqAsyncMessageSender>
+    where MS: AsyncMessageSender
+{
+    processor: AsyncTransactionProcessor<MC, MS>,
+    state: State<MS>,
+    in_flight: FuturesUnordered<BoxFuture<'static, ()>>
+}
+
+// The future is never pinned in place: its state is moved in and out as
+// it is polled.
+impl<MC, MS: AsyncMessageSender> Unpin for ProcessorFuture<MC, MS> {}
+
+impl<MC, MS> ProcessorFuture<MC, MS>
+    where MC: AsyncMessageConnection<MS>,
+          MS: AsyncMessageSender + Clone + Send + 'static
+{
+    /// Advances the connection state as far as possible, returning once it
+    /// is waiting on the validator, on a reconnect delay, or for room to
+    /// apply more transactions. Returns an error if the validator rejects a
+    /// registration or the reconnect policy gives up.
+    fn poll_state(&mut self, cx: &mut Context) -> Result<(), ProcessorError> {
+        loop {
+            match mem::replace(&mut self.state, State::Connecting { attempt: 0 }) {
+                State::Connecting { attempt } => {
+                    info!("connecting to endpoint: {}", self.processor.endpoint);
+                    let (sender, receiver) = self.processor.conn.create_async();
+                    self.state = State::Registering {
+                        attempt: attempt,
+                        sender: sender,
+                        receiver: receiver,
+                        requests: self.processor.register_requests(),
+                        pending: None
+                    };
+                }
+                State::Waiting { attempt, mut delay } => {
+                    match delay.poll_unpin(cx) {
+                        Poll::Ready(()) => {
+                            self.state = State::Connecting { attempt: attempt };
+                        }
+                        Poll::Pending => {
+                            self.state = State::Waiting { attempt: attempt, delay: delay };
+                            return Ok(())
+                        }
+                    }
+                }
+                State::Registering { attempt, mut sender, receiver, mut requests, pending } => {
+                    let (request, mut reply, mut timeout) = match pending {
+                        Some(pending) => pending,
+                        None => match requests.pop_front() {
+                            Some(request) => {
+                                info!("sending TpRegisterRequest: {} {}",
+                                      request.get_family(),
+                                      request.get_version());
+                                match send_request(&mut sender,
+                                                   Message_MessageType::TP_REGISTER_REQUEST,
+                                                   &request) {
+                                    Some(reply) => {
+                                        let timeout = Box::pin(
+                                            sleep(self.processor.register_timeout));
+                                        (request, reply, timeout)
+                                    }
+                                    None => {
+                                        self.retry(sender, attempt + 1)?;
+                                        continue
+                                    }
+                                }
+                            }
+                            None => {
+                                self.state = State::Running {
+                                    sender: sender,
+                                    receiver: receiver
+                                };
+                                continue
+                            }
+                        }
+                    };
+
+                    // Absorb the TpRegisterResponse message
+                    match reply.poll_unpin(cx) {
+                        Poll::Ready(Ok(response)) => {
+                            if let Err(err) = check_register_response(
+                                &request, response.get_content())
+                            {
+                                sender.close();
+                                return Err(err)
+                            }
+                            self.state = State::Registering {
+                                attempt: attempt,
+                                sender: sender,
+                                receiver: receiver,
+                                requests: requests,
+                                pending: None
+                            };
+                        }
+                        Poll::Ready(Err(err)) => {
+                            error!("Registration failed: {}", err.description());
+                            self.retry(sender, attempt + 1)?;
+                        }
+                        Poll::Pending => {
+                            if let Poll::Ready(()) = timeout.poll_unpin(cx) {
+                                warn!("No TpRegisterResponse received within {:?}",
+                                      self.processor.register_timeout);
+                                self.retry(sender, attempt + 1)?;
+                                continue
+                            }
+                            self.state = State::Registering {
+                                attempt: attempt,
+                                sender: sender,
+                                receiver: receiver,
+                                requests: requests,
+                                pending: Some((request, reply, timeout))
+                            };
+                            return Ok(())
+                        }
+                    }
+                }
+                State::Running { mut sender, mut receiver } => {
+                    let max_occupancy = self.processor.max_occupancy as usize;
+                    if max_occupancy > 0 && self.in_flight.len() >= max_occupancy {
+                        // Woken again when one of the in-flight requests
+                        // completes.
+                        self.state = State::Running { sender: sender, receiver: receiver };
+                        return Ok(())
+                    }
+
+                    match receiver.poll_next_unpin(cx) {
+                        Poll::Ready(Some(Ok(message))) => {
+                            info!("Message: {}", message.get_correlation_id());
+                            match message.get_message_type() {
+                                Message_MessageType::TP_PROCESS_REQUEST => {
+                                    let apply = self.processor.process_request(&sender, &message);
+                                    self.in_flight.push(apply);
+                                }
+                                _ => {
+                                    self.processor.dispatch(&mut sender, &message);
+                                }
+                            }
+                            self.state = State::Running { sender: sender, receiver: receiver };
+                        }
+                        Poll::Ready(Some(Err(ReceiveError::DisconnectedError))) |
+                        Poll::Ready(Some(Err(ReceiveError::SocketError(_)))) |
+                        Poll::Ready(None) => {
+                            info!("Trying to Reconnect");
+                            self.retry(sender, 1)?;
+                        }
+                        Poll::Ready(Some(Err(err))) => {
+                            error!("Error: {}", err.description());
+                            self.state = State::Running { sender: sender, receiver: receiver };
+                        }
+                        Poll::Pending => {
+                            self.state = State::Running { sender: sender, receiver: receiver };
+                            return Ok(())
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Closes the connection, and waits as the reconnect policy requires
+    /// before the given attempt, counting from 1. Returns an error if the
+    /// policy gives up.
+    fn retry(&mut self, mut sender: MS, attempt: u32) -> Result<(), ProcessorError> {
+        sender.close();
+
+        let policy = &self.processor.reconnect_policy;
+        if policy.is_exhausted(attempt) {
+            let msg = format!("Gave up on connecting to {} after {} attempts",
+                              self.processor.endpoint, attempt - 1);
+            error!("{}", msg);
+            return Err(ProcessorError::ConnectionError(msg));
+        }
+
+        let delay = policy.delay(attempt);
+        info!("Reconnecting in {:?} (attempt {})", delay, attempt);
+        self.state = if delay == Duration::from_millis(0) {
+            State::Connecting { attempt: attempt }
+        } else {
+            State::Waiting {
+                attempt: attempt,
+                delay: Box::pin(sleep(delay))
+            }
+        };
+        Ok(())
+    }
+}
+
+impl<MC, MS> Future for ProcessorFuture<MC, MS>
+    where MC: AsyncMessageConnection<MS>,
+          MS: AsyncMessageSender + Clone + Send + 'static
+{
+    type Output = Result<(), ProcessorError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        loop {
+            if let Err(err) = this.poll_state(cx) {
+                return Poll::Ready(Err(err))
+            }
+
+            let mut completed = false;
+            while let Poll::Ready(Some(())) = this.in_flight.poll_next_unpin(cx) {
+                completed = true;
+            }
+
+            // A completed request may have made room for more, so the
+            // connection is read again before waiting.
+            if !completed {
+                return Poll::Pending
+            }
+        }
+    }
+}
+
+impl<MC, MS: AsyncMessageSender> Drop for ProcessorFuture<MC, MS> {
+    fn drop(&mut self) {
+        match mem::replace(&mut self.state, State::Connecting { attempt: 0 }) {
+            State::Running { mut sender, .. } => {
+                info!("sending TpUnregisterRequest");
+                send_request(&mut sender,
+                             Message_MessageType::TP_UNREGISTER_REQUEST,
+                             &TpUnregisterRequest::new());
+                sender.close();
+            }
+            State::Registering { mut sender, .. } => sender.close(),
+            State::Connecting { .. } | State::Waiting { .. } => ()
+        }
+    }
+}
+
+/// Sends the request to the validator, returning a future for the reply,
+/// or None if it could not be sent.
+fn send_request<R, MS>(sender: &mut MS, message_type: Message_MessageType, request: &R)
+    -> Option<ReplyFuture>
+    where R: M,
+          MS: AsyncMessageSender
+{
+    let serialized = match request.write_to_bytes() {
+        Ok(serialized) => serialized,
+        Err(err) => {
+            error!("Serialization failed: {}", err.description());
+            return None
+        }
+    };
+
+    match sender.send_async(message_type, &generate_correlation_id(), &serialized) {
+        Ok(reply) => Some(reply),
+        Err(err) => {
+            error!("Failed to send {:?}: {}", message_type, err.description());
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::channel::mpsc::{unbounded, UnboundedSender};
+    use futures::channel::oneshot;
+    use futures::executor::LocalPool;
+    use futures::future::{abortable, AbortHandle, FutureExt};
+    use futures::task::LocalSpawnExt;
+    use protobuf;
+    use protobuf::Message as M;
+    use tokio::runtime::{Builder, Runtime};
+
+    use std::error::Error;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use messages::processor::TpProcessRequest;
+    use messages::processor::TpProcessResponse;
+    use messages::processor::TpProcessResponse_Status;
+    use messages::processor::TpRegisterRequest;
+    use messages::processor::TpRegisterResponse;
+    use messages::processor::TpRegisterResponse_Status;
+    use messages::processor::TpUnregisterResponse;
+    use messages::processor::TpUnregisterResponse_Status;
+    use messages::state_context::TpStateSetRequest;
+    use messages::state_context::TpStateSetResponse;
+    use messages::state_context::TpStateSetResponse_Status;
+    use messages::transaction::TransactionHeader;
+    use messages::validator::Message;
+    use messages::validator::Message_MessageType;
+    use messaging::stream::AsyncMessageConnection;
+    use messaging::stream::AsyncMessageReceiver;
+    use messaging::stream::AsyncMessageSender;
+    use messaging::stream::MessageFuture;
+    use messaging::stream::MessageResult;
+    use messaging::stream::MessageSender;
+    use messaging::stream::ReplyFuture;
+    use messaging::stream::SendError;
+
+    use super::AsyncTransactionProcessor;
+    use super::super::ProcessorError;
+    use super::super::async_handler::ApplyFuture;
+    use super::super::async_handler::AsyncTransactionContext;
+    use super::super::async_handler::AsyncTransactionHandler;
+    use super::super::handler::ApplyError;
+    use super::super::reconnect::ReconnectPolicy;
+
+    fn assert_send<T: Send>(_: &T) {}
+
+    /// Returns a runtime for the processor's timers. The tests which step
+    /// the processor with a LocalPool enter it, but never drive its timers.
+    fn runtime() -> Runtime {
+        Builder::new_current_thread().enable_time().build().unwrap()
+    }
+
+    #[test]
+    fn processor_future_is_send() {
+        let processor = AsyncTransactionProcessor::new("tcp://localhost:4004");
+        let future = processor.run();
+
+        // The future must be Send to be spawned on a multi-threaded runtime.
+        assert_send(&future);
+    }
+
+    /// Sets the payload of each transaction at an address in the
+    /// handler's namespace.
+    struct SetHandler {
+        family_versions: Vec<String>
+    }
+
+    impl SetHandler {
+        fn new(family_versions: &[&str]) -> Arc<SetHandler> {
+            Arc::new(SetHandler {
+                family_versions: family_versions.iter().map(|v| String::from(*v)).collect()
+            })
+        }
+    }
+
+    impl AsyncTransactionHandler for SetHandler {
+        fn family_name(&self) -> String {
+            String::from("intkey")
+        }
+
+        fn family_versions(&self) -> Vec<String> {
+            self.family_versions.clone()
+        }
+
+        fn namespaces(&self) -> Vec<String> {
+            vec![String::from("abcdef")]
+        }
+
+        fn apply(&self, request: TpProcessRequest, mut context: AsyncTransactionContext)
+            -> ApplyFuture
+        {
+            context.set_state("abcdef00", request.get_payload())
+                .map(|result| result.map_err(
+                    |err| ApplyError::InternalError(String::from(err.description()))))
+                .boxed()
+        }
+    }
+
+    /// How the mock validator answers TpRegisterRequests.
+    #[derive(Clone, Copy, PartialEq)]
+    enum Registration {
+        Accept,
+        Reject,
+        /// Drops the request, as if the connection were lost
+        Disconnect,
+        /// Never answers the request
+        Ignore
+    }
+
+    #[derive(Default)]
+    struct MockLog {
+        /// The type and content of each request sent, in order
+        requests: Vec<(Message_MessageType, Vec<u8>)>,
+        /// The type, correlation id and content of each reply sent, in order
+        replies: Vec<(Message_MessageType, String, Vec<u8>)>,
+        /// The number of times a sender was closed
+        closed: usize,
+        /// The reply senders for the requests which are never answered
+        unanswered: Vec<oneshot::Sender<MessageResult>>
+    }
+
+    /// Sends messages to a mock validator, which accepts unregistration and
+    /// state sets, and answers registration as configured.
+    #[derive(Clone)]
+    struct MockSender {
+        registration: Registration,
+        log: Arc<Mutex<MockLog>>
+    }
+
+    impl MockSender {
+        fn requests(&self, message_type: Message_MessageType) -> Vec<Vec<u8>> {
+            self.log.lock().unwrap().requests
+                .iter()
+                .filter(|&&(t, _)| t == message_type)
+                .map(|&(_, ref content)| content.clone())
+                .collect()
+        }
+
+        fn replies(&self) -> Vec<(Message_MessageType, String, Vec<u8>)> {
+            self.log.lock().unwrap().replies.clone()
+        }
+
+        fn closed(&self) -> usize {
+            self.log.lock().unwrap().closed
+        }
+    }
+
+    impl MessageSender for MockSender {
+        fn send(&mut self, _destination: Message_MessageType, _correlation_id: &str,
+                _contents: &[u8])
+            -> Result<MessageFuture, SendError>
+        {
+            // The async processor only awaits its replies
+            Err(SendError::UnknownError)
+        }
+
+        fn reply(&mut self, destination: Message_MessageType, correlation_id: &str,
+                 contents: &[u8])
+            -> Result<(), SendError>
+        {
+            self.log.lock().unwrap().replies.push(
+                (destination, String::from(correlation_id), Vec::from(contents)));
+            Ok(())
+        }
+
+        fn close(&mut self) {
+            self.log.lock().unwrap().closed += 1;
+        }
+    }
+
+    impl AsyncMessageSender for MockSender {
+        fn send_async(&mut self, destination: Message_MessageType, correlation_id: &str,
+                      contents: &[u8])
+            -> Result<ReplyFuture, SendError>
+        {
+            self.log.lock().unwrap().requests.push((destination, Vec::from(contents)));
+
+            let (tx, rx) = oneshot::channel();
+            let (message_type, content) = match destination {
+                Message_MessageType::TP_REGISTER_REQUEST => {
+                    let mut response = TpRegisterResponse::new();
+                    match self.registration {
+                        Registration::Accept =>
+                            response.set_status(TpRegisterResponse_Status::OK),
+                        Registration::Reject =>
+                            response.set_status(TpRegisterResponse_Status::ERROR),
+                        Registration::Disconnect => return Ok(ReplyFuture::new(rx)),
+                        Registration::Ignore => {
+                            self.log.lock().unwrap().unanswered.push(tx);
+                            return Ok(ReplyFuture::new(rx))
+                        }
+                    }
+                    (Message_MessageType::TP_REGISTER_RESPONSE, response.write_to_bytes().unwrap())
+                }
+                Message_MessageType::TP_UNREGISTER_REQUEST => {
+                    let mut response = TpUnregisterResponse::new();
+                    response.set_status(TpUnregisterResponse_Status::OK);
+                    (Message_MessageType::TP_UNREGISTER_RESPONSE,
+                     response.write_to_bytes().unwrap())
+                }
+                Message_MessageType::TP_STATE_SET_REQUEST => {
+                    let mut response = TpStateSetResponse::new();
+                    response.set_status(TpStateSetResponse_Status::OK);
+                    (Message_MessageType::TP_STATE_SET_RESPONSE,
+                     response.write_to_bytes().unwrap())
+                }
+                _ => return Err(SendError::UnknownError)
+            };
+
+            let mut message = Message::new();
+            message.set_message_type(message_type);
+            message.set_correlation_id(String::from(correlation_id));
+            message.set_content(content);
+            tx.send(Ok(message)).unwrap();
+            Ok(ReplyFuture::new(rx))
+        }
+    }
+
+    /// Creates MockSenders, and keeps the inbound end of each connection so
+    /// that the test can send requests from the validator, or disconnect.
+    struct MockConnection {
+        sender: MockSender,
+        inbound: Arc<Mutex<Vec<UnboundedSender<MessageResult>>>>
+    }
+
+    impl MockConnection {
+        fn new(registration: Registration) -> Self {
+            MockConnection {
+                sender: MockSender {
+                    registration: registration,
+                    log: Arc::new(Mutex::new(MockLog::default()))
+                },
+                inbound: Arc::new(Mutex::new(Vec::new()))
+            }
+        }
+    }
+
+    impl AsyncMessageConnection<MockSender> for MockConnection {
+        fn create_async(&self) -> (MockSender, AsyncMessageReceiver) {
+            let (tx, rx) = unbounded();
+            self.inbound.lock().unwrap().push(tx);
+            (self.sender.clone(), rx)
+        }
+    }
+
+    /// Spawns the processor's future on the pool, returning a handle which
+    /// drops it when aborted.
+    fn spawn(pool: &LocalPool, processor: AsyncTransactionProcessor<MockConnection, MockSender>)
+        -> AbortHandle
+    {
+        let (future, handle) = abortable(processor.run());
+        pool.spawner().spawn_local(future.map(|_| ())).unwrap();
+        handle
+    }
+
+    /// Returns a TpProcessRequest message for the given family version.
+    fn process_message(family_version: &str, correlation_id: &str) -> MessageResult {
+        let mut header = TransactionHeader::new();
+        header.set_family_name(String::from("intkey"));
+        header.set_family_version(String::from(family_version));
+        let mut request = TpProcessRequest::new();
+        request.set_header(header);
+        request.set_context_id(String::from("context"));
+        request.set_payload(b"1".to_vec());
+
+        let mut message = Message::new();
+        message.set_message_type(Message_MessageType::TP_PROCESS_REQUEST);
+        message.set_correlation_id(String::from(correlation_id));
+        message.set_content(request.write_to_bytes().unwrap());
+        Ok(message)
+    }
+
+    /// Returns the family and version of each registration request sent.
+    fn registrations(sender: &MockSender) -> Vec<(String, String)> {
+        sender.requests(Message_MessageType::TP_REGISTER_REQUEST)
+            .iter()
+            .map(|content| {
+                let request: TpRegisterRequest = protobuf::parse_from_bytes(content).unwrap();
+                (String::from(request.get_family()), String::from(request.get_version()))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn registers_each_family_version_once_and_unregisters_on_drop() {
+        let conn = MockConnection::new(Registration::Accept);
+        let sender = conn.sender.clone();
+
+        let mut processor = AsyncTransactionProcessor::with_connection("mock", conn);
+        processor.add_handler(SetHandler::new(&["1.0", "2.0"]));
+        processor.add_handler(SetHandler::new(&["2.0"]));
+
+        let runtime = runtime();
+        let _context = runtime.enter();
+        let mut pool = LocalPool::new();
+        let handle = spawn(&pool, processor);
+        pool.run_until_stalled();
+
+        assert_eq!(registrations(&sender), vec![
+            (String::from("intkey"), String::from("1.0")),
+            (String::from("intkey"), String::from("2.0"))
+        ]);
+        assert!(sender.requests(Message_MessageType::TP_UNREGISTER_REQUEST).is_empty());
+
+        handle.abort();
+        pool.run_until_stalled();
+        assert_eq!(sender.requests(Message_MessageType::TP_UNREGISTER_REQUEST).len(), 1);
+        assert_eq!(sender.closed(), 1);
+    }
+
+    #[test]
+    fn rejected_registration_fails() {
+        let mut processor = AsyncTransactionProcessor::with_connection(
+            "mock", MockConnection::new(Registration::Reject));
+        processor.add_handler(SetHandler::new(&["1.0"]));
+
+        match runtime().block_on(processor.run()) {
+            Err(ProcessorError::RegistrationError { ref family, ref version, .. }) => {
+                assert_eq!(family, "intkey");
+                assert_eq!(version, "1.0");
+            }
+            result => panic!("Expected a RegistrationError, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn applies_transactions_and_replies() {
+        let conn = MockConnection::new(Registration::Accept);
+        let sender = conn.sender.clone();
+        let inbound = conn.inbound.clone();
+
+        let mut processor = AsyncTransactionProcessor::with_connection("mock", conn);
+        processor.add_handler(SetHandler::new(&["1.0"]));
+
+        let runtime = runtime();
+        let _context = runtime.enter();
+        let mut pool = LocalPool::new();
+        let handle = spawn(&pool, processor);
+        pool.run_until_stalled();
+
+        inbound.lock().unwrap()[0].unbounded_send(process_message("1.0", "known")).unwrap();
+        inbound.lock().unwrap()[0].unbounded_send(process_message("9.9", "unknown")).unwrap();
+        pool.run_until_stalled();
+
+        let sets = sender.requests(Message_MessageType::TP_STATE_SET_REQUEST);
+        assert_eq!(sets.len(), 1);
+        let set: TpStateSetRequest = protobuf::parse_from_bytes(&sets[0]).unwrap();
+        assert_eq!(set.get_context_id(), "context");
+        assert_eq!(set.get_entries()[0].get_address(), "abcdef00");
+        assert_eq!(set.get_entries()[0].get_data(), b"1");
+
+        let statuses: Vec<(String, TpProcessResponse_Status)> = sender.replies()
+            .iter()
+            .map(|&(message_type, ref correlation_id, ref content)| {
+                assert_eq!(message_type, Message_MessageType::TP_PROCESS_RESPONSE);
+                let response: TpProcessResponse = protobuf::parse_from_bytes(content).unwrap();
+                (correlation_id.clone(), response.get_status())
+            })
+            .collect();
+        assert!(statuses.contains(&(String::from("known"), TpProcessResponse_Status::OK)));
+        assert!(statuses.contains(
+            &(String::from("unknown"), TpProcessResponse_Status::INTERNAL_ERROR)));
+
+        handle.abort();
+        pool.run_until_stalled();
+    }
+
+    #[test]
+    fn reregisters_after_disconnect() {
+        let conn = MockConnection::new(Registration::Accept);
+        let sender = conn.sender.clone();
+        let inbound = conn.inbound.clone();
+
+        let mut processor = AsyncTransactionProcessor::with_connection("mock", conn);
+        processor.add_handler(SetHandler::new(&["1.0"]));
+        processor.set_reconnect_policy(ReconnectPolicy::new()
+                                       .initial_delay(Duration::from_millis(0)));
+
+        let runtime = runtime();
+        let _context = runtime.enter();
+        let mut pool = LocalPool::new();
+        let handle = spawn(&pool, processor);
+        pool.run_until_stalled();
+        assert_eq!(registrations(&sender).len(), 1);
+
+        // Dropping the inbound end disconnects the first connection
+        inbound.lock().unwrap().clear();
+        pool.run_until_stalled();
+
+        assert_eq!(inbound.lock().unwrap().len(), 1);
+        assert_eq!(registrations(&sender).len(), 2);
+        assert_eq!(sender.closed(), 1);
+
+        // Requests on the new connection are applied
+        inbound.lock().unwrap()[0].unbounded_send(process_message("1.0", "after")).unwrap();
+        pool.run_until_stalled();
+        assert_eq!(sender.replies().len(), 1);
+
+        handle.abort();
+        pool.run_until_stalled();
+    }
+
+    #[test]
+    fn gives_up_after_max_attempts() {
+        let conn = MockConnection::new(Registration::Disconnect);
+        let inbound = conn.inbound.clone();
+
+        let mut processor = AsyncTransactionProcessor::with_connection("mock", conn);
+        processor.add_handler(SetHandler::new(&["1.0"]));
+        processor.set_reconnect_policy(ReconnectPolicy::new()
+                                       .initial_delay(Duration::from_millis(1))
+                                       .max_attempts(2));
+
+        match runtime().block_on(processor.run()) {
+            Err(ProcessorError::ConnectionError(_)) => (),
+            result => panic!("Expected a ConnectionError, got {:?}", result)
+        }
+        // The first connection, then the two reconnect attempts
+        assert_eq!(inbound.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn retries_unanswered_registration() {
+        let conn = MockConnection::new(Registration::Ignore);
+        let sender = conn.sender.clone();
+        let inbound = conn.inbound.clone();
+
+        let mut processor = AsyncTransactionProcessor::with_connection("mock", conn);
+        processor.add_handler(SetHandler::new(&["1.0"]));
+        processor.set_register_timeout(Duration::from_millis(10));
+        processor.set_reconnect_policy(ReconnectPolicy::new()
+                                       .initial_delay(Duration::from_millis(1))
+                                       .max_attempts(1));
+
+        match runtime().block_on(processor.run()) {
+            Err(ProcessorError::ConnectionError(_)) => (),
+            result => panic!("Expected a ConnectionError, got {:?}", result)
+        }
+        // Each connection is closed once its registration times out
+        assert_eq!(inbound.lock().unwrap().len(), 2);
+        assert_eq!(registrations(&sender).len(), 2);
+        assert_eq!(sender.closed(), 2);
+    }
+}
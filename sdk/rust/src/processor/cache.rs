@@ -0,0 +1,338 @@
+/*
+ * Copyright 2017 Bitwise IO, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+use std::collections::HashMap;
+
+use codec::Codec;
+
+use super::handler::ApplyError;
+use super::handler::ContextError;
+use super::handler::TransactionContext;
+
+/// CachedTransactionContext wraps a TransactionContext for the life of one
+/// apply. Reads are memoized, so each address is fetched from the validator
+/// at most once, and writes and deletes are buffered until flush is called,
+/// when they are sent as a single TpStateSetRequest and a single
+/// TpStateDeleteRequest.
+///
+/// Receipt data and events are not buffered.
+pub struct CachedTransactionContext<'a> {
+    context: &'a mut TransactionContext,
+    // The state as last read from the validator; None if unset
+    reads: HashMap<String, Option<Vec<u8>>>,
+    // The pending changes; None if deleted
+    writes: HashMap<String, Option<Vec<u8>>>
+}
+
+impl<'a> CachedTransactionContext<'a> {
+    /// Creates a cache over the given context, with no reads or pending
+    /// writes.
+    ///
+    /// # Arguments
+    ///
+    /// * `context` - the context passed to the handler's apply
+    pub fn new(context: &'a mut TransactionContext) -> CachedTransactionContext<'a> {
+        CachedTransactionContext {
+            context: context,
+            reads: HashMap::new(),
+            writes: HashMap::new()
+        }
+    }
+
+    /// Calls the given function with a cache over the context, flushing
+    /// its writes and deletes only if the function returns Ok. Handlers can
+    /// use this to give their apply transactional write semantics:
+    ///
+    /// ```ignore
+    /// fn apply(&self, request: &TpProcessRequest, context: &mut TransactionContext)
+    ///     -> Result<(), ApplyError>
+    /// {
+    ///     CachedTransactionContext::apply(context, |state| {
+    ///         let value = state.get_state(&address)?;
+    ///         state.set_state(&address, &new_value)?;
+    ///         Ok(())
+    ///     })
+    /// }
+    /// ```
+    ///
+    /// # Arguments
+    ///
+    /// * `context` - the context passed to the handler's apply
+    /// * `f` - the function which reads and writes state through the cache
+    pub fn apply<F>(context: &'a mut TransactionContext, f: F) -> Result<(), ApplyError>
+        where F: FnOnce(&mut CachedTransactionContext<'a>) -> Result<(), ApplyError>
+    {
+        let mut cache = CachedTransactionContext::new(context);
+        f(&mut cache)?;
+        cache.flush()?;
+        Ok(())
+    }
+
+    /// get_state returns the data at the given address, including any
+    /// pending write or delete. Returns None if the address is not set.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - the address to fetch
+    pub fn get_state(&mut self, address: &str) -> Result<Option<Vec<u8>>, ContextError> {
+        let mut entries = self.get_state_entries(&[String::from(address)])?;
+        Ok(entries.remove(address))
+    }
+
+    /// get_state_entries returns the data at each of the given addresses
+    /// that is set, including any pending writes or deletes. Addresses not
+    /// read before are fetched from the validator in a single request.
+    ///
+    /// # Arguments
+    ///
+    /// * `addresses` - the addresses to fetch
+    pub fn get_state_entries(&mut self, addresses: &[String])
+        -> Result<HashMap<String, Vec<u8>>, ContextError>
+    {
+        self.context.check_inputs(addresses)?;
+
+        let mut unread: Vec<String> = addresses.iter()
+            .filter(|address| {
+                !self.writes.contains_key(*address) && !self.reads.contains_key(*address)
+            })
+            .cloned()
+            .collect();
+        unread.sort();
+        unread.dedup();
+
+        if !unread.is_empty() {
+            let mut fetched = self.context.get_state_entries(&unread)?;
+            for address in unread {
+                let data = fetched.remove(&address);
+                self.reads.insert(address, data);
+            }
+        }
+
+        let mut entries = HashMap::new();
+        for address in addresses {
+            let data = match self.writes.get(address) {
+                Some(data) => data,
+                None => &self.reads[address]
+            };
+            if let Some(ref data) = *data {
+                entries.insert(address.clone(), data.clone());
+            }
+        }
+        Ok(entries)
+    }
+
+    /// get_typed returns the value at the given address, decoded with the
+    /// given codec. Returns None if the address is not set.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - the address to fetch
+    /// * `codec` - the codec the value was stored with
+    pub fn get_typed<T, C: Codec<T>>(&mut self, address: &str, codec: &C)
+        -> Result<Option<T>, ContextError>
+    {
+        match self.get_state(address)? {
+            Some(data) => Ok(Some(codec.decode(&data)?)),
+            None => Ok(None)
+        }
+    }
+
+    /// set_typed buffers a write of the value, encoded with the given
+    /// codec, to the address.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - address of where to store the value
+    /// * `value` - the value to store at the address
+    /// * `codec` - the codec to store the value with
+    pub fn set_typed<T, C: Codec<T>>(&mut self, address: &str, value: &T, codec: &C)
+        -> Result<(), ContextError>
+    {
+        let data = codec.encode(value)?;
+        self.set_state(address, &data)
+    }
+
+    /// set_state buffers a write of the given payload to the address.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - address of where to store the data
+    /// * `payload` - payload is the data to store at the address
+    pub fn set_state(&mut self, address: &str, payload: &[u8]) -> Result<(), ContextError> {
+        self.set_state_entries(vec![(String::from(address), Vec::from(payload))])
+    }
+
+    /// set_state_entries buffers a write of each address in the list to
+    /// its corresponding value.
+    ///
+    /// # Arguments
+    ///
+    /// * `entries` - the address and data pairs to store
+    pub fn set_state_entries(&mut self, entries: Vec<(String, Vec<u8>)>) -> Result<(), ContextError> {
+        let addresses: Vec<String> = entries.iter().map(|&(ref address, _)| address.clone()).collect();
+        self.context.check_outputs(&addresses)?;
+
+        for (address, data) in entries {
+            self.writes.insert(address, Some(data));
+        }
+        Ok(())
+    }
+
+    /// delete_state buffers a delete of each of the given addresses.
+    ///
+    /// # Arguments
+    ///
+    /// * `addresses` - the addresses to delete
+    pub fn delete_state(&mut self, addresses: Vec<String>) -> Result<(), ContextError> {
+        self.context.check_outputs(&addresses)?;
+
+        for address in addresses {
+            self.writes.insert(address, None);
+        }
+        Ok(())
+    }
+
+    /// add_receipt_data adds a blob to the execution result for this
+    /// transaction. It is sent to the validator immediately.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - the data to add
+    pub fn add_receipt_data(&mut self, data: &[u8]) -> Result<(), ContextError> {
+        self.context.add_receipt_data(data)
+    }
+
+    /// add_event adds a new event to the execution result for this
+    /// transaction. It is sent to the validator immediately. See
+    /// TransactionContext::add_event for a description of the arguments.
+    pub fn add_event(&mut self, event_type: String, attributes: Vec<(String, String)>,
+                     data: &[u8])
+        -> Result<(), ContextError>
+    {
+        self.context.add_event(event_type, attributes, data)
+    }
+
+    /// Sends the pending writes to the validator in a single
+    /// TpStateSetRequest, and the pending deletes in a single
+    /// TpStateDeleteRequest. The flushed values remain cached.
+    pub fn flush(&mut self) -> Result<(), ContextError> {
+        let mut sets = Vec::new();
+        let mut deletes = Vec::new();
+        for (address, data) in self.writes.drain() {
+            match data {
+                Some(ref data) => sets.push((address.clone(), data.clone())),
+                None => deletes.push(address.clone())
+            }
+            self.reads.insert(address, data);
+        }
+
+        if !sets.is_empty() {
+            self.context.set_state_entries(sets)?;
+        }
+        if !deletes.is_empty() {
+            self.context.delete_state(deletes)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use messages::processor::TpProcessRequest;
+    use processor::handler::ApplyError;
+    use processor::handler::TransactionContext;
+    use processor::handler::TransactionHandler;
+    use testing::MockValidator;
+
+    use super::CachedTransactionContext;
+
+    /// Swaps the values at "a" and "b", deletes "c", then fails if the
+    /// payload is "fail".
+    struct SwapHandler;
+
+    impl TransactionHandler for SwapHandler {
+        fn family_name(&self) -> String {
+            String::from("swap")
+        }
+
+        fn family_versions(&self) -> Vec<String> {
+            vec![String::from("1.0")]
+        }
+
+        fn namespaces(&self) -> Vec<String> {
+            vec![String::from("abcdef")]
+        }
+
+        fn apply(&self, request: &TpProcessRequest, context: &mut TransactionContext)
+            -> Result<(), ApplyError>
+        {
+            CachedTransactionContext::apply(context, |state| {
+                let a = state.get_state("a")?.unwrap_or_default();
+                let b = state.get_state("b")?.unwrap_or_default();
+                state.set_state("a", &b)?;
+                state.set_state("b", &a)?;
+                assert_eq!(state.get_state("a")?, Some(b));
+
+                state.delete_state(vec![String::from("c")])?;
+                assert_eq!(state.get_state("c")?, None);
+
+                if request.get_payload() == b"fail" {
+                    return Err(ApplyError::InvalidTransaction(String::from("Failed")));
+                }
+                Ok(())
+            })
+        }
+    }
+
+    fn seed(validator: &MockValidator) {
+        validator.set_state("a", b"1");
+        validator.set_state("b", b"2");
+        validator.set_state("c", b"3");
+    }
+
+    #[test]
+    fn flushes_on_ok() {
+        let validator = MockValidator::new();
+        seed(&validator);
+
+        validator.apply(&SwapHandler, &TpProcessRequest::new()).unwrap();
+
+        let mut expected = HashMap::new();
+        expected.insert(String::from("a"), b"2".to_vec());
+        expected.insert(String::from("b"), b"1".to_vec());
+        assert_eq!(validator.state(), expected);
+    }
+
+    #[test]
+    fn does_not_flush_on_error() {
+        let validator = MockValidator::new();
+        seed(&validator);
+
+        // The context is used directly, so that the MockValidator does not
+        // roll back the state itself.
+        let mut context = validator.context("context");
+        let mut request = TpProcessRequest::new();
+        request.set_payload(b"fail".to_vec());
+        assert!(SwapHandler.apply(&request, &mut context).is_err());
+
+        assert_eq!(validator.get_state("a"), Some(b"1".to_vec()));
+        assert_eq!(validator.get_state("b"), Some(b"2".to_vec()));
+        assert_eq!(validator.get_state("c"), Some(b"3".to_vec()));
+    }
+}
@@ -0,0 +1,145 @@
+/*
+ * Copyright 2017 Bitwise IO, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+use protobuf::Message as M;
+
+use std::collections::HashMap;
+use std::error::Error;
+
+use messages::network::PingResponse;
+use messages::validator::Message;
+use messages::validator::Message_MessageType;
+
+/// The type and content of the message sent back to the validator in reply
+/// to a request.
+pub type MessageReply = (Message_MessageType, Vec<u8>);
+
+/// A MessageHandler answers one type of request sent by the validator,
+/// other than TpProcessRequests, which are always routed to the
+/// TransactionHandlers.
+pub trait MessageHandler: Send + Sync {
+    /// Handles the message, returning the reply to send the validator, if
+    /// any.
+    fn handle(&self, message: &Message) -> Option<MessageReply>;
+}
+
+/// Replies to each PingRequest with a PingResponse.
+pub struct PingHandler;
+
+impl MessageHandler for PingHandler {
+    fn handle(&self, _message: &Message) -> Option<MessageReply> {
+        info!("sending PingResponse");
+        match PingResponse::new().write_to_bytes() {
+            Ok(serialized) => Some((Message_MessageType::PING_RESPONSE, serialized)),
+            Err(err) => {
+                error!("Serialization failed: {}", err.description());
+                None
+            }
+        }
+    }
+}
+
+/// Routes requests from the validator to the MessageHandler registered for
+/// their message type. PingRequests are handled by default.
+pub struct MessageDispatcher<'a> {
+    handlers: HashMap<Message_MessageType, Box<MessageHandler + 'a>>
+}
+
+impl<'a> MessageDispatcher<'a> {
+    pub fn new() -> MessageDispatcher<'a> {
+        let mut dispatcher = MessageDispatcher {
+            handlers: HashMap::new()
+        };
+        dispatcher.add_handler(Message_MessageType::PING_REQUEST, Box::new(PingHandler));
+        dispatcher
+    }
+
+    /// Registers the handler for the message type, replacing any handler
+    /// already registered for it.
+    ///
+    /// # Arguments
+    ///
+    /// * message_type - the type of request the handler answers
+    /// * handler - the handler
+    pub fn add_handler(&mut self, message_type: Message_MessageType,
+                       handler: Box<MessageHandler + 'a>)
+    {
+        self.handlers.insert(message_type, handler);
+    }
+
+    /// Passes the message to the handler registered for its type, returning
+    /// the handler's reply. Messages of other types are logged and dropped.
+    pub fn dispatch(&self, message: &Message) -> Option<MessageReply> {
+        match self.handlers.get(&message.get_message_type()) {
+            Some(handler) => handler.handle(message),
+            None => {
+                info!("Transaction Processor recieved invalid message type: {:?}",
+                      message.get_message_type());
+                None
+            }
+        }
+    }
+}
+
+impl<'a> Default for MessageDispatcher<'a> {
+    fn default() -> Self {
+        MessageDispatcher::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use messages::validator::Message;
+    use messages::validator::Message_MessageType;
+
+    use super::MessageDispatcher;
+    use super::MessageHandler;
+    use super::MessageReply;
+
+    struct EchoHandler;
+
+    impl MessageHandler for EchoHandler {
+        fn handle(&self, message: &Message) -> Option<MessageReply> {
+            Some((Message_MessageType::NETWORK_ACK, Vec::from(message.get_content())))
+        }
+    }
+
+    fn make_message(message_type: Message_MessageType) -> Message {
+        let mut message = Message::new();
+        message.set_message_type(message_type);
+        message.set_content(vec![1, 2, 3]);
+        message
+    }
+
+    #[test]
+    fn ping_is_answered_with_ping_response() {
+        let dispatcher = MessageDispatcher::new();
+        let reply = dispatcher.dispatch(&make_message(Message_MessageType::PING_REQUEST));
+        assert_eq!(reply.map(|(message_type, _)| message_type),
+                   Some(Message_MessageType::PING_RESPONSE));
+    }
+
+    #[test]
+    fn dispatch_to_registered_handler() {
+        let mut dispatcher = MessageDispatcher::new();
+        assert!(dispatcher.dispatch(&make_message(Message_MessageType::NETWORK_CONNECT)).is_none());
+
+        dispatcher.add_handler(Message_MessageType::NETWORK_CONNECT, Box::new(EchoHandler));
+        assert_eq!(dispatcher.dispatch(&make_message(Message_MessageType::NETWORK_CONNECT)),
+                   Some((Message_MessageType::NETWORK_ACK, vec![1, 2, 3])));
+    }
+}
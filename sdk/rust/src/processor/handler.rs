@@ -20,45 +20,70 @@ extern crate protobuf;
 extern crate rand;
 
 use protobuf::Message as M;
-use protobuf::RepeatedField;
 
 use std::error::Error as StdError;
 use std;
-use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::cmp;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use messages::processor::TpProcessRequest;
-use messages::state_context::*;
 use messages::validator::Message_MessageType;
-use messages::events::Event;
-use messages::events::Event_Attribute;
 
 use messaging::stream::MessageSender;
 use messaging::stream::SendError;
 use messaging::stream::ReceiveError;
-use messaging::zmq_stream::ZmqMessageSender;
+
+use addressing;
+use codec::Codec;
+use codec::CodecError;
 
 use super::generate_correlation_id;
+use super::requests::*;
 
 #[derive(Debug)]
 pub enum ApplyError {
     /// Returned for an Invalid Transaction.
     InvalidTransaction(String),
     /// Returned when an internal error occurs during transaction processing.
-    InternalError(String)
+    InternalError(String),
+    /// Returned for an Invalid Transaction, with application-specific data
+    /// which is sent to the validator as the response's extended_data.
+    InvalidTransactionWithData(String, Vec<u8>),
+    /// Returned when an internal error occurs during transaction
+    /// processing, with application-specific data which is sent to the
+    /// validator as the response's extended_data.
+    InternalErrorWithData(String, Vec<u8>)
+}
+
+impl ApplyError {
+    /// Returns the application-specific data carried by the error, if any.
+    pub fn extended_data(&self) -> Option<&[u8]> {
+        match *self {
+            ApplyError::InvalidTransactionWithData(_, ref data) => Some(data),
+            ApplyError::InternalErrorWithData(_, ref data) => Some(data),
+            _ => None
+        }
+    }
 }
 
 impl std::error::Error for ApplyError {
     fn description(&self) -> &str {
         match *self {
             ApplyError::InvalidTransaction(ref msg) => msg,
-            ApplyError::InternalError(ref msg) => msg
+            ApplyError::InternalError(ref msg) => msg,
+            ApplyError::InvalidTransactionWithData(ref msg, _) => msg,
+            ApplyError::InternalErrorWithData(ref msg, _) => msg
         }
     }
 
     fn cause(&self) -> Option<&std::error::Error> {
         match *self {
             ApplyError::InvalidTransaction(_) => None,
-            ApplyError::InternalError(_) => None
+            ApplyError::InternalError(_) => None,
+            ApplyError::InvalidTransactionWithData(..) => None,
+            ApplyError::InternalErrorWithData(..) => None
         }
     }
 }
@@ -69,6 +94,10 @@ impl std::fmt::Display for ApplyError {
             ApplyError::InvalidTransaction(ref s) =>
                 write!(f, "InvalidTransaction: {}", s),
             ApplyError::InternalError(ref s) =>
+                write!(f, "InternalError: {}", s),
+            ApplyError::InvalidTransactionWithData(ref s, _) =>
+                write!(f, "InvalidTransaction: {}", s),
+            ApplyError::InternalErrorWithData(ref s, _) =>
                 write!(f, "InternalError: {}", s)
         }
     }
@@ -83,11 +112,16 @@ pub enum ContextError {
     /// Returned when there is an issues setting receipt data or events.
     TransactionReceiptError(String),
     /// Returned when a ProtobufError is returned during serializing
-    SerializationError(Box<StdError>),
+    SerializationError(Box<StdError + Send + Sync>),
     /// Returned when an error is returned when sending a message
-    SendError(Box<StdError>),
+    SendError(Box<StdError + Send + Sync>),
     /// Returned when an error is returned when sending a message
-    ReceiveError(Box<StdError>),
+    ReceiveError(Box<StdError + Send + Sync>),
+    /// Returned when a typed value cannot be encoded or decoded
+    CodecError(Box<StdError + Send + Sync>),
+    /// Returned when the validator does not reply before the request
+    /// timeout or apply deadline
+    TimeoutError(String),
 }
 
 impl std::error::Error for ContextError {
@@ -99,6 +133,8 @@ impl std::error::Error for ContextError {
             ContextError::SerializationError(ref err) => err.description(),
             ContextError::SendError(ref err) => err.description(),
             ContextError::ReceiveError(ref err) => err.description(),
+            ContextError::CodecError(ref err) => err.description(),
+            ContextError::TimeoutError(ref msg) => msg,
         }
     }
 
@@ -107,9 +143,11 @@ impl std::error::Error for ContextError {
             ContextError::AuthorizationError(_) => None,
             ContextError::ResponseAttributeError(_) => None,
             ContextError::TransactionReceiptError(_) => None,
-            ContextError::SerializationError(ref err) => Some(err.borrow()),
-            ContextError::SendError(ref err) => Some(err.borrow()),
-            ContextError::ReceiveError(ref err) => Some(err.borrow()),
+            ContextError::SerializationError(ref err) => Some(&**err),
+            ContextError::SendError(ref err) => Some(&**err),
+            ContextError::ReceiveError(ref err) => Some(&**err),
+            ContextError::CodecError(ref err) => Some(&**err),
+            ContextError::TimeoutError(_) => None,
         }
     }
 }
@@ -129,6 +167,10 @@ impl std::fmt::Display for ContextError {
                 write!(f, "SendError: {}", err.description()),
             ContextError::ReceiveError(ref err) =>
                 write!(f, "ReceiveError: {}", err.description()),
+            ContextError::CodecError(ref err) =>
+                write!(f, "CodecError: {}", err.description()),
+            ContextError::TimeoutError(ref s) =>
+                write!(f, "TimeoutError: {}", s),
         }
     }
 }
@@ -136,7 +178,9 @@ impl std::fmt::Display for ContextError {
 impl From<ContextError> for ApplyError {
     fn from(context_error: ContextError) -> Self {
         match context_error {
-            ContextError::TransactionReceiptError(..) =>
+            ContextError::TransactionReceiptError(..) |
+            ContextError::CodecError(..) |
+            ContextError::TimeoutError(..) =>
                 ApplyError::InternalError(format!("{}", context_error)),
             _ => ApplyError::InvalidTransaction(format!("{}", context_error))
         }
@@ -161,10 +205,51 @@ impl From<ReceiveError> for ContextError {
     }
 }
 
+impl From<CodecError> for ContextError {
+    fn from(e: CodecError) -> Self {
+        ContextError::CodecError(Box::new(e))
+    }
+}
+
+/// The address prefixes a transaction declared in its header, which a
+/// strict TransactionContext checks each request against.
+#[derive(Clone, Debug)]
+struct DeclaredAddresses {
+    inputs: Vec<String>,
+    outputs: Vec<String>
+}
+
+impl DeclaredAddresses {
+    fn check_inputs(&self, addresses: &[String]) -> Result<(), ContextError> {
+        check_declared("inputs", &self.inputs, addresses)
+    }
+
+    fn check_outputs(&self, addresses: &[String]) -> Result<(), ContextError> {
+        check_declared("outputs", &self.outputs, addresses)
+    }
+}
+
+/// Returns an AuthorizationError naming the first address which does not
+/// start with one of the declared prefixes.
+fn check_declared(kind: &str, prefixes: &[String], addresses: &[String])
+    -> Result<(), ContextError>
+{
+    for address in addresses {
+        if !addressing::matches_prefix(address, prefixes) {
+            return Err(ContextError::AuthorizationError(format!(
+                "Address {} is not in the declared {}: {:?}", address, kind, prefixes)));
+        }
+    }
+    Ok(())
+}
+
 #[derive(Clone)]
 pub struct TransactionContext {
     context_id: String,
-    sender: ZmqMessageSender
+    sender: Arc<Mutex<MessageSender + Send>>,
+    declared: Option<DeclaredAddresses>,
+    request_timeout: Option<Duration>,
+    deadline: Option<Instant>
 }
 
 impl TransactionContext {
@@ -174,92 +259,159 @@ impl TransactionContext {
     ///
     /// # Arguments
     ///
-    /// * `sender` - for client grpc communication
     /// * `context_id` - the context_id passed in from the validator
-    pub fn new(context_id: &str, sender: ZmqMessageSender) -> TransactionContext {
+    /// * `sender` - the sender for the connection to the validator
+    pub fn new<MS>(context_id: &str, sender: MS) -> TransactionContext
+        where MS: MessageSender + Send + 'static
+    {
         TransactionContext{
             context_id: String::from(context_id),
-            sender: sender
+            sender: Arc::new(Mutex::new(sender)),
+            declared: None,
+            request_timeout: None,
+            deadline: None
         }
     }
 
-    /// get_state queries the validator state for data at each of the
-    /// addresses in the given list. The addresses that have been set
-    /// are returned.
+    /// Sets how long each request waits for the validator's reply before
+    /// failing with a TimeoutError. By default, requests wait forever.
     ///
     /// # Arguments
     ///
-    /// * `addresses` - the addresses to fetch
+    /// * `timeout` - the longest wait for each reply
+    pub fn set_request_timeout(&mut self, timeout: Duration) {
+        self.request_timeout = Some(timeout);
+    }
+
+    /// Sets the time by which every request must be answered. Requests
+    /// made, or still waiting, after the deadline fail with a
+    /// TimeoutError.
+    ///
+    /// # Arguments
+    ///
+    /// * `deadline` - the time by which the transaction must be applied
+    pub fn set_deadline(&mut self, deadline: Instant) {
+        self.deadline = Some(deadline);
+    }
+
+    /// Puts the context in strict mode, in which every get is checked
+    /// against the given inputs, and every set and delete against the given
+    /// outputs, before it is sent to the validator. An address is allowed
+    /// if it starts with one of the prefixes; any other address fails with
+    /// an AuthorizationError.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - the transaction header's inputs
+    /// * `outputs` - the transaction header's outputs
+    pub fn set_declared_addresses(&mut self, inputs: &[String], outputs: &[String]) {
+        self.declared = Some(DeclaredAddresses {
+            inputs: Vec::from(inputs),
+            outputs: Vec::from(outputs)
+        });
+    }
+
+    /// Returns an AuthorizationError if the context is strict and any of
+    /// the addresses is not within the declared inputs.
+    pub(super) fn check_inputs(&self, addresses: &[String]) -> Result<(), ContextError> {
+        match self.declared {
+            Some(ref declared) => declared.check_inputs(addresses),
+            None => Ok(())
+        }
+    }
+
+    /// Returns an AuthorizationError if the context is strict and any of
+    /// the addresses is not within the declared outputs.
+    pub(super) fn check_outputs(&self, addresses: &[String]) -> Result<(), ContextError> {
+        match self.declared {
+            Some(ref declared) => declared.check_outputs(addresses),
+            None => Ok(())
+        }
+    }
+
+    /// get_state queries the validator state for data at the given
+    /// address. Returns None if the address has not been set.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - the address to fetch
     pub fn get_state(&mut self, address: &str) -> Result<Option<Vec<u8>>, ContextError> {
-        let mut request = TpStateGetRequest::new();
-        request.set_context_id(self.context_id.clone());
-        request.set_addresses(RepeatedField::from_vec(vec!(String::from(address))));
-        let serialized = request.write_to_bytes()?;
-        let x : &[u8] = &serialized;
+        let mut entries = self.get_state_entries(&[String::from(address)])?;
+        Ok(entries.remove(address))
+    }
 
-        let mut future = self.sender.send(
-            Message_MessageType::TP_STATE_GET_REQUEST,
-            &generate_correlation_id(),
-            x)?;
-
-        let response: TpStateGetResponse = protobuf::parse_from_bytes(future.get()?.get_content())?;
-        match response.get_status() {
-            TpStateGetResponse_Status::OK => {
-                let entry = match response.get_entries().first(){
-                    Some(x) => x,
-                    None => return Err(ContextError::ResponseAttributeError(String::from("TpStateGetResponse is missing entries.")))
-                };
-                match entry.get_data().len() {
-                    0 => Ok(None),
-                    _ => Ok(Some(Vec::from(entry.get_data())))
-                }
-            },
-            TpStateGetResponse_Status::AUTHORIZATION_ERROR => {
-                Err(ContextError::AuthorizationError(format!("Tried to get unauthorized address: {}", address)))
-            },
-            TpStateGetResponse_Status::STATUS_UNSET => {
-                Err(ContextError::ResponseAttributeError(String::from("Status was not set for TpStateGetResponse")))
-            }
+    /// get_state_entries queries the validator state for data at each of
+    /// the addresses in the given list, in a single request. The addresses
+    /// that have been set are returned, mapped to their data.
+    ///
+    /// # Arguments
+    ///
+    /// * `addresses` - the addresses to fetch
+    pub fn get_state_entries(&mut self, addresses: &[String])
+        -> Result<HashMap<String, Vec<u8>>, ContextError>
+    {
+        self.check_inputs(addresses)?;
+        let request = state_get_request(&self.context_id, addresses);
+        let content = self.send_request(Message_MessageType::TP_STATE_GET_REQUEST, &request)?;
+        decode_state_get_response(addresses, &content)
+    }
+
+    /// get_typed queries the validator state for data at the given address,
+    /// decoding it with the given codec. Returns None if the address has
+    /// not been set.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - the address to fetch
+    /// * `codec` - the codec the value was stored with
+    pub fn get_typed<T, C: Codec<T>>(&mut self, address: &str, codec: &C)
+        -> Result<Option<T>, ContextError>
+    {
+        match self.get_state(address)? {
+            Some(data) => Ok(Some(codec.decode(&data)?)),
+            None => Ok(None)
         }
     }
 
-    /// set_state requests that each address in the provided map be
-    /// set in validator state to its corresponding value.
+    /// set_typed requests that the provided address be set in validator
+    /// state to the value, encoded with the given codec.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - address of where to store the value
+    /// * `value` - the value to store at the address
+    /// * `codec` - the codec to store the value with
+    pub fn set_typed<T, C: Codec<T>>(&mut self, address: &str, value: &T, codec: &C)
+        -> Result<(), ContextError>
+    {
+        let data = codec.encode(value)?;
+        self.set_state(address, &data)
+    }
+
+    /// set_state requests that the provided address be set in validator
+    /// state to the given payload.
     ///
     /// # Arguments
     ///
     /// * `address` - address of where to store the data
     /// * `paylaod` - payload is the data to store at the address
     pub fn set_state(&mut self, address: &str, payload: &[u8]) -> Result<(), ContextError> {
-        let mut entry = TpStateEntry::new();
-        entry.set_address(String::from(address));
-        entry.set_data(Vec::from(payload));
-
-        let mut request = TpStateSetRequest::new();
-        request.set_context_id(self.context_id.clone());
-        request.set_entries(RepeatedField::from_slice(&[entry]));
-        let serialized = request.write_to_bytes()?;
-        let x : &[u8] = &serialized;
-
-        let mut future = self.sender.send(
-            Message_MessageType::TP_STATE_SET_REQUEST,
-            &generate_correlation_id(),
-            x)?;
-
-        let response: TpStateSetResponse = protobuf::parse_from_bytes(future.get()?.get_content())?;
-        match response.get_status() {
-            TpStateSetResponse_Status::OK => {
-                Ok(())
-            },
-            TpStateSetResponse_Status::AUTHORIZATION_ERROR => {
-                Err(ContextError::AuthorizationError(format!("Tried to set unauthorized address: {}", address)))
-            },
-            TpStateSetResponse_Status::STATUS_UNSET => {
-                Err(ContextError::ResponseAttributeError(String::from("Status was not set for TpStateSetResponse")))
-            }
-        }
+        self.set_state_entries(vec![(String::from(address), Vec::from(payload))])
     }
 
+    /// set_state_entries requests that each address in the provided list be
+    /// set in validator state to its corresponding value, in a single
+    /// request.
+    ///
+    /// # Arguments
+    ///
+    /// * `entries` - the address and data pairs to store
+    pub fn set_state_entries(&mut self, entries: Vec<(String, Vec<u8>)>) -> Result<(), ContextError> {
+        let (addresses, request) = state_set_request(&self.context_id, entries);
+        self.check_outputs(&addresses)?;
+        let content = self.send_request(Message_MessageType::TP_STATE_SET_REQUEST, &request)?;
+        decode_state_set_response(&addresses, &content)
+    }
 
     /// delete_state requests that each of the provided addresses be unset
     /// in validator state. A list of successfully deleted addresses
@@ -269,30 +421,10 @@ impl TransactionContext {
     ///
     /// * `addresses` - the addresses to fetch
     pub fn delete_state(&mut self, addresses: Vec<String>) -> Result<Option<Vec<String>>, ContextError> {
-        let mut request = TpStateDeleteRequest::new();
-        request.set_context_id(self.context_id.clone());
-        request.set_addresses(RepeatedField::from_vec(addresses.clone()));
-
-        let serialized = request.write_to_bytes()?;
-        let x : &[u8] = &serialized;
-
-        let mut future = self.sender.send(
-            Message_MessageType::TP_STATE_DELETE_REQUEST,
-            &generate_correlation_id(),
-            x)?;
-
-        let response: TpStateDeleteResponse = protobuf::parse_from_bytes(future.get()?.get_content())?;
-        match response.get_status() {
-            TpStateDeleteResponse_Status::OK => {
-                Ok(Some(Vec::from(response.get_addresses())))
-            },
-            TpStateDeleteResponse_Status::AUTHORIZATION_ERROR => {
-                Err(ContextError::AuthorizationError(format!("Tried to delete unauthorized address: {:?}", addresses)))
-            },
-            TpStateDeleteResponse_Status::STATUS_UNSET => {
-                Err(ContextError::ResponseAttributeError(String::from("Status was not set for TpStateDeleteResponse")))
-            }
-        }
+        self.check_outputs(&addresses)?;
+        let request = state_delete_request(&self.context_id, &addresses);
+        let content = self.send_request(Message_MessageType::TP_STATE_DELETE_REQUEST, &request)?;
+        decode_state_delete_response(&addresses, &content)
     }
 
     /// add_receipt_data adds a blob to the execution result for this transaction
@@ -301,30 +433,24 @@ impl TransactionContext {
     ///
     /// * `data` - the data to add
     pub fn add_receipt_data(&mut self, data: &[u8]) ->  Result<(), ContextError> {
-        let mut request = TpReceiptAddDataRequest::new();
-        request.set_context_id(self.context_id.clone());
-        request.set_data(Vec::from(data));
-
-        let serialized = request.write_to_bytes()?;
-        let x : &[u8] = &serialized;
+        let request = receipt_add_data_request(&self.context_id, data);
+        let content = self.send_request(Message_MessageType::TP_RECEIPT_ADD_DATA_REQUEST, &request)?;
+        decode_receipt_add_data_response(data, &content)
+    }
 
-        let mut future = self.sender.send(
-            Message_MessageType::TP_RECEIPT_ADD_DATA_REQUEST,
-            &generate_correlation_id(),
-            x)?;
-
-        let response: TpReceiptAddDataResponse = protobuf::parse_from_bytes(future.get()?.get_content())?;
-        match response.get_status() {
-            TpReceiptAddDataResponse_Status::OK => {
-                Ok(())
-            },
-            TpReceiptAddDataResponse_Status::ERROR => {
-                Err(ContextError::TransactionReceiptError(format!("Failed to add receipt data {:?}", data)))
-            },
-            TpReceiptAddDataResponse_Status::STATUS_UNSET => {
-                Err(ContextError::ResponseAttributeError(String::from("Status was not set for TpReceiptAddDataResponse")))
-            }
-        }
+    /// add_typed_receipt_data adds a value to the execution result for this
+    /// transaction, encoded with the given codec. Clients can decode it
+    /// again with Receipt::decode_data and the same codec.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - the value to add
+    /// * `codec` - the codec to encode the value with
+    pub fn add_typed_receipt_data<T, C: Codec<T>>(&mut self, value: &T, codec: &C)
+        -> Result<(), ContextError>
+    {
+        let data = codec.encode(value)?;
+        self.add_receipt_data(&data)
     }
 
     /// add_event adds a new event to the execution result for this transaction.
@@ -339,51 +465,66 @@ impl TransactionContext {
     /// * `data` - Additional information about the event that is opaque to the validator.
     pub fn add_event(&mut self, event_type: String, attributes: Vec<(String, String)>, data: &[u8])
             -> Result<(), ContextError> {
-        let mut event = Event::new();
-        event.set_event_type(event_type);
-
-        let mut attributes_vec = Vec::new();
-        for (key, value) in attributes {
-            let mut attribute = Event_Attribute::new();
-            attribute.set_key(key);
-            attribute.set_value(value);
-            attributes_vec.push(attribute);
-        }
-        event.set_attributes(RepeatedField::from_vec(attributes_vec));
-        event.set_data(Vec::from(data));
-
-        let mut request = TpEventAddRequest::new();
-        request.set_context_id(self.context_id.clone());
-        request.set_event(event.clone());
+        let request = event_add_request(&self.context_id, event_type, attributes, data);
+        let content = self.send_request(Message_MessageType::TP_EVENT_ADD_REQUEST, &request)?;
+        decode_event_add_response(request.get_event(), &content)
+    }
 
+    /// Sends the request to the validator and waits for the content of its
+    /// reply.
+    fn send_request<R: M>(&mut self, message_type: Message_MessageType, request: &R)
+        -> Result<Vec<u8>, ContextError>
+    {
+        let wait = self.reply_wait()?;
         let serialized = request.write_to_bytes()?;
         let x : &[u8] = &serialized;
 
-        let mut future = self.sender.send(
-            Message_MessageType::TP_RECEIPT_ADD_DATA_REQUEST,
-            &generate_correlation_id(),
-            x)?;
-
-        let response: TpEventAddResponse = protobuf::parse_from_bytes(future.get()?.get_content())?;
-        match response.get_status() {
-            TpEventAddResponse_Status::OK => {
-                Ok(())
-            },
-            TpEventAddResponse_Status::ERROR => {
-                Err(ContextError::TransactionReceiptError(format!("Failed to add event {:?}", event)))
-            },
-            TpEventAddResponse_Status::STATUS_UNSET => {
-                Err(ContextError::ResponseAttributeError(String::from("Status was not set for TpEventAddRespons")))
-            }
-        }
+        // The sender is only locked while sending, so that clones of the
+        // context can wait for their replies concurrently.
+        let mut future = self.sender.lock()
+            .map_err(|_| ContextError::SendError(Box::new(SendError::UnknownError)))?
+            .send(message_type, &generate_correlation_id(), x)?;
+
+        let message = match wait {
+            Some(wait) => future.get_timeout(wait).map_err(|err| match err {
+                ReceiveError::TimeoutError => ContextError::TimeoutError(format!(
+                    "No reply to {:?} after {:?}", message_type, wait)),
+                err => ContextError::from(err)
+            })?,
+            None => future.get()?
+        };
+        Ok(Vec::from(message.get_content()))
+    }
 
+    /// Returns how long to wait for the next reply, given the request
+    /// timeout and deadline, or None to wait forever.
+    fn reply_wait(&self) -> Result<Option<Duration>, ContextError> {
+        let until_deadline = match self.deadline {
+            Some(deadline) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(ContextError::TimeoutError(
+                        String::from("The apply deadline has passed")));
+                }
+                Some(deadline - now)
+            }
+            None => None
+        };
+
+        Ok(match (self.request_timeout, until_deadline) {
+            (Some(timeout), Some(remaining)) => Some(cmp::min(timeout, remaining)),
+            (timeout, None) => timeout,
+            (None, remaining) => remaining
+        })
     }
 }
 
-pub trait TransactionHandler {
+pub trait TransactionHandler: Send + Sync {
     /// TransactionHandler that defines the business logic for a new transaction family.
     /// The family_name, family_versions, and namespaces functions are
     /// used by the processor to route processing requests to the handler.
+    /// Handlers may be shared by the processor's worker threads, so they
+    /// must be Send and Sync.
 
     /// family_name should return the name of the transaction family that this
     /// handler can process, e.g. "intkey"
@@ -404,3 +545,75 @@ pub trait TransactionHandler {
     /// initialized instance of the Context type.
     fn apply(&self, request: &TpProcessRequest, context: &mut TransactionContext) -> Result<(), ApplyError>;
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::{channel, Sender};
+    use std::time::{Duration, Instant};
+
+    use messages::validator::Message_MessageType;
+    use messaging::stream::MessageFuture;
+    use messaging::stream::MessageResult;
+    use messaging::stream::MessageSender;
+    use messaging::stream::SendError;
+
+    use super::ContextError;
+    use super::TransactionContext;
+
+    /// A sender whose requests are never answered.
+    struct SilentSender {
+        pending: Vec<Sender<MessageResult>>
+    }
+
+    impl MessageSender for SilentSender {
+        fn send(&mut self, _destination: Message_MessageType, _correlation_id: &str,
+                _contents: &[u8])
+            -> Result<MessageFuture, SendError>
+        {
+            let (tx, rx) = channel();
+            self.pending.push(tx);
+            Ok(MessageFuture::new(rx))
+        }
+
+        fn reply(&mut self, _destination: Message_MessageType, _correlation_id: &str,
+                 _contents: &[u8])
+            -> Result<(), SendError>
+        {
+            Ok(())
+        }
+
+        fn close(&mut self) {}
+    }
+
+    fn silent_context() -> TransactionContext {
+        TransactionContext::new("context", SilentSender { pending: Vec::new() })
+    }
+
+    #[test]
+    fn request_timeout() {
+        let mut context = silent_context();
+        context.set_request_timeout(Duration::from_millis(10));
+
+        match context.get_state("abcdef") {
+            Err(ContextError::TimeoutError(_)) => (),
+            result => panic!("Expected a TimeoutError, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn passed_deadline() {
+        let mut context = silent_context();
+        context.set_deadline(Instant::now());
+
+        match context.set_state("abcdef", b"data") {
+            Err(ContextError::TimeoutError(_)) => (),
+            result => panic!("Expected a TimeoutError, got {:?}", result)
+        }
+
+        context.set_deadline(Instant::now() + Duration::from_millis(10));
+        match context.delete_state(vec![String::from("abcdef")]) {
+            Err(ContextError::TimeoutError(_)) => (),
+            result => panic!("Expected a TimeoutError, got {:?}", result)
+        }
+    }
+}
@@ -20,126 +20,574 @@ extern crate protobuf;
 extern crate rand;
 extern crate ctrlc;
 
-use std::sync::mpsc::RecvTimeoutError;
+use std::collections::HashMap;
+use std::marker::PhantomData;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::mpsc::{RecvTimeoutError, sync_channel};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use std;
 use std::error::Error;
 
 use self::rand::Rng;
 
+pub mod cache;
+pub mod dispatch;
 pub mod handler;
+pub mod reconnect;
+mod requests;
+#[cfg(feature = "async")]
+pub mod async_handler;
+#[cfg(feature = "async")]
+pub mod async_processor;
 
 use protobuf::Message as M;
-use protobuf::repeated::RepeatedField;
+use messages::validator::Message;
 use messages::validator::Message_MessageType;
-use messages::network::PingResponse;
 use messages::processor::TpRegisterRequest;
+use messages::processor::TpRegisterResponse;
+use messages::processor::TpRegisterResponse_Status;
 use messages::processor::TpUnregisterRequest;
+use messages::processor::TpUnregisterResponse;
+use messages::processor::TpUnregisterResponse_Status;
 use messages::processor::TpProcessRequest;
 use messages::processor::TpProcessResponse;
 use messages::processor::TpProcessResponse_Status;
+use messages::transaction::TransactionHeader;
+use messaging::curve::CurveSettings;
 use messaging::stream::MessageConnection;
+use messaging::stream::MessageReceiver;
 use messaging::stream::MessageSender;
 use messaging::zmq_stream::ZmqMessageSender;
 use messaging::stream::SendError;
 use messaging::stream::ReceiveError;
 use messaging::zmq_stream::ZmqMessageConnection;
 
+use self::dispatch::MessageDispatcher;
+use self::dispatch::MessageHandler;
 use self::handler::TransactionContext;
 use self::handler::TransactionHandler;
 use self::handler::ApplyError;
+use self::reconnect::ConnectionEvent;
+use self::reconnect::ReconnectPolicy;
+use self::requests::register_request;
+
+/// How long to wait for the validator to answer each TpRegisterRequest
+/// before counting the connection attempt as failed, by default.
+const DEFAULT_REGISTER_TIMEOUT_MILLIS: u64 = 10000;
+
+/// The longest wait for a TpRegisterResponse between checks for a
+/// shutdown request.
+const SHUTDOWN_CHECK_MILLIS: u64 = 1000;
 
 /// Generates a random correlation id for use in Message
-fn generate_correlation_id() -> String {
+pub(crate) fn generate_correlation_id() -> String {
     const LENGTH: usize = 16;
     rand::thread_rng().gen_ascii_chars().take(LENGTH).collect()
 }
 
-pub struct TransactionProcessor<'a> {
+#[derive(Debug)]
+pub enum ProcessorError {
+    /// Returned when the validator does not accept the registration of a
+    /// transaction family version.
+    RegistrationError {
+        family: String,
+        version: String,
+        reason: String
+    },
+    /// Returned when the validator does not accept the processor's request
+    /// to unregister.
+    UnregistrationError(String),
+    /// Returned when the processor gives up reconnecting to the validator.
+    ConnectionError(String),
+}
+
+impl std::error::Error for ProcessorError {
+    fn description(&self) -> &str {
+        match *self {
+            ProcessorError::RegistrationError { ref reason, .. } => reason,
+            ProcessorError::UnregistrationError(ref msg) => msg,
+            ProcessorError::ConnectionError(ref msg) => msg,
+        }
+    }
+
+    fn cause(&self) -> Option<&std::error::Error> {
+        match *self {
+            ProcessorError::RegistrationError { .. } => None,
+            ProcessorError::UnregistrationError(_) => None,
+            ProcessorError::ConnectionError(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for ProcessorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            ProcessorError::RegistrationError { ref family, ref version, ref reason } =>
+                write!(f, "RegistrationError: {} {}: {}", family, version, reason),
+            ProcessorError::UnregistrationError(ref s) =>
+                write!(f, "UnregistrationError: {}", s),
+            ProcessorError::ConnectionError(ref s) =>
+                write!(f, "ConnectionError: {}", s),
+        }
+    }
+}
+
+/// Checks the validator's TpRegisterResponse to the given request.
+fn check_register_response(request: &TpRegisterRequest, content: &[u8])
+    -> Result<(), ProcessorError>
+{
+    let registration_error = |reason: String| ProcessorError::RegistrationError {
+        family: String::from(request.get_family()),
+        version: String::from(request.get_version()),
+        reason: reason
+    };
+
+    let response: TpRegisterResponse = protobuf::parse_from_bytes(content)
+        .map_err(|err| registration_error(
+            format!("Cannot parse TpRegisterResponse: {}", err.description())))?;
+    match response.get_status() {
+        TpRegisterResponse_Status::OK => Ok(()),
+        TpRegisterResponse_Status::ERROR => {
+            Err(registration_error(String::from("The validator rejected the registration")))
+        },
+        TpRegisterResponse_Status::STATUS_UNSET => {
+            Err(registration_error(String::from("Status was not set for TpRegisterResponse")))
+        }
+    }
+}
+
+/// Checks the validator's TpUnregisterResponse.
+fn check_unregister_response(content: &[u8]) -> Result<(), ProcessorError> {
+    let response: TpUnregisterResponse = protobuf::parse_from_bytes(content)
+        .map_err(|err| ProcessorError::UnregistrationError(
+            format!("Cannot parse TpUnregisterResponse: {}", err.description())))?;
+    match response.get_status() {
+        TpUnregisterResponse_Status::OK => Ok(()),
+        TpUnregisterResponse_Status::ERROR => {
+            Err(ProcessorError::UnregistrationError(
+                String::from("The validator rejected the unregistration")))
+        },
+        TpUnregisterResponse_Status::STATUS_UNSET => {
+            Err(ProcessorError::UnregistrationError(
+                String::from("Status was not set for TpUnregisterResponse")))
+        }
+    }
+}
+
+/// Builds the TpProcessResponse to send the validator for the result of
+/// applying a transaction.
+fn build_process_response(result: Result<(), ApplyError>) -> TpProcessResponse {
+    let mut response = TpProcessResponse::new();
+    match result {
+        Ok(()) => {
+            response.set_status(TpProcessResponse_Status::OK);
+            info!("TP_PROCESS_REQUEST sending TpProcessResponse: OK");
+        },
+        Err(err) => {
+            match err {
+                ApplyError::InvalidTransaction(_) |
+                ApplyError::InvalidTransactionWithData(..) => {
+                    response.set_status(
+                        TpProcessResponse_Status::INVALID_TRANSACTION);
+                },
+                _ => {
+                    response.set_status(
+                        TpProcessResponse_Status::INTERNAL_ERROR);
+                }
+            };
+            response.set_message(String::from(err.description()));
+            if let Some(data) = err.extended_data() {
+                response.set_extended_data(Vec::from(data));
+            }
+            info!("TP_PROCESS_REQUEST sending TpProcessResponse: {}",
+                  err.description());
+        }
+    };
+    response
+}
+
+#[derive(Default)]
+struct ShutdownState {
+    requested: bool,
+    running: bool
+}
+
+/// A handle for stopping a TransactionProcessor, which may be used from
+/// another thread while `start` is running.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    state: Arc<(Mutex<ShutdownState>, Condvar)>
+}
+
+impl ShutdownHandle {
+    fn new() -> Self {
+        ShutdownHandle {
+            state: Arc::new((Mutex::new(ShutdownState::default()), Condvar::new()))
+        }
+    }
+
+    /// Asks the processor to stop, without waiting for it to do so. Has no
+    /// effect if the processor is not running, so that a later start is
+    /// not stopped by a stale request.
+    pub fn request_shutdown(&self) {
+        let &(ref lock, ref cvar) = &*self.state;
+        let mut state = lock.lock().unwrap();
+        if state.running {
+            state.requested = true;
+            cvar.notify_all();
+        }
+    }
+
+    /// Asks the processor to stop, and blocks until it has unregistered
+    /// from the validator, finished the requests in flight and joined its
+    /// threads. Returns immediately, without effect, if the processor is
+    /// not running.
+    ///
+    /// This must not be called from a transaction handler, as the processor
+    /// waits for the handler to return before stopping.
+    pub fn shutdown(&self) {
+        let &(ref lock, ref cvar) = &*self.state;
+        let mut state = lock.lock().unwrap();
+        if !state.running {
+            return;
+        }
+        state.requested = true;
+        cvar.notify_all();
+        while state.running {
+            state = cvar.wait(state).unwrap();
+        }
+    }
+
+    /// Returns true if shutdown has been requested.
+    pub fn is_shutdown_requested(&self) -> bool {
+        let &(ref lock, _) = &*self.state;
+        lock.lock().unwrap().requested
+    }
+
+    /// Waits for the given duration, or until shutdown is requested.
+    /// Returns true if shutdown has been requested.
+    fn wait_timeout(&self, timeout: Duration) -> bool {
+        let &(ref lock, ref cvar) = &*self.state;
+        let state = lock.lock().unwrap();
+        if state.requested {
+            return true;
+        }
+        let (state, _) = cvar.wait_timeout(state, timeout).unwrap();
+        state.requested
+    }
+
+    fn set_running(&self) {
+        let &(ref lock, ref cvar) = &*self.state;
+        lock.lock().unwrap().running = true;
+        cvar.notify_all();
+    }
+
+    /// Marks the processor as stopped, and clears the shutdown request so
+    /// that it may be started again.
+    fn set_stopped(&self) {
+        let &(ref lock, ref cvar) = &*self.state;
+        let mut state = lock.lock().unwrap();
+        state.running = false;
+        state.requested = false;
+        cvar.notify_all();
+    }
+}
+
+/// Connects to a validator and routes transaction processing requests to
+/// the registered handlers. By default it connects over ZMQ; any other
+/// MessageConnection may be used through TransactionProcessor::with_connection.
+pub struct TransactionProcessor<'a, MC = ZmqMessageConnection, MS = ZmqMessageSender> {
     endpoint: String,
-    conn: ZmqMessageConnection,
-    handlers: Vec<&'a TransactionHandler>
+    conn: MC,
+    // Senders are created by the connection; the processor holds none
+    // itself.
+    sender_type: PhantomData<fn() -> MS>,
+    // The family versions to register, in the order they were added
+    family_versions: Vec<(String, String)>,
+    handler_map: HashMap<(String, String), &'a TransactionHandler>,
+    max_occupancy: u32,
+    strict: bool,
+    request_timeout: Option<Duration>,
+    apply_deadline: Option<Duration>,
+    register_timeout: Duration,
+    dispatcher: MessageDispatcher<'a>,
+    shutdown: ShutdownHandle,
+    reconnect_policy: ReconnectPolicy,
+    connection_callback: Option<Box<Fn(ConnectionEvent) + Send + Sync + 'a>>
 }
 
 impl<'a> TransactionProcessor<'a> {
     /// TransactionProcessor is for communicating with a
     /// validator and routing transaction processing requests to a registered
     /// handler. It uses ZMQ and channels to handle requests concurrently.
-    pub fn new(endpoint: &str) -> TransactionProcessor {
+    pub fn new(endpoint: &str) -> TransactionProcessor<'a> {
+        TransactionProcessor::with_connection(endpoint, ZmqMessageConnection::new(endpoint))
+    }
+
+    /// Encrypts the connection to the validator, and authenticates this
+    /// processor to it, with ZMQ CURVE. Must be set before start is called.
+    ///
+    /// # Arguments
+    ///
+    /// * curve - the validator's public key and this processor's keypair
+    pub fn set_curve(&mut self, curve: CurveSettings) {
+        self.conn.set_curve(curve);
+    }
+}
+
+impl<'a, MC, MS> TransactionProcessor<'a, MC, MS>
+    where MC: MessageConnection<MS> + Sync,
+          MS: MessageSender + Clone + Send + 'static
+{
+    /// Creates a TransactionProcessor which communicates with the validator
+    /// over the given connection, rather than over ZMQ.
+    ///
+    /// # Arguments
+    ///
+    /// * endpoint - the name of the validator endpoint, used when logging
+    /// * conn - the connection which creates a sender and receiver each
+    ///   time the processor connects
+    pub fn with_connection(endpoint: &str, conn: MC) -> TransactionProcessor<'a, MC, MS> {
         TransactionProcessor {
             endpoint: String::from(endpoint),
-            conn: ZmqMessageConnection::new(endpoint),
-            handlers: Vec::new()
+            conn: conn,
+            sender_type: PhantomData,
+            family_versions: Vec::new(),
+            handler_map: HashMap::new(),
+            max_occupancy: 0,
+            strict: false,
+            request_timeout: None,
+            apply_deadline: None,
+            register_timeout: Duration::from_millis(DEFAULT_REGISTER_TIMEOUT_MILLIS),
+            dispatcher: MessageDispatcher::new(),
+            shutdown: ShutdownHandle::new(),
+            reconnect_policy: ReconnectPolicy::new(),
+            connection_callback: None
         }
     }
 
+    /// Sets the policy for reconnecting to the validator after the
+    /// connection is lost.
+    ///
+    /// # Arguments
+    ///
+    /// * reconnect_policy - the delays and limits for reconnect attempts
+    pub fn set_reconnect_policy(&mut self, reconnect_policy: ReconnectPolicy) {
+        self.reconnect_policy = reconnect_policy;
+    }
+
+    /// Sets a callback which is called when the processor connects to,
+    /// is disconnected from, or re-registers with the validator.
+    ///
+    /// # Arguments
+    ///
+    /// * callback - the function called with each ConnectionEvent
+    pub fn set_connection_callback<F>(&mut self, callback: F)
+        where F: Fn(ConnectionEvent) + Send + Sync + 'a
+    {
+        self.connection_callback = Some(Box::new(callback));
+    }
+
+    /// Returns a handle which can be used to stop the processor once it
+    /// has been started.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Installs a Ctrl-C handler which shuts down the processor when the
+    /// process receives SIGINT or SIGTERM. A process may only have one such
+    /// handler, so this is left to applications which do not install their
+    /// own.
+    pub fn install_ctrlc_handler(&self) -> Result<(), ctrlc::Error> {
+        let handle = self.shutdown_handle();
+        ctrlc::set_handler(move || {
+            handle.request_shutdown();
+        })
+    }
+
+    /// Sets the maximum number of transactions this processor will apply
+    /// at once. The value is sent to the validator on registration, and
+    /// that many worker threads are used to apply transactions in parallel.
+    ///
+    /// If it is not set, the validator's default is used and transactions
+    /// are applied one at a time.
+    ///
+    /// # Arguments
+    ///
+    /// * max_occupancy - the number of transactions to apply at once
+    pub fn set_max_occupancy(&mut self, max_occupancy: u32) {
+        self.max_occupancy = max_occupancy;
+    }
+
+    /// Sets whether each handler's state requests are checked against the
+    /// inputs and outputs declared in the transaction header before they
+    /// are sent to the validator. This catches address calculation errors
+    /// without a round trip, and is off by default.
+    ///
+    /// # Arguments
+    ///
+    /// * strict - whether to check requests against the declared addresses
+    pub fn set_strict_mode(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Sets how long each of a handler's state requests waits for the
+    /// validator's reply before failing. By default, requests wait forever.
+    ///
+    /// # Arguments
+    ///
+    /// * timeout - the longest wait for each reply
+    pub fn set_request_timeout(&mut self, timeout: Duration) {
+        self.request_timeout = Some(timeout);
+    }
+
+    /// Sets how long a handler has to apply each transaction. A
+    /// transaction which takes longer is answered with an INTERNAL_ERROR,
+    /// so that the validator may retry it, and requests made after the
+    /// deadline fail. By default, there is no deadline.
+    ///
+    /// # Arguments
+    ///
+    /// * deadline - the longest time to apply each transaction
+    pub fn set_apply_deadline(&mut self, deadline: Duration) {
+        self.apply_deadline = Some(deadline);
+    }
+
+    /// Sets how long the processor waits for the validator to answer each
+    /// registration request. If it does not answer in time, the connection
+    /// attempt counts as failed, and the processor reconnects as the
+    /// reconnect policy allows. Defaults to 10 seconds.
+    ///
+    /// # Arguments
+    ///
+    /// * timeout - the longest wait for each TpRegisterResponse
+    pub fn set_register_timeout(&mut self, timeout: Duration) {
+        self.register_timeout = timeout;
+    }
+
     /// Adds a transaction family handler
     ///
+    /// The handler is registered for each of its family versions, and
+    /// TpProcessRequests are routed to it by the family name and version in
+    /// the transaction header. A handler added for a family version which
+    /// already has one replaces it, and the version is registered once.
+    ///
     /// # Arguments
     ///
     /// * handler - the handler to be added
     pub fn add_handler(&mut self, handler: &'a TransactionHandler) {
-        self.handlers.push(handler);
-    }
-
-    fn register(&mut self, mut sender: ZmqMessageSender, unregister: Arc<AtomicBool>) -> bool {
-        for handler in &self.handlers {
-            for version in handler.family_versions() {
-                let mut request = TpRegisterRequest::new();
-                request.set_family(handler.family_name().clone());
-                request.set_version(version.clone());
-                request.set_namespaces(RepeatedField::from_vec(handler.namespaces().clone()));
-                info!("sending TpRegisterRequest: {} {}",
-                      &handler.family_name(),
-                      &version);
-                let serialized = match request.write_to_bytes() {
-                    Ok(serialized) => serialized,
+        for version in handler.family_versions() {
+            let key = (handler.family_name(), version);
+            if self.handler_map.contains_key(&key) {
+                warn!("Replacing handler for family {} version {}", key.0, key.1);
+            } else {
+                self.family_versions.push(key.clone());
+            }
+            self.handler_map.insert(key, handler);
+        }
+    }
+
+    /// Adds a handler for requests of the given message type from the
+    /// validator. PingRequests are answered by default; TpProcessRequests
+    /// are always routed to the transaction handlers.
+    ///
+    /// # Arguments
+    ///
+    /// * message_type - the type of request the handler answers
+    /// * handler - the handler, which returns the reply to send
+    pub fn add_message_handler(&mut self, message_type: Message_MessageType,
+                               handler: Box<MessageHandler + 'a>)
+    {
+        if message_type == Message_MessageType::TP_PROCESS_REQUEST {
+            warn!("TpProcessRequests are routed to the transaction handlers; \
+                   ignoring the message handler");
+            return;
+        }
+        self.dispatcher.add_handler(message_type, handler);
+    }
+
+    /// Returns the handler registered for the family name and version of
+    /// the given transaction header, if any.
+    fn find_handler(&self, header: &TransactionHeader) -> Option<&'a TransactionHandler> {
+        self.handler_map
+            .get(&(String::from(header.get_family_name()),
+                   String::from(header.get_family_version())))
+            .map(|handler| *handler)
+    }
+
+    /// Registers each of the handlers' family versions with the validator.
+    /// Returns Ok(false) if the connection is lost, the validator does not
+    /// answer within the register timeout, or shutdown is requested, before
+    /// registration completes.
+    fn register(&mut self, mut sender: MS) -> Result<bool, ProcessorError> {
+        for &(ref family, ref version) in &self.family_versions {
+            let handler = self.handler_map[&(family.clone(), version.clone())];
+            let request = register_request(family, version, handler.namespaces(),
+                                           self.max_occupancy);
+            info!("sending TpRegisterRequest: {} {}", family, version);
+            let serialized = match request.write_to_bytes() {
+                Ok(serialized) => serialized,
+                Err(err) => {
+                    error!("Serialization failed: {}", err.description());
+                    // try reconnect
+                    return Ok(false)
+                }
+            };
+            let x : &[u8] = &serialized;
+
+            let mut future = match sender.send(
+                Message_MessageType::TP_REGISTER_REQUEST,
+                &generate_correlation_id(),
+                x) {
+                    Ok(fut) => fut,
                     Err(err) => {
-                        error!("Serialization failed: {}", err.description());
+                        error!("Registration failed: {}", err.description());
                         // try reconnect
-                        return false
+                        return Ok(false)
                     }
                 };
-                let x : &[u8] = &serialized;
-
-                let mut future = match sender.send(
-                    Message_MessageType::TP_REGISTER_REQUEST,
-                    &generate_correlation_id(),
-                    x) {
-                        Ok(fut) => fut,
-                        Err(err) => {
-                            error!("Registration failed: {}", err.description());
-                            // try reconnect
-                            return false
-                        }
-                    };
-
-                // Absorb the TpRegisterResponse message
-                loop{
-                    let _ = match future.get_timeout(Duration::from_millis(10000)) {
-                        Ok(_) => break,
-                        Err(_) => {
-                            if unregister.load(Ordering::SeqCst){
-                                return false
-                            }
 
-                        }
-                    };
+            let deadline = Instant::now() + self.register_timeout;
+            let response = loop {
+                let now = Instant::now();
+                if now >= deadline {
+                    warn!("No TpRegisterResponse received within {:?}",
+                          self.register_timeout);
+                    // try reconnect
+                    return Ok(false)
                 }
-            }
+                let wait = std::cmp::min(deadline - now,
+                                         Duration::from_millis(SHUTDOWN_CHECK_MILLIS));
+                match future.get_timeout(wait) {
+                    Ok(response) => break response,
+                    Err(ReceiveError::TimeoutError) => {
+                        if self.shutdown.is_shutdown_requested() {
+                            return Ok(false)
+                        }
+                    }
+                    Err(err) => {
+                        error!("Registration failed: {}", err.description());
+                        // try reconnect
+                        return Ok(false)
+                    }
+                };
+            };
+
+            check_register_response(&request, response.get_content())?;
         }
-        true
+        Ok(true)
     }
 
-    fn unregister(&mut self, mut sender: ZmqMessageSender) {
+    fn unregister(&self, mut sender: MS) -> Result<(), ProcessorError> {
         let request = TpUnregisterRequest::new();
         info!("sending TpUnregisterRequest");
         let serialized = match request.write_to_bytes() {
             Ok(serialized) => serialized,
             Err(err) => {
                 error!("Serialization failed: {}", err.description());
-                return
+                return Ok(())
             }
         };
         let x : &[u8] = &serialized;
@@ -151,67 +599,177 @@ impl<'a> TransactionProcessor<'a> {
                 Ok(fut) => fut,
                 Err(err) => {
                     error!("Unregistration failed: {}", err.description());
-                    return
+                    return Ok(())
                 }
             };
-        // Absorb the TpUnregisterResponse message, wait one second for response then continue
-        let _ = match future.get_timeout(Duration::from_millis(1000)){
-            Ok(_) => (),
+        // Wait one second for the TpUnregisterResponse, then continue
+        match future.get_timeout(Duration::from_millis(1000)){
+            Ok(response) => check_unregister_response(response.get_content()),
             Err(err) => {
                 info!("Unregistration failed: {}", err.description());
+                Ok(())
             }
-        };
-
+        }
     }
 
     /// Connects the transaction processor to a validator and starts
     /// listening for requests and routing them to an appropriate
-    /// transaction handler.
-    pub fn start(&mut self) {
-        let unregister =  Arc::new(AtomicBool::new(false));
-        let r = unregister.clone();
-        ctrlc::set_handler(move || {
-            r.store(true, Ordering::SeqCst);
-        }).expect("Error setting Ctrl-C handler");
+    /// transaction handler. Returns once shutdown has been requested
+    /// through a ShutdownHandle, after unregistering from the validator.
+    ///
+    /// Returns an error if the validator rejects the registration of a
+    /// handler or the processor's unregistration, or if the reconnect
+    /// policy gives up.
+    ///
+    /// Once start has returned, the shutdown request is cleared, and the
+    /// processor may be started again.
+    pub fn start(&mut self) -> Result<(), ProcessorError> {
+        self.shutdown.set_running();
+        let result = self.connect_and_run();
+        self.shutdown.set_stopped();
+        result
+    }
 
-        let mut first_time = true;
-        let mut restart = true;
+    fn connect_and_run(&mut self) -> Result<(), ProcessorError> {
+        let mut registered = false;
+        // The number of consecutive failed connection attempts
+        let mut attempt = 0;
 
-        while restart {
-            info!("connecting to endpoint: {}", self.endpoint);
-            if first_time {
-                first_time = false;
-            } else {
-                self.conn = ZmqMessageConnection::new(&self.endpoint);
+        loop {
+            if attempt > 0 {
+                if self.reconnect_policy.is_exhausted(attempt) {
+                    let msg = format!("Gave up on connecting to {} after {} attempts",
+                                      self.endpoint, attempt - 1);
+                    error!("{}", msg);
+                    return Err(ProcessorError::ConnectionError(msg));
+                }
+
+                let delay = self.reconnect_policy.delay(attempt);
+                info!("Reconnecting in {:?} (attempt {})", delay, attempt);
+                if self.shutdown.wait_timeout(delay) {
+                    return Ok(());
+                }
             }
+
+            info!("connecting to endpoint: {}", self.endpoint);
             let (mut sender, receiver) = self.conn.create();
 
-            if unregister.load(Ordering::SeqCst){
-                self.unregister(sender.clone());
-                restart = false;
-                continue;
+            if self.shutdown.is_shutdown_requested() {
+                let result = self.unregister(sender.clone());
+                sender.close();
+                return result;
             }
 
             // if registration is not succesful, retry
-            match self.register(sender.clone(), unregister.clone()) {
-                true => (),
-                false => continue
+            match self.register(sender.clone()) {
+                Ok(true) => (),
+                Ok(false) => {
+                    sender.close();
+                    attempt += 1;
+                    continue
+                }
+                Err(err) => {
+                    sender.close();
+                    return Err(err);
+                }
+            }
+
+            self.notify(if registered {
+                ConnectionEvent::Reregistered
+            } else {
+                ConnectionEvent::Connected
+            });
+            registered = true;
+
+            let result = self.run(&mut sender, &receiver);
+
+            sender.close();
+
+            match result {
+                Ok(true) => (),
+                Ok(false) => return Ok(()),
+                Err(err) => return Err(err)
             }
 
-            loop {
-                if unregister.load(Ordering::SeqCst){
-                    self.unregister(sender.clone());
-                    restart = false;
-                    break;
+            self.notify(ConnectionEvent::Disconnected);
+            attempt = 1;
+        }
+    }
+
+    fn notify(&self, event: ConnectionEvent) {
+        if let Some(ref callback) = self.connection_callback {
+            callback(event);
+        }
+    }
+
+    /// Receives requests from the validator until it disconnects or
+    /// shutdown is requested. When a max occupancy has been set,
+    /// TpProcessRequests are handed to a pool of that many worker threads;
+    /// otherwise they are applied on the receiving thread.
+    ///
+    /// Returns Ok(true) if the processor should reconnect to the validator.
+    fn run(&self, sender: &mut MS, receiver: &MessageReceiver)
+        -> Result<bool, ProcessorError>
+    {
+        let worker_count = self.max_occupancy as usize;
+        // Set by a worker which finds the connection lost when replying
+        let disconnected = AtomicBool::new(false);
+
+        thread::scope(|scope| {
+            // Up to worker_count requests wait here for a free worker, so
+            // that the receiving thread goes on answering pings while the
+            // pool is busy. Once the queue is full as well, the receiving
+            // thread waits for room before reading any further messages.
+            let (job_tx, job_rx) = sync_channel::<Message>(worker_count);
+            let job_rx = Arc::new(Mutex::new(job_rx));
+
+            for _ in 0..worker_count {
+                let job_rx = job_rx.clone();
+                let disconnected = &disconnected;
+                let mut worker_sender = sender.clone();
+                scope.spawn(move || {
+                    loop {
+                        let next = job_rx.lock().unwrap().recv();
+                        let message = match next {
+                            Ok(message) => message,
+                            Err(_) => break
+                        };
+                        // The validator sends the requests again once the
+                        // processor has reconnected
+                        if disconnected.load(Ordering::SeqCst) {
+                            continue;
+                        }
+                        match self.process_request(&mut worker_sender, &message) {
+                            Ok(_) => (),
+                            Err(SendError::DisconnectedError) => {
+                                error!("DisconnectedError");
+                                disconnected.store(true, Ordering::SeqCst);
+                            }
+                            Err(err) => {
+                                error!("Failed to send TpProcessResponse: {}", err.description());
+                            }
+                        }
+                    }
+                });
+            }
+
+            let restart = loop {
+                if self.shutdown.is_shutdown_requested() {
+                    break self.unregister(sender.clone()).map(|_| false);
+                }
+                if disconnected.load(Ordering::SeqCst) {
+                    info!("Trying to Reconnect");
+                    break Ok(true);
                 }
                 match receiver.recv_timeout(Duration::from_millis(1000)) {
                     Ok(r) => {
                         // Check if we have a message
                         let message = match r {
                             Ok(message) => message,
-                            Err(ReceiveError::DisconnectedError)=> {
+                            Err(ReceiveError::DisconnectedError) |
+                            Err(ReceiveError::SocketError(_)) => {
                                 info!("Trying to Reconnect");
-                                break;
+                                break Ok(true);
                             }
                             Err(err) => {
                                 error!("Error: {}", err.description());
@@ -223,100 +781,51 @@ impl<'a> TransactionProcessor<'a> {
 
                         match message.get_message_type() {
                             Message_MessageType::TP_PROCESS_REQUEST => {
-                                let request: TpProcessRequest = match protobuf::parse_from_bytes(
-                                    &message.get_content()) {
-                                    Ok(request) => request,
-                                    Err(err) => {
-                                        error!("Cannot parse TpProcessRequest: {}",
-                                               err.description());
-                                        continue
+                                if worker_count > 0 {
+                                    if let Err(_) = job_tx.send(message) {
+                                        error!("Worker pool has shut down");
+                                        break Ok(true);
                                     }
-                                };
+                                    continue;
+                                }
 
-                                let mut context = TransactionContext::new(
-                                    request.get_context_id(), sender.clone());
-
-                                let mut response = TpProcessResponse::new();
-                                match self.handlers[0].apply(&request, &mut context) {
-                                    Ok(()) => {
-                                        response.set_status(TpProcessResponse_Status::OK);
-                                        info!("TP_PROCESS_REQUEST sending TpProcessResponse: OK");
-                                    },
-                                    Err(ApplyError::InvalidTransaction(msg)) => {
-                                        response.set_status(
-                                            TpProcessResponse_Status::INVALID_TRANSACTION);
-                                        response.set_message(msg.clone());
-                                        info!("TP_PROCESS_REQUEST sending TpProcessResponse: {}",
-                                              msg);
+                                match self.process_request(sender, &message) {
+                                    Ok(_) => (),
+                                    Err(SendError::DisconnectedError) => {
+                                        error!("DisconnectedError");
+                                        break Ok(true)
                                     },
-                                    Err(err) => {
-                                        response.set_status(
-                                            TpProcessResponse_Status::INTERNAL_ERROR);
-                                        response.set_message(String::from(err.description()));
-                                        info!("TP_PROCESS_REQUEST sending TpProcessResponse: {}",
-                                              err.description());
-                                    }
-                                };
-
-                                let serialized = match response.write_to_bytes()
-                                {
-                                    Ok(serialized) => serialized,
-                                    Err(err) => {
-                                        error!("Serialization failed: {}", err.description());
-                                        continue
+                                    Err(SendError::TimeoutError) =>
+                                        error!("TimeoutError"),
+                                    Err(SendError::QueueFullError) =>
+                                        error!("QueueFullError"),
+                                    Err(SendError::UnknownError) => {
+                                        error!("UnknownError");
+                                        break Ok(false)
                                     }
                                 };
-
-                                let x : &[u8] = &serialized;
-                                match sender.reply(
-                                    Message_MessageType::TP_PROCESS_RESPONSE,
-                                    message.get_correlation_id(),
-                                    x) {
-                                        Ok(_) => (),
-                                        Err(SendError::DisconnectedError) => {
-                                            error!("DisconnectedError");
-                                            break
-                                        },
-                                        Err(SendError::TimeoutError) =>
-                                            error!("TimeoutError"),
-                                        Err(SendError::UnknownError) => {
-                                            restart = false;
-                                            println!("UnknownError");
-                                            break
-                                        }
-                                    };
                             },
-                            Message_MessageType::PING_REQUEST => {
-                                info!("sending PingResponse");
-                                let response = PingResponse::new();
-                                let serialized = match response.write_to_bytes() {
-                                    Ok(serialized) => serialized,
-                                    Err(err) => {
-                                        error!("Serialization failed: {}", err.description());
-                                        continue
-                                    }
+                            _ => {
+                                let (reply_type, reply) = match self.dispatcher.dispatch(&message) {
+                                    Some(reply) => reply,
+                                    None => continue
                                 };
-                                let x : &[u8] = &serialized;
                                 match sender.reply(
-                                    Message_MessageType::TP_PROCESS_RESPONSE,
+                                    reply_type,
                                     message.get_correlation_id(),
-                                    x){
+                                    &reply){
                                         Ok(_) => (),
                                         Err(SendError::DisconnectedError) => {
                                             error!("DisconnectedError");
-                                            break
+                                            break Ok(true)
                                         },
                                         Err(SendError::TimeoutError) => error!("TimeoutError"),
+                                        Err(SendError::QueueFullError) => error!("QueueFullError"),
                                         Err(SendError::UnknownError) => {
-                                            restart = false;
-                                            println!("UnknownError");
-                                            break
+                                            error!("UnknownError");
+                                            break Ok(false)
                                         }
                                     };
-                            },
-                            _ => {
-                                info!("Transaction Processor recieved invalid message type: {:?}",
-                                          message.get_message_type());
                             }
                         }
                     },
@@ -325,8 +834,732 @@ impl<'a> TransactionProcessor<'a> {
                         error!("Error: {}", err.description());
                     }
                 }
+            };
+
+            // Closing the job channel lets the workers finish any requests
+            // in flight and exit; the scope joins them before returning.
+            drop(job_tx);
+
+            restart
+        })
+    }
+
+    /// Applies the request with the matching handler, within the apply
+    /// deadline.
+    fn apply(&self, request: &TpProcessRequest, mut context: TransactionContext)
+        -> Result<(), ApplyError>
+    {
+        if self.strict {
+            context.set_declared_addresses(request.get_header().get_inputs(),
+                                           request.get_header().get_outputs());
+        }
+        if let Some(timeout) = self.request_timeout {
+            context.set_request_timeout(timeout);
+        }
+        let deadline = self.apply_deadline.map(|deadline| Instant::now() + deadline);
+        if let Some(deadline) = deadline {
+            context.set_deadline(deadline);
+        }
+
+        let result = match self.find_handler(request.get_header()) {
+            Some(handler) => handler.apply(request, &mut context),
+            None => Err(ApplyError::InternalError(format!(
+                "No handler registered for family {} version {}",
+                request.get_header().get_family_name(),
+                request.get_header().get_family_version())))
+        };
+
+        match deadline {
+            Some(deadline) if Instant::now() > deadline => {
+                warn!("Transaction {} exceeded the apply deadline",
+                      request.get_signature());
+                Err(ApplyError::InternalError(String::from("The apply deadline was exceeded")))
             }
-            sender.close();
+            _ => result
+        }
+    }
+
+    /// Applies the TpProcessRequest in the given message with the matching
+    /// handler and replies to the validator with the TpProcessResponse.
+    fn process_request(&self, sender: &mut MS, message: &Message)
+        -> Result<(), SendError>
+    {
+        let request: TpProcessRequest = match protobuf::parse_from_bytes(
+            &message.get_content()) {
+            Ok(request) => request,
+            Err(err) => {
+                error!("Cannot parse TpProcessRequest: {}", err.description());
+                return Ok(())
+            }
+        };
+
+        let context = TransactionContext::new(
+            request.get_context_id(), sender.clone());
+        let result = self.apply(&request, context);
+
+        let response = build_process_response(result);
+
+        let serialized = match response.write_to_bytes()
+        {
+            Ok(serialized) => serialized,
+            Err(err) => {
+                error!("Serialization failed: {}", err.description());
+                return Ok(())
+            }
+        };
+
+        let x : &[u8] = &serialized;
+        sender.reply(
+            Message_MessageType::TP_PROCESS_RESPONSE,
+            message.get_correlation_id(),
+            x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use protobuf::Message as M;
+
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::mpsc::{channel, Sender};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use messages::processor::TpProcessRequest;
+    use messages::processor::TpRegisterRequest;
+    use messages::processor::TpRegisterResponse;
+    use messages::processor::TpRegisterResponse_Status;
+    use messages::processor::TpUnregisterResponse;
+    use messages::processor::TpUnregisterResponse_Status;
+    use messages::processor::TpProcessResponse_Status;
+    use messages::transaction::TransactionHeader;
+    use messages::validator::Message;
+    use messages::validator::Message_MessageType;
+    use messaging::stream::MessageConnection;
+    use messaging::stream::MessageFuture;
+    use messaging::stream::MessageReceiver;
+    use messaging::stream::MessageResult;
+    use messaging::stream::MessageSender;
+    use messaging::stream::ReceiveError;
+    use messaging::stream::SendError;
+    use testing::MockValidator;
+
+    use super::reconnect::ConnectionEvent;
+    use super::reconnect::ReconnectPolicy;
+    use super::ProcessorError;
+    use super::build_process_response;
+    use super::TransactionProcessor;
+    use super::check_register_response;
+    use super::handler::ApplyError;
+    use super::handler::TransactionContext;
+    use super::handler::TransactionHandler;
+
+    struct MockHandler {
+        family_name: String,
+        family_versions: Vec<String>
+    }
+
+    impl MockHandler {
+        fn new(family_name: &str, family_versions: &[&str]) -> Self {
+            MockHandler {
+                family_name: String::from(family_name),
+                family_versions: family_versions.iter().map(|v| String::from(*v)).collect()
+            }
+        }
+    }
+
+    impl TransactionHandler for MockHandler {
+        fn family_name(&self) -> String {
+            self.family_name.clone()
+        }
+
+        fn family_versions(&self) -> Vec<String> {
+            self.family_versions.clone()
+        }
+
+        fn namespaces(&self) -> Vec<String> {
+            vec![String::from("abcdef")]
+        }
+
+        fn apply(&self, _request: &TpProcessRequest, _context: &mut TransactionContext)
+            -> Result<(), ApplyError>
+        {
+            Ok(())
+        }
+    }
+
+    fn make_header(family_name: &str, family_version: &str) -> TransactionHeader {
+        let mut header = TransactionHeader::new();
+        header.set_family_name(String::from(family_name));
+        header.set_family_version(String::from(family_version));
+        header
+    }
+
+    #[test]
+    fn find_handler_by_family_and_version() {
+        let intkey = MockHandler::new("intkey", &["1.0"]);
+        let xo = MockHandler::new("xo", &["1.0", "2.0"]);
+
+        let mut processor = TransactionProcessor::new("tcp://localhost:4004");
+        processor.add_handler(&intkey);
+        processor.add_handler(&xo);
+
+        let found = processor.find_handler(&make_header("intkey", "1.0"))
+            .expect("Should have found the intkey handler");
+        assert_eq!(found.family_name(), "intkey");
+
+        let found = processor.find_handler(&make_header("xo", "2.0"))
+            .expect("Should have found the xo handler");
+        assert_eq!(found.family_name(), "xo");
+
+        assert!(processor.find_handler(&make_header("xo", "3.0")).is_none());
+        assert!(processor.find_handler(&make_header("unknown", "1.0")).is_none());
+    }
+
+    #[test]
+    fn shutdown_when_not_running() {
+        let handler = MockHandler::new("intkey", &["1.0"]);
+        let conn = MockConnection::new(Registration::Accept);
+        let sender = conn.sender.clone();
+
+        let mut processor = TransactionProcessor::with_connection("mock", conn);
+        processor.add_handler(&handler);
+        let handle = processor.shutdown_handle();
+
+        // Returns immediately, and is forgotten, since the processor has
+        // not been started
+        handle.shutdown();
+        handle.request_shutdown();
+        assert!(!handle.is_shutdown_requested());
+
+        let validator_handle = handle.clone();
+        let validator_sender = sender.clone();
+        let validator = thread::spawn(move || {
+            wait_for(|| validator_sender.requests(Message_MessageType::TP_REGISTER_REQUEST) == 1);
+            validator_handle.request_shutdown();
+        });
+
+        processor.start().unwrap();
+        validator.join().unwrap();
+        assert_eq!(sender.requests(Message_MessageType::TP_REGISTER_REQUEST), 1);
+        assert_eq!(sender.requests(Message_MessageType::TP_UNREGISTER_REQUEST), 1);
+    }
+
+    #[test]
+    fn register_response_status() {
+        let mut request = TpRegisterRequest::new();
+        request.set_family(String::from("intkey"));
+        request.set_version(String::from("1.0"));
+
+        let mut response = TpRegisterResponse::new();
+        response.set_status(TpRegisterResponse_Status::OK);
+        assert!(check_register_response(
+            &request, &response.write_to_bytes().unwrap()).is_ok());
+
+        response.set_status(TpRegisterResponse_Status::ERROR);
+        match check_register_response(&request, &response.write_to_bytes().unwrap()) {
+            Err(ProcessorError::RegistrationError { family, version, .. }) => {
+                assert_eq!(family, "intkey");
+                assert_eq!(version, "1.0");
+            }
+            result => panic!("Expected a RegistrationError, got {:?}", result)
+        }
+
+        // An empty response has no status set
+        assert!(check_register_response(&request, &[]).is_err());
+    }
+
+    #[test]
+    fn process_response_extended_data() {
+        let response = build_process_response(Err(ApplyError::InvalidTransactionWithData(
+            String::from("Insufficient funds"), vec![0x01, 0x02])));
+        assert_eq!(response.get_status(), TpProcessResponse_Status::INVALID_TRANSACTION);
+        assert_eq!(response.get_message(), "Insufficient funds");
+        assert_eq!(response.get_extended_data(), &[0x01, 0x02]);
+
+        let response = build_process_response(Err(ApplyError::InternalErrorWithData(
+            String::from("Bad state"), vec![0x03])));
+        assert_eq!(response.get_status(), TpProcessResponse_Status::INTERNAL_ERROR);
+        assert_eq!(response.get_extended_data(), &[0x03]);
+
+        let response = build_process_response(Err(ApplyError::InvalidTransaction(
+            String::from("Invalid"))));
+        assert_eq!(response.get_status(), TpProcessResponse_Status::INVALID_TRANSACTION);
+        assert!(response.get_extended_data().is_empty());
+    }
+
+    struct SlowHandler;
+
+    impl TransactionHandler for SlowHandler {
+        fn family_name(&self) -> String {
+            String::from("slow")
+        }
+
+        fn family_versions(&self) -> Vec<String> {
+            vec![String::from("1.0")]
+        }
+
+        fn namespaces(&self) -> Vec<String> {
+            vec![String::from("abcdef")]
+        }
+
+        fn apply(&self, _request: &TpProcessRequest, _context: &mut TransactionContext)
+            -> Result<(), ApplyError>
+        {
+            thread::sleep(Duration::from_millis(50));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn apply_deadline_exceeded() {
+        let validator = MockValidator::new();
+        let mut request = TpProcessRequest::new();
+        request.set_header(make_header("slow", "1.0"));
+
+        let mut processor = TransactionProcessor::new("tcp://localhost:4004");
+        processor.add_handler(&SlowHandler);
+        assert!(processor.apply(&request, validator.context("context")).is_ok());
+
+        processor.set_apply_deadline(Duration::from_millis(1));
+        match processor.apply(&request, validator.context("context")) {
+            Err(ApplyError::InternalError(_)) => (),
+            result => panic!("Expected an InternalError, got {:?}", result)
+        }
+    }
+
+    /// Whether the mock validator answers TpRegisterRequests.
+    #[derive(Clone, Copy, PartialEq)]
+    enum Registration {
+        Accept,
+        Ignore
+    }
+
+    #[derive(Default)]
+    struct MockLog {
+        /// The type of each request sent, in order
+        requests: Vec<Message_MessageType>,
+        /// The type and correlation id of each reply sent, in order
+        replies: Vec<(Message_MessageType, String)>,
+        /// Keeps the futures of ignored requests waiting
+        ignored: Vec<Sender<MessageResult>>,
+        /// Whether replies fail as if the validator had disconnected
+        disconnected: bool
+    }
+
+    /// Sends messages to a mock validator, which accepts unregistration
+    /// and accepts or ignores registration.
+    #[derive(Clone)]
+    struct MockSender {
+        registration: Registration,
+        log: Arc<Mutex<MockLog>>
+    }
+
+    impl MockSender {
+        fn requests(&self, message_type: Message_MessageType) -> usize {
+            self.log.lock().unwrap().requests.iter().filter(|t| **t == message_type).count()
+        }
+
+        fn replies(&self) -> Vec<(Message_MessageType, String)> {
+            self.log.lock().unwrap().replies.clone()
+        }
+    }
+
+    impl MessageSender for MockSender {
+        fn send(&mut self, destination: Message_MessageType, correlation_id: &str,
+                _contents: &[u8])
+            -> Result<MessageFuture, SendError>
+        {
+            let mut log = self.log.lock().unwrap();
+            log.requests.push(destination);
+
+            let (tx, rx) = channel();
+            let (message_type, content) = match destination {
+                Message_MessageType::TP_REGISTER_REQUEST => {
+                    if self.registration == Registration::Ignore {
+                        log.ignored.push(tx);
+                        return Ok(MessageFuture::new(rx));
+                    }
+                    let mut response = TpRegisterResponse::new();
+                    response.set_status(TpRegisterResponse_Status::OK);
+                    (Message_MessageType::TP_REGISTER_RESPONSE, response.write_to_bytes().unwrap())
+                }
+                Message_MessageType::TP_UNREGISTER_REQUEST => {
+                    let mut response = TpUnregisterResponse::new();
+                    response.set_status(TpUnregisterResponse_Status::OK);
+                    (Message_MessageType::TP_UNREGISTER_RESPONSE,
+                     response.write_to_bytes().unwrap())
+                }
+                _ => return Err(SendError::UnknownError)
+            };
+
+            let mut message = Message::new();
+            message.set_message_type(message_type);
+            message.set_correlation_id(String::from(correlation_id));
+            message.set_content(content);
+            tx.send(Ok(message)).unwrap();
+            Ok(MessageFuture::new(rx))
+        }
+
+        fn reply(&mut self, destination: Message_MessageType, correlation_id: &str,
+                 _contents: &[u8])
+            -> Result<(), SendError>
+        {
+            let mut log = self.log.lock().unwrap();
+            if log.disconnected {
+                return Err(SendError::DisconnectedError);
+            }
+            log.replies.push((destination, String::from(correlation_id)));
+            Ok(())
+        }
+
+        fn close(&mut self) {}
+    }
+
+    /// Creates MockSenders, and keeps the inbound end of each connection so
+    /// that the test can send requests from the validator.
+    struct MockConnection {
+        sender: MockSender,
+        inbound: Arc<Mutex<Vec<Sender<MessageResult>>>>
+    }
+
+    impl MockConnection {
+        fn new(registration: Registration) -> Self {
+            MockConnection {
+                sender: MockSender {
+                    registration: registration,
+                    log: Arc::new(Mutex::new(MockLog::default()))
+                },
+                inbound: Arc::new(Mutex::new(Vec::new()))
+            }
+        }
+    }
+
+    impl MessageConnection<MockSender> for MockConnection {
+        fn create(&self) -> (MockSender, MessageReceiver) {
+            let (tx, rx) = channel();
+            self.inbound.lock().unwrap().push(tx);
+            (self.sender.clone(), rx)
+        }
+    }
+
+    /// Returns a message of the given type from the validator.
+    fn make_message(message_type: Message_MessageType, correlation_id: &str) -> MessageResult {
+        let mut message = Message::new();
+        message.set_message_type(message_type);
+        message.set_correlation_id(String::from(correlation_id));
+        Ok(message)
+    }
+
+    /// Waits up to five seconds for the condition to hold.
+    fn wait_for<F: Fn() -> bool>(condition: F) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition() {
+            assert!(Instant::now() < deadline, "Timed out waiting for the processor");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn reregisters_after_disconnect() {
+        let handler = MockHandler::new("intkey", &["1.0"]);
+        let conn = MockConnection::new(Registration::Accept);
+        let sender = conn.sender.clone();
+        let inbound = conn.inbound.clone();
+        let events = Arc::new(Mutex::new(Vec::new()));
+
+        let mut processor = TransactionProcessor::with_connection("mock", conn);
+        processor.add_handler(&handler);
+        processor.set_reconnect_policy(ReconnectPolicy::new()
+                                       .initial_delay(Duration::from_millis(1)));
+        let callback_events = events.clone();
+        processor.set_connection_callback(move |event| {
+            callback_events.lock().unwrap().push(event);
+        });
+
+        let handle = processor.shutdown_handle();
+        let validator = thread::spawn(move || {
+            wait_for(|| inbound.lock().unwrap().len() == 1);
+            inbound.lock().unwrap()[0].send(Err(ReceiveError::DisconnectedError)).unwrap();
+
+            // Answered once the processor has registered again
+            wait_for(|| inbound.lock().unwrap().len() == 2);
+            inbound.lock().unwrap()[1]
+                .send(make_message(Message_MessageType::PING_REQUEST, "ping")).unwrap();
+            wait_for(|| !sender.replies().is_empty());
+            handle.request_shutdown();
+            sender
+        });
+
+        processor.start().unwrap();
+        let sender = validator.join().unwrap();
+
+        assert_eq!(*events.lock().unwrap(), vec![ConnectionEvent::Connected,
+                                                 ConnectionEvent::Disconnected,
+                                                 ConnectionEvent::Reregistered]);
+        assert_eq!(sender.requests(Message_MessageType::TP_REGISTER_REQUEST), 2);
+        assert_eq!(sender.requests(Message_MessageType::TP_UNREGISTER_REQUEST), 1);
+        assert_eq!(sender.replies(),
+                   vec![(Message_MessageType::PING_RESPONSE, String::from("ping"))]);
+    }
+
+    #[test]
+    fn shutdown_unregisters_running_processor() {
+        let handler = MockHandler::new("intkey", &["1.0"]);
+        let conn = MockConnection::new(Registration::Accept);
+        let sender = conn.sender.clone();
+        let inbound = conn.inbound.clone();
+
+        let mut processor = TransactionProcessor::with_connection("mock", conn);
+        processor.add_handler(&handler);
+        let handle = processor.shutdown_handle();
+
+        for run in 1..3 {
+            let validator_handle = handle.clone();
+            let inbound = inbound.clone();
+            let validator = thread::spawn(move || {
+                wait_for(|| inbound.lock().unwrap().len() == run);
+                // Returns once the processor has stopped
+                validator_handle.shutdown();
+            });
+
+            processor.start().unwrap();
+            validator.join().unwrap();
+
+            assert_eq!(sender.requests(Message_MessageType::TP_REGISTER_REQUEST), run);
+            assert_eq!(sender.requests(Message_MessageType::TP_UNREGISTER_REQUEST), run);
+            assert!(!handle.is_shutdown_requested());
+        }
+    }
+
+    /// Returns a TpProcessRequest for the given family version from the
+    /// validator.
+    fn process_message(family_name: &str, correlation_id: &str) -> MessageResult {
+        let mut request = TpProcessRequest::new();
+        request.set_header(make_header(family_name, "1.0"));
+        let mut message = Message::new();
+        message.set_message_type(Message_MessageType::TP_PROCESS_REQUEST);
+        message.set_correlation_id(String::from(correlation_id));
+        message.set_content(request.write_to_bytes().unwrap());
+        Ok(message)
+    }
+
+    fn count_replies(sender: &MockSender, message_type: Message_MessageType) -> usize {
+        sender.replies().iter().filter(|&&(t, _)| t == message_type).count()
+    }
+
+    /// Records how many applies run at once, and blocks each apply until
+    /// the gate is opened.
+    #[derive(Default)]
+    struct GatedHandler {
+        open: AtomicBool,
+        running: AtomicUsize,
+        max_running: AtomicUsize
+    }
+
+    impl TransactionHandler for GatedHandler {
+        fn family_name(&self) -> String {
+            String::from("gated")
+        }
+
+        fn family_versions(&self) -> Vec<String> {
+            vec![String::from("1.0")]
+        }
+
+        fn namespaces(&self) -> Vec<String> {
+            vec![String::from("abcdef")]
+        }
+
+        fn apply(&self, _request: &TpProcessRequest, _context: &mut TransactionContext)
+            -> Result<(), ApplyError>
+        {
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_running.fetch_max(running, Ordering::SeqCst);
+            while !self.open.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(1));
+            }
+            thread::sleep(Duration::from_millis(10));
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn workers_apply_up_to_max_occupancy() {
+        let handler = Arc::new(GatedHandler::default());
+        let conn = MockConnection::new(Registration::Accept);
+        let sender = conn.sender.clone();
+        let inbound = conn.inbound.clone();
+
+        let mut processor = TransactionProcessor::with_connection("mock", conn);
+        processor.add_handler(&*handler);
+        processor.set_max_occupancy(2);
+
+        let handle = processor.shutdown_handle();
+        let gated = handler.clone();
+        let validator = thread::spawn(move || {
+            wait_for(|| inbound.lock().unwrap().len() == 1);
+            for i in 0..6 {
+                inbound.lock().unwrap()[0]
+                    .send(process_message("gated", &i.to_string())).unwrap();
+            }
+            wait_for(|| gated.running.load(Ordering::SeqCst) == 2);
+            gated.open.store(true, Ordering::SeqCst);
+            wait_for(|| count_replies(&sender, Message_MessageType::TP_PROCESS_RESPONSE) == 6);
+            handle.request_shutdown();
+        });
+
+        processor.start().unwrap();
+        validator.join().unwrap();
+
+        assert_eq!(handler.max_running.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn ping_answered_while_workers_are_busy() {
+        let handler = Arc::new(GatedHandler::default());
+        let conn = MockConnection::new(Registration::Accept);
+        let sender = conn.sender.clone();
+        let inbound = conn.inbound.clone();
+
+        let mut processor = TransactionProcessor::with_connection("mock", conn);
+        processor.add_handler(&*handler);
+        processor.set_max_occupancy(1);
+
+        let handle = processor.shutdown_handle();
+        let gated = handler.clone();
+        let validator = thread::spawn(move || {
+            wait_for(|| inbound.lock().unwrap().len() == 1);
+            let inbound = inbound.lock().unwrap()[0].clone();
+            inbound.send(process_message("gated", "busy")).unwrap();
+            inbound.send(process_message("gated", "queued")).unwrap();
+            wait_for(|| gated.running.load(Ordering::SeqCst) == 1);
+
+            inbound.send(make_message(Message_MessageType::PING_REQUEST, "ping")).unwrap();
+            wait_for(|| count_replies(&sender, Message_MessageType::PING_RESPONSE) == 1);
+            assert_eq!(count_replies(&sender, Message_MessageType::TP_PROCESS_RESPONSE), 0);
+
+            gated.open.store(true, Ordering::SeqCst);
+            wait_for(|| count_replies(&sender, Message_MessageType::TP_PROCESS_RESPONSE) == 2);
+            handle.request_shutdown();
+        });
+
+        processor.start().unwrap();
+        validator.join().unwrap();
+    }
+
+    #[test]
+    fn full_queue_holds_back_messages() {
+        let handler = Arc::new(GatedHandler::default());
+        let conn = MockConnection::new(Registration::Accept);
+        let sender = conn.sender.clone();
+        let inbound = conn.inbound.clone();
+
+        let mut processor = TransactionProcessor::with_connection("mock", conn);
+        processor.add_handler(&*handler);
+        processor.set_max_occupancy(1);
+
+        let handle = processor.shutdown_handle();
+        let gated = handler.clone();
+        let validator = thread::spawn(move || {
+            wait_for(|| inbound.lock().unwrap().len() == 1);
+            let inbound = inbound.lock().unwrap()[0].clone();
+            inbound.send(process_message("gated", "busy")).unwrap();
+            inbound.send(process_message("gated", "queued")).unwrap();
+            inbound.send(process_message("gated", "waiting")).unwrap();
+            inbound.send(make_message(Message_MessageType::PING_REQUEST, "ping")).unwrap();
+            wait_for(|| gated.running.load(Ordering::SeqCst) == 1);
+
+            // The receiving thread waits for room in the queue before
+            // reading the ping
+            thread::sleep(Duration::from_millis(50));
+            assert_eq!(count_replies(&sender, Message_MessageType::PING_RESPONSE), 0);
+
+            gated.open.store(true, Ordering::SeqCst);
+            wait_for(|| count_replies(&sender, Message_MessageType::PING_RESPONSE) == 1);
+            wait_for(|| count_replies(&sender, Message_MessageType::TP_PROCESS_RESPONSE) == 3);
+            handle.request_shutdown();
+        });
+
+        processor.start().unwrap();
+        validator.join().unwrap();
+    }
+
+    #[test]
+    fn worker_reply_disconnect_reconnects() {
+        let handler = Arc::new(GatedHandler::default());
+        handler.open.store(true, Ordering::SeqCst);
+        let conn = MockConnection::new(Registration::Accept);
+        let sender = conn.sender.clone();
+        let inbound = conn.inbound.clone();
+
+        let mut processor = TransactionProcessor::with_connection("mock", conn);
+        processor.add_handler(&*handler);
+        processor.set_max_occupancy(2);
+        processor.set_reconnect_policy(ReconnectPolicy::new()
+                                       .initial_delay(Duration::from_millis(1)));
+
+        let handle = processor.shutdown_handle();
+        let validator = thread::spawn(move || {
+            wait_for(|| inbound.lock().unwrap().len() == 1);
+            sender.log.lock().unwrap().disconnected = true;
+            inbound.lock().unwrap()[0].send(process_message("gated", "lost")).unwrap();
+
+            wait_for(|| inbound.lock().unwrap().len() == 2);
+            sender.log.lock().unwrap().disconnected = false;
+            handle.request_shutdown();
+            sender
+        });
+
+        processor.start().unwrap();
+        let sender = validator.join().unwrap();
+
+        assert_eq!(sender.requests(Message_MessageType::TP_REGISTER_REQUEST), 2);
+    }
+
+    #[test]
+    fn duplicate_family_version_registered_once() {
+        let first = MockHandler::new("intkey", &["1.0"]);
+        let second = MockHandler::new("intkey", &["1.0", "2.0"]);
+        let conn = MockConnection::new(Registration::Accept);
+        let sender = conn.sender.clone();
+
+        let mut processor = TransactionProcessor::with_connection("mock", conn);
+        processor.add_handler(&first);
+        processor.add_handler(&second);
+
+        assert!(processor.register(sender.clone()).unwrap());
+        assert_eq!(sender.requests(Message_MessageType::TP_REGISTER_REQUEST), 2);
+        assert_eq!(processor.family_versions,
+                   vec![(String::from("intkey"), String::from("1.0")),
+                        (String::from("intkey"), String::from("2.0"))]);
+
+        let found = processor.find_handler(&make_header("intkey", "1.0")).unwrap();
+        assert_eq!(found.family_versions().len(), 2);
+    }
+
+    #[test]
+    fn gives_up_when_registration_is_not_answered() {
+        let handler = MockHandler::new("intkey", &["1.0"]);
+        let conn = MockConnection::new(Registration::Ignore);
+        let sender = conn.sender.clone();
+        let inbound = conn.inbound.clone();
+
+        let mut processor = TransactionProcessor::with_connection("mock", conn);
+        processor.add_handler(&handler);
+        processor.set_register_timeout(Duration::from_millis(10));
+        processor.set_reconnect_policy(ReconnectPolicy::new()
+                                       .initial_delay(Duration::from_millis(1))
+                                       .jitter(0.0)
+                                       .max_attempts(2));
+
+        match processor.start() {
+            Err(ProcessorError::ConnectionError(_)) => (),
+            result => panic!("Expected a ConnectionError, got {:?}", result)
         }
+        // The first connection, then the two reconnect attempts
+        assert_eq!(inbound.lock().unwrap().len(), 3);
+        assert_eq!(sender.requests(Message_MessageType::TP_REGISTER_REQUEST), 3);
     }
 }
@@ -0,0 +1,179 @@
+/*
+ * Copyright 2017 Bitwise IO, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+use rand;
+use rand::Rng;
+
+use std::time::Duration;
+
+/// Events reported to the callback set with
+/// TransactionProcessor::set_connection_callback.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConnectionEvent {
+    /// The processor connected and registered with the validator for the
+    /// first time.
+    Connected,
+    /// The validator disconnected.
+    Disconnected,
+    /// The processor reconnected and registered again after a disconnect.
+    Reregistered,
+}
+
+/// Controls how the processor reconnects to the validator after losing its
+/// connection. Delays double with each failed attempt, from the initial
+/// delay up to the max delay, and are randomly spread by the jitter
+/// fraction.
+#[derive(Clone, Debug)]
+pub struct ReconnectPolicy {
+    initial_delay: Duration,
+    max_delay: Duration,
+    jitter: f64,
+    max_attempts: Option<u32>,
+}
+
+impl ReconnectPolicy {
+    /// Creates a policy which waits 100ms before the first attempt, backs
+    /// off to at most 30s with 10% jitter, and never gives up.
+    pub fn new() -> Self {
+        ReconnectPolicy {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(30),
+            jitter: 0.1,
+            max_attempts: None,
+        }
+    }
+
+    /// Sets the delay before the first reconnect attempt.
+    pub fn initial_delay(mut self, delay: Duration) -> Self {
+        self.initial_delay = delay;
+        self
+    }
+
+    /// Sets the longest delay between reconnect attempts.
+    pub fn max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+
+    /// Sets the fraction, between 0 and 1, by which each delay is randomly
+    /// lengthened or shortened.
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.max(0.0).min(1.0);
+        self
+    }
+
+    /// Sets the number of consecutive failed attempts after which the
+    /// processor gives up, and TransactionProcessor::start returns a
+    /// ProcessorError::ConnectionError. By default, it never gives up.
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = Some(max_attempts);
+        self
+    }
+
+    /// Returns true if the given attempt, counting from 1, is beyond the
+    /// policy's max attempts.
+    pub fn is_exhausted(&self, attempt: u32) -> bool {
+        match self.max_attempts {
+            Some(max_attempts) => attempt > max_attempts,
+            None => false
+        }
+    }
+
+    /// Returns the delay before the given attempt, counting from 1,
+    /// without jitter.
+    pub fn base_delay(&self, attempt: u32) -> Duration {
+        let mut delay = self.initial_delay;
+        for _ in 1..attempt {
+            if delay >= self.max_delay {
+                break;
+            }
+            delay = delay.checked_mul(2).unwrap_or(self.max_delay);
+        }
+        if delay > self.max_delay {
+            self.max_delay
+        } else {
+            delay
+        }
+    }
+
+    /// Returns the delay before the given attempt, counting from 1, with
+    /// jitter applied.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let base = self.base_delay(attempt);
+        if self.jitter == 0.0 {
+            return base;
+        }
+
+        let factor = rand::thread_rng().gen_range(1.0 - self.jitter, 1.0 + self.jitter);
+        let millis = duration_as_millis(base) as f64 * factor;
+        Duration::from_millis(millis as u64)
+    }
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy::new()
+    }
+}
+
+fn duration_as_millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + (duration.subsec_nanos() / 1_000_000) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::ReconnectPolicy;
+
+    #[test]
+    fn delays_back_off_to_max() {
+        let policy = ReconnectPolicy::new()
+            .initial_delay(Duration::from_millis(100))
+            .max_delay(Duration::from_millis(500))
+            .jitter(0.0);
+
+        assert_eq!(policy.delay(1), Duration::from_millis(100));
+        assert_eq!(policy.delay(2), Duration::from_millis(200));
+        assert_eq!(policy.delay(3), Duration::from_millis(400));
+        assert_eq!(policy.delay(4), Duration::from_millis(500));
+        assert_eq!(policy.delay(100), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn jitter_stays_in_range() {
+        let policy = ReconnectPolicy::new()
+            .initial_delay(Duration::from_millis(1000))
+            .jitter(0.5);
+
+        for _ in 0..100 {
+            let delay = policy.delay(1);
+            assert!(delay >= Duration::from_millis(500));
+            assert!(delay <= Duration::from_millis(1500));
+        }
+    }
+
+    #[test]
+    fn max_attempts() {
+        let policy = ReconnectPolicy::new();
+        assert!(!policy.is_exhausted(1000));
+
+        let policy = policy.max_attempts(3);
+        assert!(!policy.is_exhausted(3));
+        assert!(policy.is_exhausted(4));
+    }
+}
@@ -0,0 +1,204 @@
+/*
+ * Copyright 2017 Bitwise IO, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! The requests a transaction context sends the validator, and the decoding
+//! of the validator's responses, shared by TransactionContext and
+//! AsyncTransactionContext. The processors' registration request is built
+//! here as well.
+
+use protobuf;
+use protobuf::RepeatedField;
+
+use std::collections::HashMap;
+
+use messages::events::Event;
+use messages::events::Event_Attribute;
+use messages::processor::TpRegisterRequest;
+use messages::state_context::*;
+
+use super::handler::ContextError;
+
+/// Builds the TpRegisterRequest for one version of a transaction family.
+pub(super) fn register_request(family: &str, version: &str, namespaces: Vec<String>,
+                               max_occupancy: u32)
+    -> TpRegisterRequest
+{
+    let mut request = TpRegisterRequest::new();
+    request.set_family(String::from(family));
+    request.set_version(String::from(version));
+    request.set_namespaces(RepeatedField::from_vec(namespaces));
+    request.set_max_occupancy(max_occupancy);
+    request
+}
+
+pub(super) fn state_get_request(context_id: &str, addresses: &[String]) -> TpStateGetRequest {
+    let mut request = TpStateGetRequest::new();
+    request.set_context_id(String::from(context_id));
+    request.set_addresses(RepeatedField::from_slice(addresses));
+    request
+}
+
+pub(super) fn decode_state_get_response(addresses: &[String], content: &[u8])
+    -> Result<HashMap<String, Vec<u8>>, ContextError>
+{
+    let response: TpStateGetResponse = protobuf::parse_from_bytes(content)?;
+    match response.get_status() {
+        TpStateGetResponse_Status::OK => {
+            if response.get_entries().is_empty() && !addresses.is_empty() {
+                return Err(ContextError::ResponseAttributeError(String::from("TpStateGetResponse is missing entries.")))
+            }
+            Ok(response.get_entries()
+                .iter()
+                .filter(|entry| !entry.get_data().is_empty())
+                .map(|entry| (String::from(entry.get_address()), Vec::from(entry.get_data())))
+                .collect())
+        },
+        TpStateGetResponse_Status::AUTHORIZATION_ERROR => {
+            Err(ContextError::AuthorizationError(format!("Tried to get unauthorized addresses: {:?}", addresses)))
+        },
+        TpStateGetResponse_Status::STATUS_UNSET => {
+            Err(ContextError::ResponseAttributeError(String::from("Status was not set for TpStateGetResponse")))
+        }
+    }
+}
+
+/// Builds a TpStateSetRequest for the given entries, returning it along
+/// with the addresses being set.
+pub(super) fn state_set_request(context_id: &str, entries: Vec<(String, Vec<u8>)>)
+    -> (Vec<String>, TpStateSetRequest)
+{
+    let addresses: Vec<String> = entries.iter().map(|&(ref address, _)| address.clone()).collect();
+    let state_entries: Vec<TpStateEntry> = entries
+        .into_iter()
+        .map(|(address, payload)| {
+            let mut entry = TpStateEntry::new();
+            entry.set_address(address);
+            entry.set_data(payload);
+            entry
+        })
+        .collect();
+
+    let mut request = TpStateSetRequest::new();
+    request.set_context_id(String::from(context_id));
+    request.set_entries(RepeatedField::from_vec(state_entries));
+    (addresses, request)
+}
+
+pub(super) fn decode_state_set_response(addresses: &[String], content: &[u8])
+    -> Result<(), ContextError>
+{
+    let response: TpStateSetResponse = protobuf::parse_from_bytes(content)?;
+    match response.get_status() {
+        TpStateSetResponse_Status::OK => {
+            Ok(())
+        },
+        TpStateSetResponse_Status::AUTHORIZATION_ERROR => {
+            Err(ContextError::AuthorizationError(format!("Tried to set unauthorized addresses: {:?}", addresses)))
+        },
+        TpStateSetResponse_Status::STATUS_UNSET => {
+            Err(ContextError::ResponseAttributeError(String::from("Status was not set for TpStateSetResponse")))
+        }
+    }
+}
+
+pub(super) fn state_delete_request(context_id: &str, addresses: &[String]) -> TpStateDeleteRequest {
+    let mut request = TpStateDeleteRequest::new();
+    request.set_context_id(String::from(context_id));
+    request.set_addresses(RepeatedField::from_slice(addresses));
+    request
+}
+
+pub(super) fn decode_state_delete_response(addresses: &[String], content: &[u8])
+    -> Result<Option<Vec<String>>, ContextError>
+{
+    let response: TpStateDeleteResponse = protobuf::parse_from_bytes(content)?;
+    match response.get_status() {
+        TpStateDeleteResponse_Status::OK => {
+            Ok(Some(Vec::from(response.get_addresses())))
+        },
+        TpStateDeleteResponse_Status::AUTHORIZATION_ERROR => {
+            Err(ContextError::AuthorizationError(format!("Tried to delete unauthorized address: {:?}", addresses)))
+        },
+        TpStateDeleteResponse_Status::STATUS_UNSET => {
+            Err(ContextError::ResponseAttributeError(String::from("Status was not set for TpStateDeleteResponse")))
+        }
+    }
+}
+
+pub(super) fn receipt_add_data_request(context_id: &str, data: &[u8]) -> TpReceiptAddDataRequest {
+    let mut request = TpReceiptAddDataRequest::new();
+    request.set_context_id(String::from(context_id));
+    request.set_data(Vec::from(data));
+    request
+}
+
+pub(super) fn decode_receipt_add_data_response(data: &[u8], content: &[u8])
+    -> Result<(), ContextError>
+{
+    let response: TpReceiptAddDataResponse = protobuf::parse_from_bytes(content)?;
+    match response.get_status() {
+        TpReceiptAddDataResponse_Status::OK => {
+            Ok(())
+        },
+        TpReceiptAddDataResponse_Status::ERROR => {
+            Err(ContextError::TransactionReceiptError(format!("Failed to add receipt data {:?}", data)))
+        },
+        TpReceiptAddDataResponse_Status::STATUS_UNSET => {
+            Err(ContextError::ResponseAttributeError(String::from("Status was not set for TpReceiptAddDataResponse")))
+        }
+    }
+}
+
+pub(super) fn event_add_request(context_id: &str, event_type: String,
+                                attributes: Vec<(String, String)>, data: &[u8])
+    -> TpEventAddRequest
+{
+    let mut event = Event::new();
+    event.set_event_type(event_type);
+
+    let mut attributes_vec = Vec::new();
+    for (key, value) in attributes {
+        let mut attribute = Event_Attribute::new();
+        attribute.set_key(key);
+        attribute.set_value(value);
+        attributes_vec.push(attribute);
+    }
+    event.set_attributes(RepeatedField::from_vec(attributes_vec));
+    event.set_data(Vec::from(data));
+
+    let mut request = TpEventAddRequest::new();
+    request.set_context_id(String::from(context_id));
+    request.set_event(event);
+    request
+}
+
+pub(super) fn decode_event_add_response(event: &Event, content: &[u8])
+    -> Result<(), ContextError>
+{
+    let response: TpEventAddResponse = protobuf::parse_from_bytes(content)?;
+    match response.get_status() {
+        TpEventAddResponse_Status::OK => {
+            Ok(())
+        },
+        TpEventAddResponse_Status::ERROR => {
+            Err(ContextError::TransactionReceiptError(format!("Failed to add event {:?}", event)))
+        },
+        TpEventAddResponse_Status::STATUS_UNSET => {
+            Err(ContextError::ResponseAttributeError(String::from("Status was not set for TpEventAddRespons")))
+        }
+    }
+}
@@ -0,0 +1,273 @@
+/*
+ * Copyright 2017 Intel Corporation
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Decoding transaction receipts into plain Rust types.
+//!
+//! A receipt records what applying a committed transaction did: the state
+//! it changed, the events it added with TransactionContext::add_event, and
+//! the data it added with TransactionContext::add_receipt_data, in the
+//! order the handler added them. Data added with add_typed_receipt_data can
+//! be decoded again with the same codec.
+
+use protobuf;
+
+use std;
+use std::error::Error as StdError;
+
+use codec::Codec;
+use codec::CodecError;
+use messages::events;
+use messages::transaction_receipt::StateChangeList;
+use messages::transaction_receipt::StateChange_Type;
+use messages::transaction_receipt::TransactionReceipt;
+use messages::transaction_receipt;
+
+#[derive(Debug)]
+pub enum ReceiptError {
+    /// Returned when serialized state changes cannot be parsed
+    ProtobufError(protobuf::ProtobufError),
+    /// Returned when a state change is neither a set nor a delete
+    InvalidStateChange(String),
+}
+
+impl StdError for ReceiptError {
+    fn description(&self) -> &str {
+        match *self {
+            ReceiptError::ProtobufError(ref err) => err.description(),
+            ReceiptError::InvalidStateChange(ref msg) => msg,
+        }
+    }
+
+    fn cause(&self) -> Option<&StdError> {
+        match *self {
+            ReceiptError::ProtobufError(ref err) => Some(err),
+            ReceiptError::InvalidStateChange(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for ReceiptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            ReceiptError::ProtobufError(ref err) => write!(f, "ProtobufError: {}", err),
+            ReceiptError::InvalidStateChange(ref s) => write!(f, "InvalidStateChange: {}", s),
+        }
+    }
+}
+
+impl From<protobuf::ProtobufError> for ReceiptError {
+    fn from(err: protobuf::ProtobufError) -> Self {
+        ReceiptError::ProtobufError(err)
+    }
+}
+
+/// A change a transaction made to one address in state.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StateChange {
+    Set { address: String, value: Vec<u8> },
+    Delete { address: String },
+}
+
+impl StateChange {
+    pub fn address(&self) -> &str {
+        match *self {
+            StateChange::Set { ref address, .. } => address,
+            StateChange::Delete { ref address } => address,
+        }
+    }
+
+    fn from_proto(mut change: transaction_receipt::StateChange)
+        -> Result<StateChange, ReceiptError>
+    {
+        match change.get_field_type() {
+            StateChange_Type::SET => Ok(StateChange::Set {
+                address: change.take_address(),
+                value: change.take_value(),
+            }),
+            StateChange_Type::DELETE => Ok(StateChange::Delete {
+                address: change.take_address(),
+            }),
+            StateChange_Type::TYPE_UNSET => Err(ReceiptError::InvalidStateChange(format!(
+                "No change type for address {}", change.get_address()))),
+        }
+    }
+}
+
+/// Decodes a serialized StateChangeList, such as the data of a
+/// "sawtooth/state-delta" event.
+pub fn decode_state_changes(data: &[u8]) -> Result<Vec<StateChange>, ReceiptError> {
+    let mut changes: StateChangeList = protobuf::parse_from_bytes(data)?;
+    changes.take_state_changes()
+        .into_iter()
+        .map(StateChange::from_proto)
+        .collect()
+}
+
+/// An event added by a transaction, in the form given to
+/// TransactionContext::add_event.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Event {
+    pub event_type: String,
+    pub attributes: Vec<(String, String)>,
+    pub data: Vec<u8>,
+}
+
+impl Event {
+    /// Returns the value of the first attribute with the key, if any.
+    pub fn attribute(&self, key: &str) -> Option<&str> {
+        self.attributes.iter()
+            .find(|&&(ref k, _)| k == key)
+            .map(|&(_, ref value)| value.as_str())
+    }
+}
+
+impl From<events::Event> for Event {
+    fn from(mut event: events::Event) -> Self {
+        Event {
+            event_type: event.take_event_type(),
+            attributes: event.take_attributes()
+                .into_iter()
+                .map(|mut attribute| (attribute.take_key(), attribute.take_value()))
+                .collect(),
+            data: event.take_data(),
+        }
+    }
+}
+
+/// The decoded receipt of a committed transaction.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Receipt {
+    pub transaction_id: String,
+    pub state_changes: Vec<StateChange>,
+    pub events: Vec<Event>,
+    /// The receipt data, in the order it was added
+    pub data: Vec<Vec<u8>>,
+}
+
+impl Receipt {
+    pub fn from_proto(mut receipt: TransactionReceipt) -> Result<Receipt, ReceiptError> {
+        let state_changes = receipt.take_state_changes()
+            .into_iter()
+            .map(StateChange::from_proto)
+            .collect::<Result<Vec<StateChange>, ReceiptError>>()?;
+        Ok(Receipt {
+            transaction_id: receipt.take_transaction_id(),
+            state_changes: state_changes,
+            events: receipt.take_events().into_iter().map(Event::from).collect(),
+            data: receipt.take_data().into_vec(),
+        })
+    }
+
+    /// Returns the change the transaction made at the address, if any.
+    pub fn state_change(&self, address: &str) -> Option<&StateChange> {
+        self.state_changes.iter().find(|change| change.address() == address)
+    }
+
+    /// Returns the events of the given type, in the order they were added.
+    pub fn events_of_type(&self, event_type: &str) -> Vec<&Event> {
+        self.events.iter().filter(|event| event.event_type == event_type).collect()
+    }
+
+    /// Decodes each piece of receipt data with the codec it was added with,
+    /// in the order it was added.
+    ///
+    /// # Arguments
+    ///
+    /// * `codec` - the codec given to add_typed_receipt_data
+    pub fn decode_data<T, C: Codec<T>>(&self, codec: &C) -> Result<Vec<T>, CodecError> {
+        self.data.iter().map(|data| codec.decode(data)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use protobuf;
+    use protobuf::RepeatedField;
+
+    use codec::ProtobufCodec;
+    use messages::events;
+    use messages::events::Event_Attribute;
+    use messages::transaction::TransactionHeader;
+    use messages::transaction_receipt;
+    use messages::transaction_receipt::StateChangeList;
+    use messages::transaction_receipt::StateChange_Type;
+    use messages::transaction_receipt::TransactionReceipt;
+
+    use super::decode_state_changes;
+    use super::Receipt;
+    use super::ReceiptError;
+    use super::StateChange;
+
+    fn make_change(address: &str, value: &[u8], change_type: StateChange_Type)
+        -> transaction_receipt::StateChange
+    {
+        let mut change = transaction_receipt::StateChange::new();
+        change.set_address(String::from(address));
+        change.set_value(value.to_vec());
+        change.set_field_type(change_type);
+        change
+    }
+
+    #[test]
+    fn decode_receipt() {
+        let mut attribute = Event_Attribute::new();
+        attribute.set_key(String::from("address"));
+        attribute.set_value(String::from("abc123"));
+        let mut event = events::Event::new();
+        event.set_event_type(String::from("intkey/set"));
+        event.set_attributes(RepeatedField::from_vec(vec![attribute]));
+
+        let mut header = TransactionHeader::new();
+        header.set_family_name(String::from("intkey"));
+        let data = protobuf::Message::write_to_bytes(&header).unwrap();
+
+        let mut receipt = TransactionReceipt::new();
+        receipt.set_transaction_id(String::from("txn"));
+        receipt.set_state_changes(RepeatedField::from_vec(vec![
+            make_change("abc123", b"1", StateChange_Type::SET),
+            make_change("def456", b"", StateChange_Type::DELETE),
+        ]));
+        receipt.set_events(RepeatedField::from_vec(vec![event]));
+        receipt.set_data(RepeatedField::from_vec(vec![data]));
+
+        let receipt = Receipt::from_proto(receipt).unwrap();
+        assert_eq!(receipt.transaction_id, "txn");
+        assert_eq!(receipt.state_change("abc123"), Some(&StateChange::Set {
+            address: String::from("abc123"),
+            value: b"1".to_vec(),
+        }));
+        assert_eq!(receipt.state_change("def456"),
+                   Some(&StateChange::Delete { address: String::from("def456") }));
+        assert_eq!(receipt.events_of_type("intkey/set")[0].attribute("address"), Some("abc123"));
+
+        let headers: Vec<TransactionHeader> = receipt.decode_data(&ProtobufCodec).unwrap();
+        assert_eq!(headers[0].get_family_name(), "intkey");
+    }
+
+    #[test]
+    fn unset_change_type_is_invalid() {
+        let mut changes = StateChangeList::new();
+        changes.set_state_changes(RepeatedField::from_vec(vec![
+            make_change("abc123", b"1", StateChange_Type::TYPE_UNSET)]));
+        let data = protobuf::Message::write_to_bytes(&changes).unwrap();
+
+        match decode_state_changes(&data) {
+            Err(ReceiptError::InvalidStateChange(_)) => (),
+            result => panic!("Expected InvalidStateChange, got {:?}", result)
+        }
+    }
+}
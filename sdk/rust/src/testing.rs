@@ -0,0 +1,403 @@
+/*
+ * Copyright 2017 Bitwise IO, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! An in-memory stand-in for the validator, for unit testing transaction
+//! handlers without a running network.
+
+use protobuf;
+use protobuf::Message as M;
+use protobuf::MessageStatic;
+use protobuf::RepeatedField;
+
+use std::collections::HashMap;
+use std::sync::mpsc::channel;
+use std::sync::{Arc, Mutex};
+
+use messages::events::Event;
+use messages::processor::TpProcessRequest;
+use messages::state_context::*;
+use messages::validator::Message;
+use messages::validator::Message_MessageType;
+use messaging::stream::MessageFuture;
+use messaging::stream::MessageSender;
+use messaging::stream::SendError;
+use processor::handler::ApplyError;
+use processor::handler::TransactionContext;
+use processor::handler::TransactionHandler;
+
+#[derive(Clone, Default)]
+struct MockState {
+    state: HashMap<String, Vec<u8>>,
+    receipt_data: Vec<Vec<u8>>,
+    events: Vec<Event>
+}
+
+/// MockValidator answers a TransactionContext's requests from in-memory
+/// state, so that a handler's apply can be called directly in a test.
+///
+/// Clones share the same state.
+#[derive(Clone, Default)]
+pub struct MockValidator {
+    state: Arc<Mutex<MockState>>,
+    strict: bool
+}
+
+impl MockValidator {
+    /// Creates a MockValidator with empty state and no receipt data or
+    /// events.
+    pub fn new() -> MockValidator {
+        MockValidator::default()
+    }
+
+    /// Seeds the state at the given address.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - the address to set
+    /// * `data` - the data to store at the address
+    pub fn set_state(&self, address: &str, data: &[u8]) {
+        self.state.lock().unwrap().state.insert(String::from(address), Vec::from(data));
+    }
+
+    /// Returns the data at the given address, if it has been set.
+    pub fn get_state(&self, address: &str) -> Option<Vec<u8>> {
+        self.state.lock().unwrap().state.get(address).cloned()
+    }
+
+    /// Returns a copy of the entire state.
+    pub fn state(&self) -> HashMap<String, Vec<u8>> {
+        self.state.lock().unwrap().state.clone()
+    }
+
+    /// Returns the receipt data added by handlers, in the order it was added.
+    pub fn receipt_data(&self) -> Vec<Vec<u8>> {
+        self.state.lock().unwrap().receipt_data.clone()
+    }
+
+    /// Returns the events added by handlers, in the order they were added.
+    pub fn events(&self) -> Vec<Event> {
+        self.state.lock().unwrap().events.clone()
+    }
+
+    /// Sets whether the contexts passed to apply check each request against
+    /// the inputs and outputs in the transaction header, as with
+    /// TransactionProcessor::set_strict_mode.
+    pub fn set_strict_mode(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Returns a MessageSender whose requests are answered by this
+    /// validator.
+    pub fn sender(&self) -> MockMessageSender {
+        MockMessageSender {
+            state: self.state.clone()
+        }
+    }
+
+    /// Returns a TransactionContext backed by this validator.
+    ///
+    /// # Arguments
+    ///
+    /// * `context_id` - the context_id the handler will pass in its requests
+    pub fn context(&self, context_id: &str) -> TransactionContext {
+        TransactionContext::new(context_id, self.sender())
+    }
+
+    /// Calls the handler's apply with the given request and a context
+    /// backed by this validator.
+    ///
+    /// As with the validator, the state changes, receipt data, and events
+    /// of a transaction are discarded if apply returns an error.
+    ///
+    /// # Arguments
+    ///
+    /// * `handler` - the handler to test
+    /// * `request` - the request passed to apply
+    pub fn apply(&self, handler: &TransactionHandler, request: &TpProcessRequest)
+        -> Result<(), ApplyError>
+    {
+        let snapshot = self.state.lock().unwrap().clone();
+        let mut context = self.context(request.get_context_id());
+        if self.strict {
+            context.set_declared_addresses(request.get_header().get_inputs(),
+                                           request.get_header().get_outputs());
+        }
+
+        let result = handler.apply(request, &mut context);
+        if result.is_err() {
+            *self.state.lock().unwrap() = snapshot;
+        }
+        result
+    }
+}
+
+/// A MessageSender which answers requests from a MockValidator's state
+/// instead of sending them over a connection.
+#[derive(Clone)]
+pub struct MockMessageSender {
+    state: Arc<Mutex<MockState>>
+}
+
+impl MockMessageSender {
+    /// Handles a request, returning the type and content of the reply.
+    fn handle(&self, message_type: Message_MessageType, contents: &[u8])
+        -> Result<(Message_MessageType, Vec<u8>), SendError>
+    {
+        let mut state = self.state.lock().map_err(|_| SendError::UnknownError)?;
+        match message_type {
+            Message_MessageType::TP_STATE_GET_REQUEST => {
+                let request: TpStateGetRequest = parse(contents)?;
+                let entries = request.get_addresses()
+                    .iter()
+                    .map(|address| {
+                        let mut entry = TpStateEntry::new();
+                        entry.set_address(address.clone());
+                        if let Some(data) = state.state.get(address) {
+                            entry.set_data(data.clone());
+                        }
+                        entry
+                    })
+                    .collect();
+
+                let mut response = TpStateGetResponse::new();
+                response.set_status(TpStateGetResponse_Status::OK);
+                response.set_entries(RepeatedField::from_vec(entries));
+                reply(Message_MessageType::TP_STATE_GET_RESPONSE, &response)
+            }
+            Message_MessageType::TP_STATE_SET_REQUEST => {
+                let request: TpStateSetRequest = parse(contents)?;
+                let mut addresses = Vec::new();
+                for entry in request.get_entries() {
+                    addresses.push(String::from(entry.get_address()));
+                    state.state.insert(String::from(entry.get_address()),
+                                       Vec::from(entry.get_data()));
+                }
+
+                let mut response = TpStateSetResponse::new();
+                response.set_status(TpStateSetResponse_Status::OK);
+                response.set_addresses(RepeatedField::from_vec(addresses));
+                reply(Message_MessageType::TP_STATE_SET_RESPONSE, &response)
+            }
+            Message_MessageType::TP_STATE_DELETE_REQUEST => {
+                let request: TpStateDeleteRequest = parse(contents)?;
+                let deleted = request.get_addresses()
+                    .iter()
+                    .filter(|address| state.state.remove(*address).is_some())
+                    .cloned()
+                    .collect();
+
+                let mut response = TpStateDeleteResponse::new();
+                response.set_status(TpStateDeleteResponse_Status::OK);
+                response.set_addresses(RepeatedField::from_vec(deleted));
+                reply(Message_MessageType::TP_STATE_DELETE_RESPONSE, &response)
+            }
+            Message_MessageType::TP_RECEIPT_ADD_DATA_REQUEST => {
+                let request: TpReceiptAddDataRequest = parse(contents)?;
+                state.receipt_data.push(Vec::from(request.get_data()));
+
+                let mut response = TpReceiptAddDataResponse::new();
+                response.set_status(TpReceiptAddDataResponse_Status::OK);
+                reply(Message_MessageType::TP_RECEIPT_ADD_DATA_RESPONSE, &response)
+            }
+            Message_MessageType::TP_EVENT_ADD_REQUEST => {
+                let request: TpEventAddRequest = parse(contents)?;
+                state.events.push(request.get_event().clone());
+
+                let mut response = TpEventAddResponse::new();
+                response.set_status(TpEventAddResponse_Status::OK);
+                reply(Message_MessageType::TP_EVENT_ADD_RESPONSE, &response)
+            }
+            _ => {
+                error!("MockValidator cannot handle message type: {:?}", message_type);
+                Err(SendError::UnknownError)
+            }
+        }
+    }
+}
+
+impl MessageSender for MockMessageSender {
+    fn send(&mut self, destination: Message_MessageType, correlation_id: &str,
+            contents: &[u8])
+        -> Result<MessageFuture, SendError>
+    {
+        let (message_type, content) = self.handle(destination, contents)?;
+
+        let mut message = Message::new();
+        message.set_message_type(message_type);
+        message.set_correlation_id(String::from(correlation_id));
+        message.set_content(content);
+
+        let (tx, rx) = channel();
+        tx.send(Ok(message)).map_err(|_| SendError::UnknownError)?;
+        Ok(MessageFuture::new(rx))
+    }
+
+    fn reply(&mut self, _destination: Message_MessageType, _correlation_id: &str,
+             _contents: &[u8])
+        -> Result<(), SendError>
+    {
+        Ok(())
+    }
+
+    fn close(&mut self) {}
+}
+
+fn parse<R: M + MessageStatic>(contents: &[u8]) -> Result<R, SendError> {
+    protobuf::parse_from_bytes(contents).map_err(|err| {
+        error!("MockValidator cannot parse request: {}", err);
+        SendError::UnknownError
+    })
+}
+
+fn reply<R: M>(message_type: Message_MessageType, response: &R)
+    -> Result<(Message_MessageType, Vec<u8>), SendError>
+{
+    response.write_to_bytes()
+        .map(|content| (message_type, content))
+        .map_err(|_| SendError::UnknownError)
+}
+
+#[cfg(test)]
+mod tests {
+    use protobuf::RepeatedField;
+
+    use messages::processor::TpProcessRequest;
+    use messages::transaction::TransactionHeader;
+    use processor::handler::ApplyError;
+    use processor::handler::TransactionContext;
+    use processor::handler::TransactionHandler;
+
+    use super::MockValidator;
+
+    /// Moves the value at "from" to "to", failing after the move if the
+    /// payload is "fail".
+    struct MoveHandler;
+
+    impl TransactionHandler for MoveHandler {
+        fn family_name(&self) -> String {
+            String::from("move")
+        }
+
+        fn family_versions(&self) -> Vec<String> {
+            vec![String::from("1.0")]
+        }
+
+        fn namespaces(&self) -> Vec<String> {
+            vec![String::from("abcdef")]
+        }
+
+        fn apply(&self, request: &TpProcessRequest, context: &mut TransactionContext)
+            -> Result<(), ApplyError>
+        {
+            let value = context.get_state("from")?
+                .ok_or_else(|| ApplyError::InvalidTransaction(String::from("Nothing to move")))?;
+            context.set_state("to", &value)?;
+            context.delete_state(vec![String::from("from")])?;
+            context.add_receipt_data(b"moved")?;
+            context.add_event(String::from("move/moved"),
+                              vec![(String::from("to"), String::from("to"))],
+                              &value)?;
+
+            if request.get_payload() == b"fail" {
+                return Err(ApplyError::InternalError(String::from("Failed after moving")));
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn apply_updates_state_receipts_and_events() {
+        let validator = MockValidator::new();
+        validator.set_state("from", b"value");
+
+        let mut request = TpProcessRequest::new();
+        request.set_context_id(String::from("context"));
+        validator.apply(&MoveHandler, &request).unwrap();
+
+        assert_eq!(validator.get_state("from"), None);
+        assert_eq!(validator.get_state("to"), Some(b"value".to_vec()));
+        assert_eq!(validator.receipt_data(), vec![b"moved".to_vec()]);
+
+        let events = validator.events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].get_event_type(), "move/moved");
+        assert_eq!(events[0].get_data(), b"value");
+    }
+
+    #[test]
+    fn apply_error_discards_changes() {
+        let validator = MockValidator::new();
+        validator.set_state("from", b"value");
+
+        let mut request = TpProcessRequest::new();
+        request.set_payload(b"fail".to_vec());
+        assert!(validator.apply(&MoveHandler, &request).is_err());
+
+        assert_eq!(validator.get_state("from"), Some(b"value".to_vec()));
+        assert_eq!(validator.get_state("to"), None);
+        assert!(validator.receipt_data().is_empty());
+        assert!(validator.events().is_empty());
+
+        let validator = MockValidator::new();
+        match validator.apply(&MoveHandler, &TpProcessRequest::new()) {
+            Err(ApplyError::InvalidTransaction(_)) => (),
+            result => panic!("Expected an InvalidTransaction, got {:?}", result)
+        }
+    }
+
+    fn strict_request(inputs: &[&str], outputs: &[&str]) -> TpProcessRequest {
+        let mut header = TransactionHeader::new();
+        header.set_inputs(RepeatedField::from_vec(
+            inputs.iter().map(|a| String::from(*a)).collect()));
+        header.set_outputs(RepeatedField::from_vec(
+            outputs.iter().map(|a| String::from(*a)).collect()));
+
+        let mut request = TpProcessRequest::new();
+        request.set_header(header);
+        request
+    }
+
+    #[test]
+    fn strict_mode_allows_declared_prefixes() {
+        let mut validator = MockValidator::new();
+        validator.set_strict_mode(true);
+        validator.set_state("from", b"value");
+
+        validator.apply(&MoveHandler, &strict_request(&["fr", "to"], &["from", "t"])).unwrap();
+        assert_eq!(validator.get_state("to"), Some(b"value".to_vec()));
+    }
+
+    #[test]
+    fn strict_mode_rejects_undeclared_addresses() {
+        let mut validator = MockValidator::new();
+        validator.set_strict_mode(true);
+        validator.set_state("from", b"value");
+
+        // "to" is not a declared output
+        match validator.apply(&MoveHandler, &strict_request(&["from"], &["from"])) {
+            Err(ApplyError::InvalidTransaction(msg)) => {
+                assert!(msg.contains("to"));
+                assert!(msg.contains("outputs"));
+            }
+            result => panic!("Expected an InvalidTransaction, got {:?}", result)
+        }
+
+        // "from" is not a declared input
+        assert!(validator.apply(&MoveHandler, &strict_request(&["to"], &["from", "to"])).is_err());
+        assert_eq!(validator.get_state("from"), Some(b"value".to_vec()));
+    }
+}
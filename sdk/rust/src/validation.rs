@@ -0,0 +1,432 @@
+/*
+ * Copyright 2017 Intel Corporation
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Checking the structure and signatures of transactions, batches and
+//! blocks received from elsewhere, as the validator does before accepting
+//! them.
+//!
+//! A Verifier checks everything it can rather than stopping at the first
+//! problem, and returns a ValidationReport listing each problem with the
+//! id of the transaction, batch or block it was found in. Checks that need
+//! state, such as whether a transaction's dependencies are committed, are
+//! out of its scope.
+
+use protobuf;
+
+use std;
+
+use addressing::sha512_hex;
+use messages::batch::Batch;
+use messages::batch::BatchHeader;
+use messages::block::Block;
+use messages::block::BlockHeader;
+use messages::transaction::Transaction;
+use messages::transaction::TransactionHeader;
+use signing::Context;
+use signing::secp256k1::Secp256k1Context;
+use signing::secp256k1::Secp256k1PublicKey;
+
+/// The number of hex characters in a compressed or an uncompressed
+/// secp256k1 public key.
+const PUBLIC_KEY_LENGTHS: [usize; 2] = [66, 130];
+
+/// The number of hex characters in a compact secp256k1 signature.
+const SIGNATURE_LENGTH: usize = 128;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ItemType {
+    Transaction,
+    Batch,
+    Block,
+}
+
+/// What is wrong with a transaction, batch or block.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProblemKind {
+    /// The header cannot be parsed
+    InvalidHeader(String),
+    /// The signer public key in the header is not a valid public key
+    InvalidPublicKey(String),
+    /// The header signature is not the header signed by the signer
+    InvalidSignature,
+    /// The payload_sha512 in the header is not the hash of the payload
+    PayloadHashMismatch { expected: String, actual: String },
+    /// The transaction's batcher public key is not the batch's signer
+    BatcherKeyMismatch { expected: String, actual: String },
+    /// The ids in the header are not those of the items contained, in order
+    IdsMismatch { listed: Vec<String>, contained: Vec<String> },
+}
+
+/// A problem, and the item it was found in.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Problem {
+    pub item_type: ItemType,
+    /// The header signature of the item
+    pub id: String,
+    pub kind: ProblemKind,
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?} {}: ", self.item_type, self.id)?;
+        match self.kind {
+            ProblemKind::InvalidHeader(ref msg) => write!(f, "invalid header: {}", msg),
+            ProblemKind::InvalidPublicKey(ref key) =>
+                write!(f, "invalid signer public key {}", key),
+            ProblemKind::InvalidSignature => write!(f, "invalid header signature"),
+            ProblemKind::PayloadHashMismatch { ref expected, ref actual } =>
+                write!(f, "payload hash is {}, but header has {}", actual, expected),
+            ProblemKind::BatcherKeyMismatch { ref expected, ref actual } =>
+                write!(f, "batcher public key is {}, but batch was signed by {}",
+                       actual, expected),
+            ProblemKind::IdsMismatch { ref listed, ref contained } =>
+                write!(f, "header lists ids {:?}, but contains {:?}", listed, contained),
+        }
+    }
+}
+
+/// Every problem found in an item and the items it contains.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ValidationReport {
+    pub problems: Vec<Problem>,
+}
+
+impl ValidationReport {
+    /// Returns true if no problems were found.
+    pub fn is_valid(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    fn add(&mut self, item_type: ItemType, id: &str, kind: ProblemKind) {
+        self.problems.push(Problem {
+            item_type: item_type,
+            id: String::from(id),
+            kind: kind,
+        });
+    }
+}
+
+impl std::fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.is_valid() {
+            return write!(f, "no problems found");
+        }
+        for (i, problem) in self.problems.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+/// Verifies secp256k1-signed transactions, batches and blocks. Creating a
+/// Verifier sets up the signing context, which is costly, so one should be
+/// reused across items.
+pub struct Verifier {
+    context: Secp256k1Context,
+}
+
+impl Verifier {
+    pub fn new() -> Verifier {
+        Verifier {
+            context: Secp256k1Context::new(),
+        }
+    }
+
+    /// Checks the transaction's header signature and payload hash.
+    pub fn verify_transaction(&self, transaction: &Transaction) -> ValidationReport {
+        let mut report = ValidationReport::default();
+        self.check_transaction(transaction, None, &mut report);
+        report
+    }
+
+    /// Checks the batch's header signature and transaction ids, and each of
+    /// its transactions, including that they name the batch's signer as
+    /// their batcher.
+    pub fn verify_batch(&self, batch: &Batch) -> ValidationReport {
+        let mut report = ValidationReport::default();
+        self.check_batch(batch, &mut report);
+        report
+    }
+
+    /// Checks the block's header signature and batch ids, and each of its
+    /// batches.
+    pub fn verify_block(&self, block: &Block) -> ValidationReport {
+        let mut report = ValidationReport::default();
+        let id = block.get_header_signature();
+
+        match protobuf::parse_from_bytes::<BlockHeader>(block.get_header()) {
+            Ok(header) => {
+                self.check_signature(ItemType::Block, id, block.get_header(),
+                                     header.get_signer_public_key(), &mut report);
+                let contained = block.get_batches()
+                    .iter()
+                    .map(|batch| String::from(batch.get_header_signature()))
+                    .collect();
+                check_ids(ItemType::Block, id, header.get_batch_ids(), contained, &mut report);
+            }
+            Err(err) =>
+                report.add(ItemType::Block, id, ProblemKind::InvalidHeader(err.to_string()))
+        }
+
+        for batch in block.get_batches() {
+            self.check_batch(batch, &mut report);
+        }
+        report
+    }
+
+    fn check_batch(&self, batch: &Batch, report: &mut ValidationReport) {
+        let id = batch.get_header_signature();
+
+        let header = match protobuf::parse_from_bytes::<BatchHeader>(batch.get_header()) {
+            Ok(header) => header,
+            Err(err) => {
+                report.add(ItemType::Batch, id, ProblemKind::InvalidHeader(err.to_string()));
+                for transaction in batch.get_transactions() {
+                    self.check_transaction(transaction, None, report);
+                }
+                return;
+            }
+        };
+
+        self.check_signature(ItemType::Batch, id, batch.get_header(),
+                             header.get_signer_public_key(), report);
+        let contained = batch.get_transactions()
+            .iter()
+            .map(|transaction| String::from(transaction.get_header_signature()))
+            .collect();
+        check_ids(ItemType::Batch, id, header.get_transaction_ids(), contained, report);
+
+        for transaction in batch.get_transactions() {
+            self.check_transaction(transaction, Some(header.get_signer_public_key()), report);
+        }
+    }
+
+    /// Checks the transaction, and if it is in a batch, that its batcher is
+    /// the batch's signer.
+    fn check_transaction(&self, transaction: &Transaction, batcher_public_key: Option<&str>,
+                         report: &mut ValidationReport)
+    {
+        let id = transaction.get_header_signature();
+
+        let header = match protobuf::parse_from_bytes::<TransactionHeader>(
+            transaction.get_header())
+        {
+            Ok(header) => header,
+            Err(err) => {
+                report.add(ItemType::Transaction, id,
+                           ProblemKind::InvalidHeader(err.to_string()));
+                return;
+            }
+        };
+
+        self.check_signature(ItemType::Transaction, id, transaction.get_header(),
+                             header.get_signer_public_key(), report);
+
+        let payload_sha512 = sha512_hex(transaction.get_payload());
+        if header.get_payload_sha512() != payload_sha512 {
+            report.add(ItemType::Transaction, id, ProblemKind::PayloadHashMismatch {
+                expected: String::from(header.get_payload_sha512()),
+                actual: payload_sha512,
+            });
+        }
+
+        if let Some(batcher_public_key) = batcher_public_key {
+            if header.get_batcher_public_key() != batcher_public_key {
+                report.add(ItemType::Transaction, id, ProblemKind::BatcherKeyMismatch {
+                    expected: String::from(batcher_public_key),
+                    actual: String::from(header.get_batcher_public_key()),
+                });
+            }
+        }
+    }
+
+    fn check_signature(&self, item_type: ItemType, signature: &str, header: &[u8],
+                       public_key: &str, report: &mut ValidationReport)
+    {
+        // The key and signature are checked for length first, as parsing
+        // malformed hex panics
+        let key = if PUBLIC_KEY_LENGTHS.contains(&public_key.len()) {
+            Secp256k1PublicKey::from_hex(public_key).ok()
+        } else {
+            None
+        };
+        let key = match key {
+            Some(key) => key,
+            None => {
+                report.add(item_type, signature,
+                           ProblemKind::InvalidPublicKey(String::from(public_key)));
+                return;
+            }
+        };
+
+        let verified = signature.len() == SIGNATURE_LENGTH &&
+            self.context.verify(signature, header, &key).unwrap_or(false);
+        if !verified {
+            report.add(item_type, signature, ProblemKind::InvalidSignature);
+        }
+    }
+}
+
+impl Default for Verifier {
+    fn default() -> Self {
+        Verifier::new()
+    }
+}
+
+fn check_ids(item_type: ItemType, id: &str, listed: &[String], contained: Vec<String>,
+             report: &mut ValidationReport)
+{
+    if listed != contained.as_slice() {
+        report.add(item_type, id, ProblemKind::IdsMismatch {
+            listed: listed.to_vec(),
+            contained: contained,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use protobuf;
+    use protobuf::Message;
+    use protobuf::RepeatedField;
+
+    use builders::BatchBuilder;
+    use builders::TransactionBuilder;
+    use messages::batch::Batch;
+    use messages::batch::BatchHeader;
+    use messages::block::Block;
+    use messages::block::BlockHeader;
+    use signing;
+    use signing::create_context;
+    use signing::secp256k1::Secp256k1PrivateKey;
+
+    use super::ItemType;
+    use super::ProblemKind;
+    use super::Verifier;
+
+    static KEY1_PRIV_HEX: &'static str =
+        "2f1e7b7a130d7ba9da0068b3bb0ba1d79e7e77110302c9f746c3c2a63fe40088";
+    static KEY1_PUB_HEX: &'static str =
+        "026a2c795a9776f75464aa3bda3534c3154a6e91b357b1181d3f515110f84b67c5";
+    static KEY2_PRIV_HEX: &'static str =
+        "51b845c2cdde22fe646148f0b51eaf5feec8c82ee921d5e0cbe7619f3bb9c62d";
+    static KEY2_PUB_HEX: &'static str =
+        "039c20a66b4ec7995391dbec1d8bb0e2c6e6fd63cd259ed5b877cb4ea98858cf6d";
+
+    fn transaction_builder(payload: &[u8]) -> TransactionBuilder {
+        TransactionBuilder::new()
+            .family_name("intkey")
+            .family_version("1.0")
+            .payload(payload.to_vec())
+    }
+
+    #[test]
+    fn valid_block() {
+        let context = create_context("secp256k1").unwrap();
+        let private_key = Secp256k1PrivateKey::from_hex(KEY1_PRIV_HEX).unwrap();
+        let signer = signing::Signer::new(context.as_ref(), &private_key);
+
+        let transaction = transaction_builder(b"payload").build(&signer).unwrap();
+        let batch = BatchBuilder::new().add_transaction(transaction).build(&signer).unwrap();
+
+        let mut header = BlockHeader::new();
+        header.set_signer_public_key(String::from(KEY1_PUB_HEX));
+        header.set_batch_ids(RepeatedField::from_vec(
+            vec![String::from(batch.get_header_signature())]));
+        let header_bytes = header.write_to_bytes().unwrap();
+        let mut block = Block::new();
+        block.set_header_signature(signer.sign(&header_bytes).unwrap());
+        block.set_header(header_bytes);
+        block.set_batches(RepeatedField::from_vec(vec![batch]));
+
+        let report = Verifier::new().verify_block(&block);
+        assert!(report.is_valid(), "{}", report);
+    }
+
+    #[test]
+    fn report_every_problem() {
+        let context = create_context("secp256k1").unwrap();
+        let private_key = Secp256k1PrivateKey::from_hex(KEY1_PRIV_HEX).unwrap();
+        let signer = signing::Signer::new(context.as_ref(), &private_key);
+        let other_key = Secp256k1PrivateKey::from_hex(KEY2_PRIV_HEX).unwrap();
+        let other_signer = signing::Signer::new(context.as_ref(), &other_key);
+
+        // Batched by another signer, with a payload swapped after signing
+        let mut tampered = transaction_builder(b"payload")
+            .batcher_public_key(KEY2_PUB_HEX)
+            .build(&signer)
+            .unwrap();
+        tampered.set_payload(b"tampered".to_vec());
+        let tampered_id = String::from(tampered.get_header_signature());
+        let valid = transaction_builder(b"valid").build(&signer).unwrap();
+
+        // Signed by hand, as BatchBuilder refuses another signer's batcher key
+        let mut header = BatchHeader::new();
+        header.set_signer_public_key(String::from(KEY1_PUB_HEX));
+        header.set_transaction_ids(RepeatedField::from_vec(vec![tampered_id.clone()]));
+        let header_bytes = header.write_to_bytes().unwrap();
+        let mut batch = Batch::new();
+        batch.set_header_signature(signer.sign(&header_bytes).unwrap());
+        batch.set_header(header_bytes);
+        batch.set_transactions(RepeatedField::from_vec(vec![tampered, valid.clone()]));
+        let batch_id = String::from(batch.get_header_signature());
+
+        let report = Verifier::new().verify_batch(&batch);
+        let problems: Vec<(ItemType, &str)> = report.problems
+            .iter()
+            .map(|problem| (problem.item_type, problem.id.as_str()))
+            .collect();
+        assert_eq!(problems, vec![
+            (ItemType::Batch, batch_id.as_str()),
+            (ItemType::Transaction, tampered_id.as_str()),
+            (ItemType::Transaction, tampered_id.as_str()),
+        ]);
+        match report.problems[0].kind {
+            ProblemKind::IdsMismatch { ref listed, ref contained } => {
+                assert_eq!(listed, &vec![tampered_id.clone()]);
+                assert_eq!(contained.len(), 2);
+            }
+            ref kind => panic!("Expected IdsMismatch, got {:?}", kind)
+        }
+        match report.problems[1].kind {
+            ProblemKind::PayloadHashMismatch { .. } => (),
+            ref kind => panic!("Expected PayloadHashMismatch, got {:?}", kind)
+        }
+        assert_eq!(report.problems[2].kind, ProblemKind::BatcherKeyMismatch {
+            expected: String::from(KEY1_PUB_HEX),
+            actual: String::from(KEY2_PUB_HEX),
+        });
+
+        // A signature by another key, and one which is not hex at all
+        let mut forged = valid.clone();
+        forged.set_header_signature(other_signer.sign(valid.get_header()).unwrap());
+        assert_eq!(Verifier::new().verify_transaction(&forged).problems[0].kind,
+                   ProblemKind::InvalidSignature);
+        forged.set_header_signature(String::from("not a signature"));
+        assert_eq!(Verifier::new().verify_transaction(&forged).problems[0].kind,
+                   ProblemKind::InvalidSignature);
+
+        let block: Block = protobuf::parse_from_bytes(&[0x0a, 0x01, 0xff]).unwrap();
+        match Verifier::new().verify_block(&block).problems[0].kind {
+            ProblemKind::InvalidHeader(_) => (),
+            ref kind => panic!("Expected InvalidHeader, got {:?}", kind)
+        }
+    }
+}